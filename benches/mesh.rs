@@ -0,0 +1,122 @@
+use criterion::*;
+use lazy_static::lazy_static;
+use ray::bvh::BVHNode;
+use ray::geometry::mesh::{MeshData, Shading};
+use ray::geometry::triangle_mesh::TriangleMesh;
+use ray::geometry::Hitable;
+use ray::material::{diffuse::Diffuse, Material};
+use ray::matrix::Matrix4;
+use ray::ray::Ray;
+use ray::texture::solid::Solid;
+use ray::vec3::Vec3;
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+/// Keeps track of how much is allocated so the two can be compared by
+/// memory as well as speed
+struct Counting;
+
+static ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATED.fetch_add(layout.size(), Ordering::Relaxed);
+        System.alloc(layout)
+    }
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        ALLOCATED.fetch_sub(layout.size(), Ordering::Relaxed);
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: Counting = Counting;
+
+/// A bumpy 200 by 200 grid, 80,000 triangles
+fn grid() -> MeshData {
+    let n = 200;
+    let mut data = MeshData::default();
+    for y in 0..=n {
+        for x in 0..=n {
+            let height = (x as f32 * 0.3).sin() * (y as f32 * 0.2).cos();
+            data.positions.push(Vec3::new(x as f32, y as f32, height));
+        }
+    }
+    for y in 0..n {
+        for x in 0..n {
+            let i = y * (n + 1) + x;
+            data.indices.extend_from_slice(&[
+                i,
+                i + 1,
+                i + n + 2,
+                i,
+                i + n + 2,
+                i + n + 1,
+            ]);
+        }
+    }
+    data.generate_normals(Shading::Smooth);
+    data
+}
+
+lazy_static! {
+    static ref DATA: MeshData = grid();
+    static ref MATERIAL: Arc<dyn Material> = Arc::new(Diffuse::new(Solid::new(Vec3::from(0.5))));
+    /// Slanted rays spread over the whole grid, most of them hitting
+    static ref RAYS: Vec<Ray> = (0..1000)
+        .map(|i| {
+            let (x, y) = ((i % 40) as f32 * 5.1, (i / 40) as f32 * 8.1);
+            Ray::new(Vec3::new(x, y, 5.0), Vec3::new(0.3, 0.2, -1.0))
+        })
+        .collect();
+}
+
+fn boxed() -> BVHNode {
+    BVHNode::from(&mut DATA.triangles(Matrix4::identity(), MATERIAL.clone()))
+}
+
+fn compact() -> TriangleMesh {
+    TriangleMesh::new(&DATA, Matrix4::identity(), MATERIAL.clone())
+}
+
+/// How many bytes are still allocated once `build`'s result is done being
+/// made
+fn retained<T>(build: impl FnOnce() -> T) -> usize {
+    let before = ALLOCATED.load(Ordering::Relaxed);
+    let built = build();
+    let after = ALLOCATED.load(Ordering::Relaxed);
+    drop(built);
+    after - before
+}
+
+fn hits(mesh: &dyn Hitable) -> usize {
+    RAYS.iter()
+        .filter(|&&r| mesh.hit(r, 0.001, std::f32::MAX).is_some())
+        .count()
+}
+
+fn bench_mesh(c: &mut Criterion) {
+    lazy_static::initialize(&DATA);
+    println!(
+        "memory: boxed triangles {} bytes, TriangleMesh {} bytes",
+        retained(boxed),
+        retained(compact)
+    );
+    c.bench(
+        "mesh build",
+        Benchmark::new("boxed triangles", |b| b.iter(boxed))
+            .with_function("TriangleMesh", |b| b.iter(compact))
+            .sample_size(20),
+    );
+    let (old, new) = (boxed(), compact());
+    assert_eq!(hits(&old), hits(&new));
+    c.bench(
+        "mesh hit",
+        Benchmark::new("boxed triangles", move |b| b.iter(|| hits(&old)))
+            .with_function("TriangleMesh", move |b| b.iter(|| hits(&new))),
+    );
+}
+
+criterion_group!(mesh_bench, bench_mesh);
+criterion_main!(mesh_bench);
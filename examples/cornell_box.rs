@@ -88,6 +88,8 @@ pub fn main() {
         bounces: 50,
         background: Solid::new(Color::zero()),
         show_bg: false,
+        atmosphere: None,
+        lights: Vec::new(),
     }
     .render_to_file("cornell_box.png")
     .unwrap();
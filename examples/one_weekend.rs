@@ -78,6 +78,8 @@ pub fn main() {
             b: Color::new(0.5, 0.7, 1.0),
         },
         show_bg: true,
+        atmosphere: None,
+        lights: Vec::new(),
     }
     .render_to_file("one_weekend.png")
     .unwrap();
@@ -26,6 +26,8 @@ pub fn main() {
         bounces: 50,
         background: hdr_image("outside.hdr"),
         show_bg: true,
+        atmosphere: None,
+        lights: Vec::new(),
     }
     .render_to_file("sphere_row.png")
     .unwrap();
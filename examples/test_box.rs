@@ -30,6 +30,8 @@ pub fn main() {
         bounces: 50,
         background: solid!(0),
         show_bg: false,
+        atmosphere: None,
+        lights: Vec::new(),
     }
     .render_to_file("test_box.png")
     .unwrap();
@@ -105,6 +105,8 @@ pub fn main() {
         bounces: 100,
         background: Solid::new(Color::zero()),
         show_bg: true,
+        atmosphere: None,
+        lights: Vec::new(),
     }
     .render_to_file("the_next_week.png")
     .unwrap();
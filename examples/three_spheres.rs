@@ -45,6 +45,8 @@ pub fn main() {
             b: Color::new(0.5, 0.7, 1.0),
         },
         show_bg: true,
+        atmosphere: None,
+        lights: Vec::new(),
     }
     .render_to_file("three_spheres.png")
     .unwrap();
@@ -0,0 +1,62 @@
+extern crate ray;
+use ray::*;
+use_all!();
+
+pub fn main() {
+    let width = 300;
+    let height = 300;
+    let camera = CameraTrack::turntable(
+        Vec3::new(0, 3, -8),
+        Vec3::new(0, 1, 0),
+        35.0,
+        width as f32 / height as f32,
+        4.0,
+    );
+    let spin = Track::new(Quaternion::identity()).key(
+        4.0,
+        Quaternion::from_axis_angle(Vec3::new(1, 1, 0), 180.0),
+        Interpolation::ease_in_out(),
+    );
+    let hue = Track::new(Color::new(0.8, 0.2, 0.2)).key(
+        4.0,
+        Color::new(0.2, 0.2, 0.8),
+        Interpolation::Linear,
+    );
+    Animation {
+        frames: 0..48,
+        fps: 12.0,
+        gif: true,
+    }
+    .render_to_files("turntable.png", |t| {
+        let objects = BVHNode::from(&mut vec![
+            rect!(xz, (-10, -10), (10, 10), 0, diffuse!(solid!(0.5))) as Box<dyn Hitable>,
+            Box::new(Translate::new(
+                Orient::new(
+                    Prism::new(
+                        Vec3::from(-1),
+                        Vec3::from(1),
+                        diffuse!(Solid::new(hue.value_at(t))),
+                    ),
+                    spin.value_at(t),
+                ),
+                Vec3::new(0, 1.8, 0),
+            )),
+        ]);
+        Scene {
+            objects,
+            camera: camera.at(t),
+            width,
+            height,
+            samples: 50,
+            bounces: 10,
+            background: Gradient {
+                a: Color::from(1),
+                b: Color::new(0.5, 0.7, 1.0),
+            },
+            show_bg: true,
+            atmosphere: None,
+            lights: Vec::new(),
+        }
+    })
+    .unwrap();
+}
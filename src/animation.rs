@@ -0,0 +1,292 @@
+use crate::background::Background;
+use crate::camera::{Camera, Lens};
+use crate::quaternion::Quaternion;
+use crate::scene::Scene;
+use crate::vec3::Vec3;
+use image::{gif, ConvertBuffer, Frame, RgbaImage};
+use num_rational::Ratio;
+use std::fs::File;
+use std::io;
+use std::ops::Range;
+use std::path::PathBuf;
+
+/// Anything that can be blended between two keyframes
+pub trait Lerp: Copy {
+    fn lerp(&self, other: &Self, t: f32) -> Self;
+}
+
+impl Lerp for f32 {
+    fn lerp(&self, other: &Self, t: f32) -> Self {
+        self + (other - self) * t
+    }
+}
+
+impl Lerp for Vec3 {
+    fn lerp(&self, other: &Self, t: f32) -> Self {
+        *self + (*other - *self) * t
+    }
+}
+
+impl Lerp for Quaternion {
+    fn lerp(&self, other: &Self, t: f32) -> Self {
+        self.slerp(other, t)
+    }
+}
+
+/// How a keyframe blends into the one after it
+#[derive(Copy, Clone, Debug)]
+pub enum Interpolation {
+    /// Hold the value until the next keyframe
+    Step,
+    Linear,
+    /// Cubic bezier easing curve from (0, 0) to (1, 1) with control points
+    /// (x1, y1) and (x2, y2), the same as css timing functions
+    Bezier(f32, f32, f32, f32),
+}
+
+impl Interpolation {
+    pub fn ease_in_out() -> Self {
+        Interpolation::Bezier(0.42, 0.0, 0.58, 1.0)
+    }
+
+    /// Maps linear progress between two keyframes to eased progress
+    fn apply(self, t: f32) -> f32 {
+        match self {
+            Interpolation::Step => 0.0,
+            Interpolation::Linear => t,
+            Interpolation::Bezier(x1, y1, x2, y2) => {
+                let bezier = |s: f32, a: f32, b: f32| {
+                    3.0 * (1.0 - s).powi(2) * s * a
+                        + 3.0 * (1.0 - s) * s * s * b
+                        + s.powi(3)
+                };
+                // x is monotonic for control points in [0, 1] so bisect for
+                // the curve parameter that lands on t
+                let (mut lo, mut hi) = (0.0, 1.0);
+                for _ in 0..24 {
+                    let mid = (lo + hi) / 2.0;
+                    if bezier(mid, x1, x2) < t {
+                        lo = mid;
+                    } else {
+                        hi = mid;
+                    }
+                }
+                bezier((lo + hi) / 2.0, y1, y2)
+            }
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct Keyframe<T: Lerp> {
+    pub time: f32,
+    pub value: T,
+    pub interpolation: Interpolation,
+}
+
+/// A value that changes over time. Before the first keyframe and after the
+/// last one the value is held constant.
+#[derive(Clone, Debug)]
+pub struct Track<T: Lerp> {
+    keys: Vec<Keyframe<T>>,
+}
+
+impl<T: Lerp> Track<T> {
+    pub fn new(value: T) -> Self {
+        Track {
+            keys: vec![Keyframe {
+                time: 0.0,
+                value,
+                interpolation: Interpolation::Linear,
+            }],
+        }
+    }
+
+    /// Adds a keyframe, replacing any existing one at the same time
+    pub fn key(mut self, time: f32, value: T, interpolation: Interpolation) -> Self {
+        self.keys.retain(|k| k.time != time);
+        let idx = self
+            .keys
+            .iter()
+            .position(|k| k.time > time)
+            .unwrap_or(self.keys.len());
+        self.keys.insert(
+            idx,
+            Keyframe {
+                time,
+                value,
+                interpolation,
+            },
+        );
+        self
+    }
+
+    pub fn value_at(&self, time: f32) -> T {
+        let next = self.keys.iter().position(|k| k.time > time);
+        match next {
+            Some(0) => self.keys[0].value,
+            None => self.keys[self.keys.len() - 1].value,
+            Some(i) => {
+                let (a, b) = (&self.keys[i - 1], &self.keys[i]);
+                let t = (time - a.time) / (b.time - a.time);
+                a.value.lerp(&b.value, a.interpolation.apply(t))
+            }
+        }
+    }
+}
+
+impl<T: Lerp> From<T> for Track<T> {
+    fn from(value: T) -> Self {
+        Track::new(value)
+    }
+}
+
+/// Keyframed version of the arguments to `Camera::new`
+#[derive(Clone, Debug)]
+pub struct CameraTrack {
+    pub position: Track<Vec3>,
+    pub look_at: Track<Vec3>,
+    pub up_dir: Track<Vec3>,
+    pub fov: Track<f32>,
+    pub aperture: Track<f32>,
+    pub aspect: f32,
+}
+
+impl CameraTrack {
+    pub fn at(&self, time: f32) -> Camera {
+        Camera::new(
+            self.position.value_at(time),
+            self.look_at.value_at(time),
+            self.up_dir.value_at(time),
+            self.fov.value_at(time),
+            self.aspect,
+            self.aperture.value_at(time),
+        )
+    }
+
+    /// Circles the camera around `center` once over `duration` seconds,
+    /// starting from `position` and keeping the same height
+    pub fn turntable(
+        position: Vec3,
+        center: Vec3,
+        fov: f32,
+        aspect: f32,
+        duration: f32,
+    ) -> Self {
+        let offset = position - center;
+        let mut orbit = Track::new(position);
+        let steps = 64;
+        for i in 1..=steps {
+            let angle = 2.0 * std::f32::consts::PI * i as f32 / steps as f32;
+            let p = center + offset.rotate_y(angle.cos(), angle.sin());
+            orbit =
+                orbit.key(duration * i as f32 / steps as f32, p, Interpolation::Linear);
+        }
+        CameraTrack {
+            position: orbit,
+            look_at: center.into(),
+            up_dir: Vec3::new(0, 1, 0).into(),
+            fov: fov.into(),
+            aperture: 0.0.into(),
+            aspect,
+        }
+    }
+}
+
+/// Renders a range of frames to numbered images, `name.png` becomes
+/// `name_0000.png`, `name_0001.png`, etc. The scene is rebuilt for every
+/// frame by calling `build` with the frame's time in seconds, so any part of
+/// it can be driven by a `Track`.
+pub struct Animation {
+    pub frames: Range<usize>,
+    pub fps: f32,
+    /// Also collect the frames into an animated `name.gif`
+    pub gif: bool,
+}
+
+impl Animation {
+    pub fn frame_filename(filename: &str, frame: usize) -> String {
+        let path = PathBuf::from(filename);
+        let stem = path.file_stem().unwrap().to_string_lossy();
+        let ext = path
+            .extension()
+            .map_or("png".into(), |e| e.to_string_lossy());
+        path.with_file_name(format!("{}_{:04}.{}", stem, frame, ext))
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    pub fn render_to_files<T, C, F>(&self, filename: &str, mut build: F) -> io::Result<()>
+    where
+        T: Background,
+        C: Lens,
+        F: FnMut(f32) -> Scene<T, C>,
+    {
+        let mut encoder = if self.gif {
+            let file = File::create(PathBuf::from(filename).with_extension("gif"))?;
+            Some(gif::Encoder::new(file))
+        } else {
+            None
+        };
+        // gif delays are measured in hundredths of a second
+        let delay = Ratio::from_integer((100.0 / self.fps).round() as u16);
+        for frame in self.frames.clone() {
+            println!(
+                "frame {} of {}",
+                frame - self.frames.start + 1,
+                self.frames.len()
+            );
+            let scene = build(frame as f32 / self.fps);
+            let image =
+                scene.render_and_save(&Animation::frame_filename(filename, frame))?;
+            if let Some(encoder) = encoder.as_mut() {
+                let rgba: RgbaImage = image.convert();
+                encoder
+                    .encode_frames(vec![Frame::from_parts(rgba, 0, 0, delay)])
+                    .map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_linear() {
+        let track = Track::new(0.0).key(2.0, 10.0, Interpolation::Linear);
+        assert_eq!(track.value_at(-1.0), 0.0);
+        assert_eq!(track.value_at(1.0), 5.0);
+        assert_eq!(track.value_at(3.0), 10.0);
+    }
+
+    #[test]
+    fn test_step() {
+        let track = Track::new(Vec3::zero())
+            .key(0.0, Vec3::from(1), Interpolation::Step)
+            .key(1.0, Vec3::from(2), Interpolation::Linear);
+        assert_eq!(track.value_at(0.99), Vec3::from(1));
+        assert_eq!(track.value_at(1.0), Vec3::from(2));
+    }
+
+    #[test]
+    fn test_bezier() {
+        let ease = Interpolation::ease_in_out();
+        assert!(ease.apply(0.0).abs() < 1e-4);
+        assert!((ease.apply(0.5) - 0.5).abs() < 1e-4);
+        assert!((ease.apply(1.0) - 1.0).abs() < 1e-4);
+        assert!(ease.apply(0.1) < 0.1);
+        assert!(ease.apply(0.9) > 0.9);
+    }
+
+    #[test]
+    fn test_frame_filename() {
+        assert_eq!(Animation::frame_filename("spin.png", 7), "spin_0007.png");
+        assert_eq!(
+            Animation::frame_filename("out/spin", 12),
+            "out/spin_0012.png"
+        );
+    }
+}
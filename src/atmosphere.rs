@@ -0,0 +1,178 @@
+use crate::geometry::volume::Segment;
+use crate::material::Material;
+use crate::ray::Ray;
+use crate::scene::Color;
+use std::sync::Arc;
+
+/// Falloff stops past this many scale heights above the base, where the
+/// density is under a ten thousandth
+const TOP: f32 = 10.0;
+
+/// A medium filling the whole scene, like fog or haze, that every ray goes
+/// through on its way to whatever it hits or the background. Light shafts
+/// show up wherever objects shadow it, as long as the lights are in the
+/// scene's `lights` so the fog can sample them directly.
+///
+/// Fog that's the same everywhere hides the background completely, so
+/// outdoor scenes should have it fall off with height.
+#[derive(Debug)]
+pub struct Atmosphere {
+    /// How much of each channel gets absorbed and scattered per unit of
+    /// length at full density
+    absorption: Color,
+    scattering: Color,
+    /// The height it starts thinning out at, and how much higher it's
+    /// 1/e as thick
+    falloff: Option<(f32, f32)>,
+    phase_function: Arc<dyn Material>,
+}
+
+impl Atmosphere {
+    /// The same everywhere, only scattering
+    pub fn new(density: f32, phase_function: Arc<dyn Material>) -> Self {
+        Atmosphere {
+            absorption: Color::zero(),
+            scattering: Color::from(density),
+            falloff: None,
+            phase_function,
+        }
+    }
+
+    pub fn with_coefficients(mut self, absorption: Color, scattering: Color) -> Self {
+        self.absorption = absorption;
+        self.scattering = scattering;
+        self
+    }
+
+    /// Full density below `base`, thinning out exponentially above it.
+    /// `scale_height` has to be above 0.
+    pub fn with_falloff(mut self, base: f32, scale_height: f32) -> Self {
+        assert!(
+            scale_height > 0.0,
+            "scale height must be above 0, not {}",
+            scale_height
+        );
+        self.falloff = Some((base, scale_height));
+        self
+    }
+
+    fn density(&self, y: f32) -> f32 {
+        match self.falloff {
+            Some((base, scale_height)) => (-(y - base).max(0.0) / scale_height).exp(),
+            None => 1.0,
+        }
+    }
+
+    /// The part of the ray up to `t_max` that's thick enough to matter
+    fn span(&self, r: Ray, t_max: f32) -> Option<(f32, f32)> {
+        let (mut start, mut end) = (0.0f32, t_max);
+        if let Some((base, scale_height)) = self.falloff {
+            let top = base + scale_height * TOP;
+            let (o, d) = (r.origin.y, r.dir.y);
+            if d > 0.0 {
+                end = end.min((top - o) / d);
+            } else if d < 0.0 {
+                start = start.max((top - o) / d);
+            } else if o > top {
+                return None;
+            }
+        }
+        if start < end {
+            Some((start, end))
+        } else {
+            None
+        }
+    }
+
+    /// The part of a ray going as far as `t_max` that it can get absorbed
+    /// or scattered on, to be tracked along with any other media
+    pub(crate) fn segment(&self, r: Ray, t_max: f32) -> Option<Segment> {
+        let (start, end) = self.span(r, t_max)?;
+        // density only changes with height, so the densest point is at one
+        // of the ends
+        let densest = self
+            .density(r.point_at_param(start).y)
+            .max(self.density(r.point_at_param(end).y));
+        let extinction = self.absorption + self.scattering;
+        let len = r.dir.len();
+        let majorant = densest * extinction.x.max(extinction.y).max(extinction.z) * len;
+        Some(Segment::new(
+            (start, end),
+            majorant,
+            move |t| {
+                let density = self.density(r.point_at_param(t).y) * len;
+                (self.absorption * density, self.scattering * density)
+            },
+            self.phase_function.as_ref(),
+        ))
+    }
+
+    pub fn phase_function(&self) -> &dyn Material {
+        self.phase_function.as_ref()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometry::volume::{track, Event};
+    use crate::material::isotropic::Isotropic;
+    use crate::texture::solid::Solid;
+    use crate::vec3::Vec3;
+
+    fn phase() -> Arc<dyn Material> {
+        Arc::new(Isotropic::new(Solid::new(Color::from(1))))
+    }
+
+    /// How often a ray makes it to t_max
+    fn transmittance(atmosphere: &Atmosphere, r: Ray, t_max: f32) -> f32 {
+        let segment: Vec<Segment> = atmosphere.segment(r, t_max).into_iter().collect();
+        let samples = 20_000;
+        let through = (0..samples)
+            .filter(|_| match track(&segment) {
+                Event::Through(weight) => {
+                    assert!((weight - Color::from(1)).len() < 1e-4);
+                    true
+                }
+                Event::Scattered(t, ..) => {
+                    assert!(t > 0.0 && t < t_max);
+                    false
+                }
+                Event::Absorbed => panic!("nothing absorbs"),
+            })
+            .count();
+        through as f32 / samples as f32
+    }
+
+    #[test]
+    fn test_uniform() {
+        let fog = Atmosphere::new(0.5, phase());
+        let r = Ray::new(Vec3::new(1, 2, 3), Vec3::new(0.0, -0.6, 0.8));
+        assert!((transmittance(&fog, r, 2.0) - (-1.0f32).exp()).abs() < 0.02);
+        // with nothing to hit it never gets out
+        assert!(transmittance(&fog, r, std::f32::MAX) == 0.0);
+    }
+
+    #[test]
+    fn test_falloff() {
+        let haze = Atmosphere::new(1.0, phase()).with_falloff(0.0, 2.0);
+        // straight up from the base goes through 2 units worth in total
+        let r = Ray::new(Vec3::zero(), Vec3::new(0, 1, 0));
+        assert!((transmittance(&haze, r, std::f32::MAX) - (-2.0f32).exp()).abs() < 0.02);
+        // and the same coming back down, stopping at the base
+        let r = Ray::new(Vec3::new(0, 100, 0), Vec3::new(0, -1, 0));
+        assert!((transmittance(&haze, r, 100.0) - (-2.0f32).exp()).abs() < 0.02);
+        // level with the top, it's too thin to matter
+        let r = Ray::new(Vec3::new(0, 21, 0), Vec3::new(1, 0, 0));
+        assert_eq!(haze.span(r, std::f32::MAX), None);
+        // below the base it's as thick as it gets
+        let r = Ray::new(Vec3::new(0, -5, 0), Vec3::new(1, 0, 0));
+        assert!((transmittance(&haze, r, 1.0) - (-1.0f32).exp()).abs() < 0.02);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_flat_falloff() {
+        Atmosphere::new(1.0, phase()).with_falloff(0.0, 0.0);
+    }
+}
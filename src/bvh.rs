@@ -1,5 +1,6 @@
 use crate::geometry::{
     aabb::AABB,
+    volume::Segment,
     {HitRecord, Hitable},
 };
 use crate::ray::Ray;
@@ -9,6 +10,8 @@ pub struct BVHNode {
     pub bb: AABB,
     pub left: Option<Box<dyn Hitable>>,
     pub right: Option<Box<dyn Hitable>>,
+    /// Whether any media are under this node
+    media: bool,
 }
 
 impl BVHNode {
@@ -17,7 +20,13 @@ impl BVHNode {
         left: Option<Box<dyn Hitable>>,
         right: Option<Box<dyn Hitable>>,
     ) -> Self {
-        BVHNode { bb, left, right }
+        let media = left.iter().chain(right.iter()).any(|h| h.has_media());
+        BVHNode {
+            bb,
+            left,
+            right,
+            media,
+        }
     }
 }
 
@@ -44,6 +53,17 @@ impl Hitable for BVHNode {
     fn get_bb(&self) -> AABB {
         self.bb
     }
+    fn has_media(&self) -> bool {
+        self.media
+    }
+    fn media<'a>(&'a self, r: Ray, t_min: f32, t_max: f32, found: &mut Vec<Segment<'a>>) {
+        if !self.media || !self.bb.hit(r, t_min, t_max) {
+            return;
+        }
+        for child in self.left.iter().chain(self.right.iter()) {
+            child.media(r, t_min, t_max, found);
+        }
+    }
 }
 
 /// Recursively split the list of objects in two such that the sum of
@@ -36,6 +36,13 @@ pub fn almost_faster_rand() -> Vec3 {
     Vec3::new(r[0] as f32, r[1] as f32, 0) * random::<f32>().sqrt()
 }
 
+/// Anything that can turn a position on the image into a ray. Coordinates are
+/// in [0..1] with (0, 0) at the bottom left of the final image.
+pub trait Lens: Send + Sync {
+    fn get_ray(&self, h: f32, v: f32) -> Ray;
+}
+
+#[derive(Clone, Debug)]
 pub struct Camera {
     origin: Vec3,
     lower_left: Vec3,
@@ -55,6 +62,21 @@ impl Camera {
         fov: f32,
         aspect: f32,
         aperture: f32,
+    ) -> Self {
+        let focus_dist = (position - look_at).len();
+        Camera::with_focus(position, look_at, up_dir, fov, aspect, aperture, focus_dist)
+    }
+
+    /// Same as `new` but with the plane of focus at an arbitrary distance
+    /// instead of at `look_at`
+    pub fn with_focus(
+        position: Vec3,
+        look_at: Vec3,
+        up_dir: Vec3,
+        fov: f32,
+        aspect: f32,
+        aperture: f32,
+        focus_dist: f32,
     ) -> Self {
         let theta = fov * std::f32::consts::PI / 180.0;
         let half_height = (theta / 2.0).tan();
@@ -62,7 +84,6 @@ impl Camera {
         let w = (position - look_at).normalize();
         let u = up_dir.cross(&w).normalize();
         let v = w.cross(&u);
-        let focus_dist = (position - look_at).len();
         Camera {
             origin: position,
             lower_left: position - (u * half_width + v * half_height + w) * focus_dist,
@@ -74,6 +95,15 @@ impl Camera {
         }
     }
 
+    /// Moves the eye without moving the plane of focus, which skews the view
+    /// frustum. Objects on the focal plane stay put in the image.
+    pub fn shift(&self, offset: Vec3) -> Self {
+        Camera {
+            origin: self.origin + offset,
+            ..self.clone()
+        }
+    }
+
     pub fn get_ray(&self, h: f32, v: f32) -> Ray {
         let rand = rand_in_unit_disk() * self.lens_radius;
         let offset = self.u * rand.x + self.v * rand.y;
@@ -85,3 +115,204 @@ impl Camera {
         )
     }
 }
+
+impl Lens for Camera {
+    fn get_ray(&self, h: f32, v: f32) -> Ray {
+        Camera::get_ray(self, h, v)
+    }
+}
+
+/// How the two eyes of a stereo render are packed into one image
+#[derive(Copy, Clone, Debug)]
+pub enum StereoLayout {
+    SideBySide,
+    /// Left eye on top
+    OverUnder,
+}
+
+impl StereoLayout {
+    /// Splits a position on the full image into which eye it belongs to
+    /// (true for left) and the position within that eye's image
+    fn split(self, h: f32, v: f32) -> (bool, f32, f32) {
+        match self {
+            StereoLayout::SideBySide if h < 0.5 => (true, h * 2.0, v),
+            StereoLayout::SideBySide => (false, h * 2.0 - 1.0, v),
+            StereoLayout::OverUnder if v >= 0.5 => (true, h, v * 2.0 - 1.0),
+            StereoLayout::OverUnder => (false, h, v * 2.0),
+        }
+    }
+}
+
+/// How far apart the two eyes of a stereo camera are, and how far away
+/// their views meet
+#[derive(Copy, Clone, Debug)]
+pub struct Eyes {
+    pub interocular: f32,
+    pub convergence: f32,
+}
+
+/// A pair of off-axis cameras. Both eyes share an image plane at the
+/// convergence distance so anything at that depth has zero parallax, which
+/// avoids the keystoning you get from toeing the cameras in.
+#[derive(Clone, Debug)]
+pub struct StereoCamera {
+    left: Camera,
+    right: Camera,
+    layout: StereoLayout,
+}
+
+impl StereoCamera {
+    /// fov is specified in degrees and aspect is for a single eye, so a side
+    /// by side image should be twice as wide as a single view
+    pub fn new(
+        position: Vec3,
+        look_at: Vec3,
+        up_dir: Vec3,
+        fov: f32,
+        aspect: f32,
+        eyes: Eyes,
+        layout: StereoLayout,
+    ) -> Self {
+        let center = Camera::with_focus(
+            position,
+            look_at,
+            up_dir,
+            fov,
+            aspect,
+            0.0,
+            eyes.convergence,
+        );
+        let half_offset = center.u * (eyes.interocular / 2.0);
+        StereoCamera {
+            left: center.shift(-half_offset),
+            right: center.shift(half_offset),
+            layout,
+        }
+    }
+}
+
+impl Lens for StereoCamera {
+    fn get_ray(&self, h: f32, v: f32) -> Ray {
+        match self.layout.split(h, v) {
+            (true, h, v) => self.left.get_ray(h, v),
+            (false, h, v) => self.right.get_ray(h, v),
+        }
+    }
+}
+
+/// Omni-directional stereo: a full 360x180 equirectangular panorama per eye
+/// for viewing in a headset. Rather than two fixed eye positions, each column
+/// of the panorama gets eyes placed on a circle so that the offset between
+/// them is always perpendicular to the viewing direction.
+#[derive(Clone, Debug)]
+pub struct OdsCamera {
+    position: Vec3,
+    forward: Vec3,
+    up: Vec3,
+    right: Vec3,
+    interocular: f32,
+    convergence: f32,
+    layout: StereoLayout,
+}
+
+impl OdsCamera {
+    /// The center of the panorama faces `look_at`. Eye rays are angled in to
+    /// meet at the convergence distance; use `std::f32::INFINITY` to keep
+    /// them parallel.
+    pub fn new(
+        position: Vec3,
+        look_at: Vec3,
+        up_dir: Vec3,
+        eyes: Eyes,
+        layout: StereoLayout,
+    ) -> Self {
+        let forward = (look_at - position).normalize();
+        let right = forward.cross(&up_dir).normalize();
+        OdsCamera {
+            position,
+            forward,
+            up: right.cross(&forward),
+            right,
+            interocular: eyes.interocular,
+            convergence: eyes.convergence,
+            layout,
+        }
+    }
+}
+
+impl Lens for OdsCamera {
+    fn get_ray(&self, h: f32, v: f32) -> Ray {
+        use std::f32::consts::PI;
+        let (left, h, v) = self.layout.split(h, v);
+        let longitude = (h - 0.5) * 2.0 * PI;
+        let latitude = (v - 0.5) * PI;
+        let dir = self.right * (longitude.sin() * latitude.cos())
+            + self.up * latitude.sin()
+            + self.forward * (longitude.cos() * latitude.cos());
+        // the eye baseline rotates with longitude but ignores latitude
+        let tangent = self.right * longitude.cos() - self.forward * longitude.sin();
+        let offset = tangent * (self.interocular / 2.0) * if left { -1.0 } else { 1.0 };
+        if self.convergence.is_finite() {
+            Ray::new(self.position + offset, dir * self.convergence - offset)
+        } else {
+            Ray::new(self.position + offset, dir)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: Vec3, b: Vec3) -> bool {
+        (a - b).len() < 1e-4
+    }
+
+    #[test]
+    fn test_stereo_convergence() {
+        let rig = StereoCamera::new(
+            Vec3::zero(),
+            Vec3::new(0, 0, -1),
+            Vec3::new(0, 1, 0),
+            90.0,
+            1.0,
+            Eyes {
+                interocular: 0.5,
+                convergence: 10.0,
+            },
+            StereoLayout::SideBySide,
+        );
+        let left = rig.get_ray(0.25, 0.5);
+        let right = rig.get_ray(0.75, 0.5);
+        assert!(close(left.origin, Vec3::new(-0.25, 0, 0)));
+        assert!(close(right.origin, Vec3::new(0.25, 0, 0)));
+        // the center of both eyes meets on the convergence plane
+        assert!(close(left.point_at_param(1.0), right.point_at_param(1.0)));
+        assert!(close(left.point_at_param(1.0), Vec3::new(0, 0, -10)));
+    }
+
+    #[test]
+    fn test_ods_eye_offset() {
+        let ods = OdsCamera::new(
+            Vec3::zero(),
+            Vec3::new(0, 0, -1),
+            Vec3::new(0, 1, 0),
+            Eyes {
+                interocular: 0.5,
+                convergence: std::f32::INFINITY,
+            },
+            StereoLayout::OverUnder,
+        );
+        for &h in &[0.1, 0.3, 0.5, 0.8] {
+            let left = ods.get_ray(h, 0.75);
+            let right = ods.get_ray(h, 0.25);
+            let baseline = right.origin - left.origin;
+            assert!((baseline.len() - 0.5).abs() < 1e-4);
+            assert!(baseline.dot(&left.dir).abs() < 1e-4);
+            assert!(close(left.dir, right.dir));
+        }
+        let forward = ods.get_ray(0.5, 0.75);
+        assert!(close(forward.dir, Vec3::new(0, 0, -1)));
+        assert!(close(forward.origin, Vec3::new(-0.25, 0, 0)));
+    }
+}
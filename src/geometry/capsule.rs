@@ -0,0 +1,154 @@
+use super::aabb::AABB;
+use super::{solve_quadratic, tangents, HitRecord, Hitable};
+use crate::material::Material;
+use crate::ray::Ray;
+use crate::scene::Color;
+use crate::vec3::Vec3;
+use std::f32::consts::PI;
+use std::sync::Arc;
+
+#[macro_export]
+macro_rules! capsule {
+    ($p0:expr, $p1:expr, $radius:expr, $mat:expr) => {
+        Box::new(Capsule::new($p0.into(), $p1.into(), $radius as f32, $mat))
+    };
+}
+
+/// Every point within `radius` of the segment from `p0` to `p1`, so a
+/// cylinder with a half sphere on each end
+#[derive(Debug, Clone)]
+pub struct Capsule {
+    p0: Vec3,
+    p1: Vec3,
+    radius: f32,
+    material: Arc<dyn Material>,
+}
+
+impl Capsule {
+    pub fn new(p0: Vec3, p1: Vec3, radius: f32, material: Arc<dyn Material>) -> Self {
+        Capsule {
+            p0,
+            p1,
+            radius,
+            material,
+        }
+    }
+
+    /// Where along the segment each crossing of the surface is, either the
+    /// side or one of the end caps
+    fn crossings(&self, r: Ray) -> Vec<f32> {
+        let axis = self.p1 - self.p0;
+        let length = axis.len();
+        let w = axis / length;
+        let oa = r.origin - self.p0;
+        let mut ts = Vec::with_capacity(6);
+        // the side is a cylinder around the axis, which only counts between
+        // the ends
+        let d_perp = r.dir - w * r.dir.dot(&w);
+        let o_perp = oa - w * oa.dot(&w);
+        if let Some((t0, t1)) = solve_quadratic(
+            d_perp.dot(&d_perp),
+            2.0 * o_perp.dot(&d_perp),
+            o_perp.dot(&o_perp) - self.radius * self.radius,
+        ) {
+            for &t in &[t0, t1] {
+                let y = (oa + r.dir * t).dot(&w);
+                if y >= 0.0 && y <= length {
+                    ts.push(t);
+                }
+            }
+        }
+        // and the spheres only count past them
+        for &(center, side) in &[(self.p0, -1.0), (self.p1, 1.0)] {
+            let oc = r.origin - center;
+            if let Some((t0, t1)) = solve_quadratic(
+                r.dir.dot(&r.dir),
+                2.0 * oc.dot(&r.dir),
+                oc.dot(&oc) - self.radius * self.radius,
+            ) {
+                for &t in &[t0, t1] {
+                    if (oc + r.dir * t).dot(&w) * side >= 0.0 {
+                        ts.push(t);
+                    }
+                }
+            }
+        }
+        ts
+    }
+}
+
+impl Hitable for Capsule {
+    fn hit(&self, r: Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+        let t = self
+            .crossings(r)
+            .into_iter()
+            .filter(|&t| t > t_min && t < t_max)
+            .fold(None, |closest: Option<f32>, t| {
+                Some(closest.map_or(t, |c| c.min(t)))
+            })?;
+        let point = r.point_at_param(t);
+        let axis = self.p1 - self.p0;
+        let length = axis.len();
+        let w = axis / length;
+        let y = (point - self.p0).dot(&w).max(0.0).min(length);
+        let normal = (point - (self.p0 + w * y)) / self.radius;
+        let (a, b) = tangents(w);
+        // v runs along the whole outline, from the tip of one cap to the other
+        let along = (point - self.p0).dot(&w);
+        let v = (along + self.radius) / (length + 2.0 * self.radius);
+        Some(HitRecord {
+            t,
+            u: (normal.dot(&b).atan2(normal.dot(&a)) + PI) / (2.0 * PI),
+            v: v.max(0.0).min(1.0),
+            point,
+            normal,
+            geometric_normal: normal,
+            front_face: r.dir.dot(&normal) < 0.0,
+            tangent: Vec3::zero(),
+            color: Color::from(1),
+            material: self.material.as_ref(),
+        })
+    }
+
+    fn get_bb(&self) -> AABB {
+        let extent = Vec3::from(self.radius);
+        AABB::new(
+            self.p0.piecewise_min(&self.p1) - extent,
+            self.p0.piecewise_max(&self.p1) + extent,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::specular::Specular;
+
+    #[test]
+    fn test_hit() {
+        let material = Arc::new(Specular::new(Color::from(1), 0.0));
+        let capsule = Capsule::new(Vec3::new(0, 0, 0), Vec3::new(0, 0, 4), 1.0, material);
+        // the side
+        let r = Ray::new(Vec3::new(-3, 0, 2), Vec3::new(1, 0, 0));
+        let hit = capsule.hit(r, 0.0, std::f32::MAX).unwrap();
+        assert!((hit.t - 2.0).abs() < 1e-5);
+        assert!((hit.normal - Vec3::new(-1, 0, 0)).len() < 1e-5);
+        assert!((hit.v - 0.5).abs() < 1e-5);
+        // end on, the tip of the cap
+        let r = Ray::new(Vec3::new(0, 0, 10), Vec3::new(0, 0, -2));
+        let hit = capsule.hit(r, 0.0, std::f32::MAX).unwrap();
+        assert!((hit.t - 2.5).abs() < 1e-5);
+        assert!((hit.normal - Vec3::new(0, 0, 1)).len() < 1e-5);
+        // and out the other end from inside
+        let hit = capsule.hit(r, 3.0, std::f32::MAX).unwrap();
+        assert!((hit.t - 5.5).abs() < 1e-5);
+        assert!(!hit.front_face);
+        // past the end of the side but outside the cap
+        let r = Ray::new(Vec3::new(-3.0, 0.9, 4.9), Vec3::new(1, 0, 0));
+        assert!(capsule.hit(r, 0.0, std::f32::MAX).is_none());
+        assert_eq!(
+            capsule.get_bb(),
+            AABB::new(Vec3::new(-1, -1, -1), Vec3::new(1, 1, 5))
+        );
+    }
+}
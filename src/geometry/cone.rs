@@ -0,0 +1,165 @@
+use super::aabb::AABB;
+use super::disk::Disk;
+use super::{azimuth, solve_quadratic, HitRecord, Hitable};
+use crate::material::Material;
+use crate::ray::Ray;
+use crate::scene::Color;
+use crate::vec3::Vec3;
+use std::sync::Arc;
+
+#[macro_export]
+macro_rules! cone {
+    ($base:expr, $radius:expr, $height:expr, $mat:expr) => {
+        Box::new(Cone::new(
+            $base.into(),
+            $radius as f32,
+            $height as f32,
+            $mat,
+        ))
+    };
+}
+
+/// A cone around the y axis with a circle of `radius` at `base` narrowing to
+/// a point `height` above it. The base is open unless it's given a cap.
+#[derive(Debug, Clone)]
+pub struct Cone {
+    base: Vec3,
+    radius: f32,
+    height: f32,
+    cap: Option<Disk>,
+    material: Arc<dyn Material>,
+}
+
+impl Cone {
+    pub fn new(
+        base: Vec3,
+        radius: f32,
+        height: f32,
+        material: Arc<dyn Material>,
+    ) -> Self {
+        Cone {
+            base,
+            radius,
+            height,
+            cap: None,
+            material,
+        }
+    }
+
+    /// Closes the base with a disk of the same material
+    pub fn with_cap(self, cap: bool) -> Self {
+        let cap = if cap {
+            Some(Disk::new(self.base, self.radius, self.material.clone()).facing_down())
+        } else {
+            None
+        };
+        Cone { cap, ..self }
+    }
+
+    /// The side satisfies x^2 + z^2 = (k (h - y))^2 where k is the slope
+    fn hit_side(&self, r: Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+        let o = r.origin - self.base;
+        let d = r.dir;
+        let k2 = (self.radius / self.height).powi(2);
+        let below_apex = self.height - o.y;
+        let a = d.x * d.x + d.z * d.z - k2 * d.y * d.y;
+        let b = 2.0 * (o.x * d.x + o.z * d.z + k2 * below_apex * d.y);
+        let c = o.x * o.x + o.z * o.z - k2 * below_apex * below_apex;
+        // rays parallel to the slope only cross the side once
+        let (t0, t1) = if a == 0.0 {
+            if b == 0.0 {
+                return None;
+            }
+            (-c / b, -c / b)
+        } else {
+            solve_quadratic(a, b, c)?
+        };
+        for &t in &[t0, t1] {
+            let p = o + d * t;
+            // the equation also describes a second cone mirrored above the apex
+            if t > t_min && t < t_max && p.y >= 0.0 && p.y <= self.height {
+                let gradient = Vec3::new(p.x, k2 * (self.height - p.y), p.z);
+                let normal = if gradient.len() > 0.0 {
+                    gradient.normalize()
+                } else {
+                    Vec3::new(0, 1, 0)
+                };
+                return Some(HitRecord {
+                    t,
+                    u: azimuth(p),
+                    v: p.y / self.height,
+                    point: r.point_at_param(t),
+                    normal,
+                    geometric_normal: normal,
+                    front_face: r.dir.dot(&normal) < 0.0,
+                    tangent: Vec3::zero(),
+                    color: Color::from(1),
+                    material: self.material.as_ref(),
+                });
+            }
+        }
+        None
+    }
+}
+
+impl Hitable for Cone {
+    fn hit(&self, r: Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+        let side = self.hit_side(r, t_min, t_max);
+        match &self.cap {
+            Some(cap) => {
+                let t_max = side.as_ref().map_or(t_max, |h| h.t);
+                cap.hit(r, t_min, t_max).or(side)
+            }
+            None => side,
+        }
+    }
+
+    fn get_bb(&self) -> AABB {
+        AABB::new(
+            self.base - Vec3::new(self.radius, 0.0, self.radius),
+            self.base + Vec3::new(self.radius, self.height, self.radius),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::specular::Specular;
+
+    #[test]
+    fn test_hit() {
+        let material = Arc::new(Specular::new(Color::from(1), 0.0));
+        let cone = Cone::new(Vec3::new(0, 0, 0), 1.0, 2.0, material).with_cap(true);
+        // halfway up the radius is halved
+        let r = Ray::new(Vec3::new(-5, 1, 0), Vec3::new(1, 0, 0));
+        let hit = cone.hit(r, 0.0, std::f32::MAX).unwrap();
+        assert!((hit.t - 4.5).abs() < 1e-5);
+        assert!((hit.normal - Vec3::new(-2, 1, 0).normalize()).len() < 1e-5);
+        assert!((hit.v - 0.5).abs() < 1e-5);
+        // the mirrored cone above the apex isn't there
+        let r = Ray::new(Vec3::new(-5, 3, 0), Vec3::new(1, 0, 0));
+        assert!(cone.hit(r, 0.0, std::f32::MAX).is_none());
+        let up = Ray::new(Vec3::new(0.2, -1.0, 0.0), Vec3::new(0, 1, 0));
+        let hit = cone.hit(up, 0.0, std::f32::MAX).unwrap();
+        assert_eq!(hit.t, 1.0);
+        assert_eq!(hit.normal, Vec3::new(0, -1, 0));
+    }
+
+    #[test]
+    fn test_parallel_to_slope() {
+        let material = Arc::new(Specular::new(Color::from(1), 0.0));
+        let cone = Cone::new(Vec3::new(0, 0, 0), 1.0, 2.0, material);
+        // up through the open base and out the far side
+        let r = Ray::new(Vec3::new(0, -1, 0), Vec3::new(0.5, 1.0, 0.0));
+        let hit = cone.hit(r, 0.0, std::f32::MAX).unwrap();
+        assert!((hit.t - 1.5).abs() < 1e-5);
+        assert!((hit.point - Vec3::new(0.75, 0.5, 0.0)).len() < 1e-5);
+        assert!(!hit.front_face);
+        // and back in from outside
+        let r = Ray::new(Vec3::new(1.75, 2.5, 0.0), Vec3::new(-0.5, -1.0, 0.0));
+        let hit = cone.hit(r, 0.0, std::f32::MAX).unwrap();
+        assert!((hit.t - 2.0).abs() < 1e-5);
+        assert!(hit.front_face);
+    }
+}
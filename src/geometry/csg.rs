@@ -0,0 +1,324 @@
+use super::aabb::AABB;
+use super::{HitRecord, Hitable};
+use crate::ray::Ray;
+
+#[macro_export]
+macro_rules! union {
+    ($a:expr, $b:expr) => {
+        Box::new(Union::new(*$a, *$b))
+    };
+}
+
+#[macro_export]
+macro_rules! intersection {
+    ($a:expr, $b:expr) => {
+        Box::new(Intersection::new(*$a, *$b))
+    };
+}
+
+#[macro_export]
+macro_rules! difference {
+    ($a:expr, $b:expr) => {
+        Box::new(Difference::new(*$a, *$b))
+    };
+}
+
+/// How far past a crossing to look for the next one
+const EPSILON: f32 = 0.0001;
+
+/// Every crossing of an object's surface along a ray in order, by asking for
+/// the next hit just past the previous one. Whether each one is entering or
+/// leaving the object comes from `front_face`, so the object has to be closed.
+struct Crossings<'a, T: Hitable + ?Sized> {
+    obj: &'a T,
+    r: Ray,
+    next: Option<HitRecord<'a>>,
+}
+
+impl<'a, T: Hitable + ?Sized> Crossings<'a, T> {
+    fn new(obj: &'a T, r: Ray, t_min: f32) -> Self {
+        Crossings {
+            obj,
+            r,
+            next: obj.hit(r, t_min, std::f32::MAX),
+        }
+    }
+
+    /// Whether the ray starts inside, which is when the first crossing leaves
+    fn starts_inside(&self) -> bool {
+        self.next.as_ref().map_or(false, |h| !h.front_face)
+    }
+
+    fn peek(&self) -> Option<f32> {
+        self.next.as_ref().map(|h| h.t)
+    }
+}
+
+impl<'a, T: Hitable + ?Sized> Iterator for Crossings<'a, T> {
+    type Item = HitRecord<'a>;
+
+    fn next(&mut self) -> Option<HitRecord<'a>> {
+        let hit = self.next.take()?;
+        self.next = self.obj.hit(self.r, hit.t + EPSILON, std::f32::MAX);
+        Some(hit)
+    }
+}
+
+/// The stretches of the ray between `t_min` and `t_max` that are inside a
+/// closed object, as pairs of entering and leaving distances
+pub fn intervals<T: Hitable + ?Sized>(
+    obj: &T,
+    r: Ray,
+    t_min: f32,
+    t_max: f32,
+) -> Vec<(f32, f32)> {
+    let mut crossings = Crossings::new(obj, r, t_min);
+    let mut start = if crossings.starts_inside() {
+        Some(t_min)
+    } else {
+        None
+    };
+    let mut result = Vec::new();
+    for hit in &mut crossings {
+        if hit.t > t_max {
+            break;
+        }
+        match (hit.front_face, start) {
+            (true, None) => start = Some(hit.t),
+            (false, Some(s)) => {
+                result.push((s, hit.t));
+                start = None;
+            }
+            // a glancing hit missed the other half of the pair
+            _ => {}
+        }
+    }
+    if let Some(s) = start {
+        result.push((s, t_max));
+    }
+    result
+}
+
+/// Walks the crossings of both objects together, and returns the first one
+/// where being inside the combination changes
+fn combine<'a, A: Hitable, B: Hitable>(
+    a: &'a A,
+    b: &'a B,
+    r: Ray,
+    t_min: f32,
+    t_max: f32,
+    inside: impl Fn(bool, bool) -> bool,
+) -> Option<HitRecord<'a>> {
+    let mut a_hits = Crossings::new(a, r, t_min);
+    let mut b_hits = Crossings::new(b, r, t_min);
+    let mut in_a = a_hits.starts_inside();
+    let mut in_b = b_hits.starts_inside();
+    let mut was_inside = inside(in_a, in_b);
+    loop {
+        let from_a = match (a_hits.peek(), b_hits.peek()) {
+            (Some(ta), Some(tb)) => ta <= tb,
+            (Some(_), None) => true,
+            (None, Some(_)) => false,
+            (None, None) => return None,
+        };
+        let mut hit = if from_a {
+            let hit = a_hits.next()?;
+            in_a = hit.front_face;
+            hit
+        } else {
+            let hit = b_hits.next()?;
+            in_b = hit.front_face;
+            hit
+        };
+        if hit.t > t_max {
+            return None;
+        }
+        let now_inside = inside(in_a, in_b);
+        if now_inside != was_inside {
+            // surfaces cut away by a difference face the other way
+            if hit.front_face != now_inside {
+                hit.normal = -hit.normal;
+                hit.geometric_normal = -hit.geometric_normal;
+                hit.front_face = now_inside;
+            }
+            return Some(hit);
+        }
+        was_inside = now_inside;
+    }
+}
+
+/// Everything inside either object
+#[derive(Debug)]
+pub struct Union<A: Hitable, B: Hitable> {
+    a: A,
+    b: B,
+}
+
+impl<A: Hitable, B: Hitable> Union<A, B> {
+    pub fn new(a: A, b: B) -> Self {
+        Union { a, b }
+    }
+}
+
+impl<A: Hitable, B: Hitable> Hitable for Union<A, B> {
+    fn hit(&self, r: Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+        combine(&self.a, &self.b, r, t_min, t_max, |a, b| a || b)
+    }
+    fn get_bb(&self) -> AABB {
+        self.a.get_bb().combine(&self.b.get_bb())
+    }
+}
+
+/// Only what's inside both objects
+#[derive(Debug)]
+pub struct Intersection<A: Hitable, B: Hitable> {
+    a: A,
+    b: B,
+}
+
+impl<A: Hitable, B: Hitable> Intersection<A, B> {
+    pub fn new(a: A, b: B) -> Self {
+        Intersection { a, b }
+    }
+}
+
+impl<A: Hitable, B: Hitable> Hitable for Intersection<A, B> {
+    fn hit(&self, r: Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+        combine(&self.a, &self.b, r, t_min, t_max, |a, b| a && b)
+    }
+    fn get_bb(&self) -> AABB {
+        let (a, b) = (self.a.get_bb(), self.b.get_bb());
+        let min = a.min.piecewise_max(&b.min);
+        // boxes that don't overlap leave an empty box at the corner
+        AABB::new(min, a.max.piecewise_min(&b.max).piecewise_max(&min))
+    }
+}
+
+/// `a` with everything inside `b` cut out of it
+#[derive(Debug)]
+pub struct Difference<A: Hitable, B: Hitable> {
+    a: A,
+    b: B,
+}
+
+impl<A: Hitable, B: Hitable> Difference<A, B> {
+    pub fn new(a: A, b: B) -> Self {
+        Difference { a, b }
+    }
+}
+
+impl<A: Hitable, B: Hitable> Hitable for Difference<A, B> {
+    fn hit(&self, r: Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+        combine(&self.a, &self.b, r, t_min, t_max, |a, b| a && !b)
+    }
+    fn get_bb(&self) -> AABB {
+        self.a.get_bb()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometry::cylinder::Cylinder;
+    use crate::geometry::mesh::MeshData;
+    use crate::geometry::prism::Prism;
+    use crate::geometry::sphere::Sphere;
+    use crate::geometry::triangle_mesh::TriangleMesh;
+    use crate::material::specular::Specular;
+    use crate::material::Material;
+    use crate::matrix::Matrix4;
+    use crate::scene::Color;
+    use crate::vec3::Vec3;
+    use std::sync::Arc;
+
+    fn material() -> Arc<dyn Material> {
+        Arc::new(Specular::new(Color::from(1), 0.0))
+    }
+
+    fn sphere(x: f32) -> Sphere {
+        Sphere::new(Vec3::new(x, 0.0, 0.0), 1.0, material())
+    }
+
+    fn along_x(x: f32) -> Ray {
+        Ray::new(Vec3::new(x, 0.0, 0.0), Vec3::new(1, 0, 0))
+    }
+
+    #[test]
+    fn test_intervals() {
+        let pair = Union::new(sphere(0.0), sphere(5.0));
+        let found = intervals(&pair, along_x(-10.0), 0.0, std::f32::MAX);
+        assert_eq!(found, vec![(9.0, 11.0), (14.0, 16.0)]);
+        // starting inside and ending early clip the intervals
+        let found = intervals(&pair, along_x(0.0), 0.0, 5.0);
+        assert_eq!(found, vec![(0.0, 1.0), (4.0, 5.0)]);
+    }
+
+    #[test]
+    fn test_union() {
+        let blob = Union::new(sphere(0.0), sphere(1.5));
+        let found = intervals(&blob, along_x(-10.0), 0.0, std::f32::MAX);
+        assert_eq!(found, vec![(9.0, 12.5)]);
+        // from inside the overlap it only stops at the far side
+        let hit = blob.hit(along_x(0.5), 0.0, std::f32::MAX).unwrap();
+        assert_eq!(hit.t, 2.0);
+        assert!(!hit.front_face);
+    }
+
+    #[test]
+    fn test_intersection() {
+        let lens = Intersection::new(sphere(0.0), sphere(1.5));
+        let hit = lens.hit(along_x(-10.0), 0.0, std::f32::MAX).unwrap();
+        assert_eq!(hit.t, 10.5);
+        assert_eq!(hit.normal, Vec3::new(-1, 0, 0));
+        let found = intervals(&lens, along_x(-10.0), 0.0, std::f32::MAX);
+        assert_eq!(found, vec![(10.5, 11.0)]);
+        let bb = lens.get_bb();
+        assert_eq!(bb.min, Vec3::new(0.5, -1.0, -1.0));
+        assert_eq!(bb.max, Vec3::new(1.0, 1.0, 1.0));
+    }
+
+    #[test]
+    fn test_drilled_plate() {
+        let plate = Prism::new(Vec3::new(-2, 0, -2), Vec3::new(2, 1, 2), material());
+        let hole =
+            Cylinder::new(Vec3::new(0, -1, 0), 0.5, 3.0, material()).with_caps(true);
+        let drilled = Difference::new(plate, hole);
+        // straight down the hole
+        let down = Ray::new(Vec3::new(0.2, 5.0, 0.0), Vec3::new(0, -1, 0));
+        assert!(drilled.hit(down, 0.0, std::f32::MAX).is_none());
+        // through the wall of the hole, which faces into it
+        let across = Ray::new(Vec3::new(-5.0, 0.5, 0.0), Vec3::new(1, 0, 0));
+        let found = intervals(&drilled, across, 0.0, std::f32::MAX);
+        assert_eq!(found, vec![(3.0, 4.5), (5.5, 7.0)]);
+        let hit = drilled.hit(across, 3.5, std::f32::MAX).unwrap();
+        assert_eq!(hit.t, 4.5);
+        assert_eq!(hit.normal, Vec3::new(1, 0, 0));
+        assert!(!hit.front_face);
+        let hit = drilled.hit(across, 4.6, std::f32::MAX).unwrap();
+        assert_eq!(hit.normal, Vec3::new(-1, 0, 0));
+        assert!(hit.front_face);
+    }
+
+    #[test]
+    fn test_closed_mesh() {
+        // a tetrahedron with a sphere bitten out of one corner
+        let data = MeshData {
+            positions: vec![
+                Vec3::new(0, 0, 0),
+                Vec3::new(4, 0, 0),
+                Vec3::new(0, 4, 0),
+                Vec3::new(0, 0, 4),
+            ],
+            indices: vec![0, 2, 1, 0, 1, 3, 0, 3, 2, 1, 2, 3],
+            ..MeshData::default()
+        };
+        let mesh = TriangleMesh::new(&data, Matrix4::identity(), material());
+        let bitten = Difference::new(mesh, sphere(0.0));
+        let r = Ray::new(Vec3::new(-1.0, 0.5, 0.5), Vec3::new(1, 0, 0));
+        let found = intervals(&bitten, r, 0.0, std::f32::MAX);
+        assert_eq!(found.len(), 1);
+        let (enter, exit) = found[0];
+        assert!((enter - (1.0 + 0.5f32.sqrt())).abs() < 1e-5);
+        assert!((exit - 4.0).abs() < 1e-5);
+    }
+}
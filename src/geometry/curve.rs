@@ -0,0 +1,387 @@
+use super::aabb::AABB;
+use super::{tangents, HitRecord, Hitable};
+use crate::material::Material;
+use crate::ray::Ray;
+use crate::scene::Color;
+use crate::vec3::Vec3;
+use std::sync::Arc;
+
+/// How a curve's width is turned into a surface
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum CurveShape {
+    /// A strip that always turns to face the ray
+    Flat,
+    /// A strip that always faces the ray but is shaded like a round tube, for
+    /// hair and fur
+    Cylinder,
+    /// A strip facing along normals that blend from the first to the second
+    /// over the curve, for blades of grass
+    Ribbon(Vec3, Vec3),
+}
+
+/// A cubic Bezier segment with a width that changes linearly along it. Rays
+/// are tested against it by splitting it in half until the halves are
+/// nearly straight, skipping halves that the ray passes by.
+#[derive(Debug, Clone)]
+pub struct Curve {
+    points: [Vec3; 4],
+    widths: (f32, f32),
+    shape: CurveShape,
+    material: Arc<dyn Material>,
+}
+
+impl Curve {
+    pub fn new(
+        points: [Vec3; 4],
+        widths: (f32, f32),
+        material: Arc<dyn Material>,
+    ) -> Self {
+        Curve {
+            points,
+            widths,
+            shape: CurveShape::Cylinder,
+            material,
+        }
+    }
+
+    /// The middle segment of a uniform B-spline, which passes near the two
+    /// middle points rather than through the end ones. Consecutive segments
+    /// of a strand share 3 points and join smoothly.
+    pub fn from_bspline(
+        points: [Vec3; 4],
+        widths: (f32, f32),
+        material: Arc<dyn Material>,
+    ) -> Self {
+        let [p0, p1, p2, p3] = points;
+        let bezier = [
+            (p0 + p1 * 4.0 + p2) / 6.0,
+            (p1 * 2.0 + p2) / 3.0,
+            (p1 + p2 * 2.0) / 3.0,
+            (p1 + p2 * 4.0 + p3) / 6.0,
+        ];
+        Curve::new(bezier, widths, material)
+    }
+
+    pub fn with_shape(self, shape: CurveShape) -> Self {
+        Curve { shape, ..self }
+    }
+
+    fn width(&self, u: f32) -> f32 {
+        self.widths.0 + (self.widths.1 - self.widths.0) * u
+    }
+
+    /// The ribbon's normal, or the direction facing the ray for the others
+    fn facing(&self, u: f32, tangent: Vec3, dir: Vec3) -> Vec3 {
+        let n = match self.shape {
+            CurveShape::Ribbon(n0, n1) => n0 * (1.0 - u) + n1 * u,
+            _ => -dir,
+        };
+        let n = n - tangent * n.dot(&tangent);
+        if n.len() > 0.0 {
+            n.normalize()
+        } else {
+            tangents(tangent).0
+        }
+    }
+
+    /// Closest crossing in `cp`, which has the ray starting at the origin and
+    /// heading down z. Hits are (z, u), narrowing `z_max` as they're found.
+    fn recurse(
+        &self,
+        cp: &[Vec3; 4],
+        (u0, u1): (f32, f32),
+        depth: u32,
+        dir: Vec3,
+        z_min: f32,
+        z_max: &mut f32,
+    ) -> Option<(f32, f32)> {
+        if depth > 0 {
+            let mid = (u0 + u1) / 2.0;
+            let (first, second) = split(cp);
+            let mut closest = None;
+            for &(half, range) in &[(first, (u0, mid)), (second, (mid, u1))] {
+                let width = self.width(range.0).max(self.width(range.1));
+                if overlaps(&half, width, z_min, *z_max) {
+                    if let Some(hit) =
+                        self.recurse(&half, range, depth - 1, dir, z_min, z_max)
+                    {
+                        closest = Some(hit);
+                    }
+                }
+            }
+            return closest;
+        }
+        // the ray has to pass between the planes at both ends of the segment
+        let edge = (cp[1].y - cp[0].y) * -cp[0].y + cp[0].x * (cp[0].x - cp[1].x);
+        if edge < 0.0 {
+            return None;
+        }
+        let edge = (cp[2].y - cp[3].y) * -cp[3].y + cp[3].x * (cp[3].x - cp[2].x);
+        if edge < 0.0 {
+            return None;
+        }
+        // then close enough to the line between them
+        let segment = Vec3::new(cp[3].x - cp[0].x, cp[3].y - cp[0].y, 0.0);
+        let denom = segment.dot(&segment);
+        if denom == 0.0 {
+            return None;
+        }
+        let w = -Vec3::new(cp[0].x, cp[0].y, 0.0).dot(&segment) / denom;
+        let u = (u0 + (u1 - u0) * w).max(u0).min(u1);
+        let mut width = self.width(u);
+        if let CurveShape::Ribbon(..) = self.shape {
+            // ribbons seen edge on get thinner
+            let (_, tangent) = eval(&self.points, u);
+            width *= self.facing(u, tangent.normalize(), dir).dot(&dir).abs();
+        }
+        let (pc, _) = eval(cp, w.max(0.0).min(1.0));
+        if pc.x * pc.x + pc.y * pc.y > width * width * 0.25 {
+            return None;
+        }
+        if pc.z <= z_min || pc.z >= *z_max {
+            return None;
+        }
+        *z_max = pc.z;
+        Some((pc.z, u))
+    }
+}
+
+/// The point and derivative at u, by de Casteljau's algorithm
+fn eval(cp: &[Vec3; 4], u: f32) -> (Vec3, Vec3) {
+    let lerp = |a: Vec3, b: Vec3| a * (1.0 - u) + b * u;
+    let (a, b, c) = (lerp(cp[0], cp[1]), lerp(cp[1], cp[2]), lerp(cp[2], cp[3]));
+    let (d, e) = (lerp(a, b), lerp(b, c));
+    let derivative = if (e - d).len() > 0.0 {
+        (e - d) * 3.0
+    } else {
+        cp[3] - cp[0]
+    };
+    (lerp(d, e), derivative)
+}
+
+/// The two halves of a segment
+fn split(cp: &[Vec3; 4]) -> ([Vec3; 4], [Vec3; 4]) {
+    let middle = (cp[0] + cp[1] * 3.0 + cp[2] * 3.0 + cp[3]) / 8.0;
+    (
+        [
+            cp[0],
+            (cp[0] + cp[1]) / 2.0,
+            (cp[0] + cp[1] * 2.0 + cp[2]) / 4.0,
+            middle,
+        ],
+        [
+            middle,
+            (cp[1] + cp[2] * 2.0 + cp[3]) / 4.0,
+            (cp[2] + cp[3]) / 2.0,
+            cp[3],
+        ],
+    )
+}
+
+/// Whether the box around the control points, padded by half the width,
+/// could be hit by a ray starting at the origin heading down z
+fn overlaps(cp: &[Vec3; 4], width: f32, z_min: f32, z_max: f32) -> bool {
+    let min =
+        cp[1..].iter().fold(cp[0], |m, p| m.piecewise_min(p)) - Vec3::from(width / 2.0);
+    let max =
+        cp[1..].iter().fold(cp[0], |m, p| m.piecewise_max(p)) + Vec3::from(width / 2.0);
+    min.x <= 0.0
+        && max.x >= 0.0
+        && min.y <= 0.0
+        && max.y >= 0.0
+        && max.z >= z_min
+        && min.z <= z_max
+}
+
+impl Hitable for Curve {
+    fn hit(&self, r: Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+        let len = r.dir.len();
+        let dir = r.dir / len;
+        let (dx, dy) = tangents(dir);
+        let to_ray = |p: Vec3| {
+            let d = p - r.origin;
+            Vec3::new(d.dot(&dx), d.dot(&dy), d.dot(&dir))
+        };
+        let p = &self.points;
+        let cp = [to_ray(p[0]), to_ray(p[1]), to_ray(p[2]), to_ray(p[3])];
+        let (z_min, mut z_max) = (t_min * len, t_max.min(std::f32::MAX / len) * len);
+        let max_width = self.widths.0.max(self.widths.1);
+        if !overlaps(&cp, max_width, z_min, z_max) {
+            return None;
+        }
+        // split until the halves are within a twentieth of the width of
+        // being straight
+        let bend = (0..2)
+            .map(|i| (cp[i] - cp[i + 1] * 2.0 + cp[i + 2]).len())
+            .fold(0.0, f32::max);
+        let depth = if bend > 0.0 && max_width > 0.0 {
+            let levels =
+                (2f32.sqrt() * 6.0 * bend / (8.0 * max_width * 0.05)).log2() / 2.0;
+            levels.max(0.0).min(10.0) as u32
+        } else {
+            0
+        };
+        let (z, u) = self.recurse(&cp, (0.0, 1.0), depth, dir, z_min, &mut z_max)?;
+
+        let (center, derivative) = eval(&self.points, u);
+        let tangent = derivative.normalize();
+        let facing = self.facing(u, tangent, dir);
+        let t = match self.shape {
+            // where the ray crosses the tilted ribbon rather than the middle
+            CurveShape::Ribbon(..) => {
+                (center - r.origin).dot(&facing) / r.dir.dot(&facing)
+            }
+            _ => z / len,
+        };
+        let point = r.point_at_param(t);
+        let side = tangent.cross(&facing);
+        let half = self.width(u) / 2.0;
+        let offset = if half > 0.0 {
+            ((point - center).dot(&side) / half).max(-1.0).min(1.0)
+        } else {
+            0.0
+        };
+        let normal = match self.shape {
+            CurveShape::Cylinder => {
+                facing * (1.0 - offset * offset).sqrt() + side * offset
+            }
+            _ => facing,
+        };
+        Some(HitRecord {
+            t,
+            u,
+            v: 0.5 + offset / 2.0,
+            point,
+            normal,
+            geometric_normal: facing,
+            front_face: r.dir.dot(&facing) < 0.0,
+            tangent,
+            color: Color::from(1),
+            material: self.material.as_ref(),
+        })
+    }
+
+    fn get_bb(&self) -> AABB {
+        let pad = Vec3::from(self.widths.0.max(self.widths.1) / 2.0);
+        let bb = self.points[1..]
+            .iter()
+            .fold(AABB::new(self.points[0], self.points[0]), |bb, &p| {
+                bb.combine(&AABB::new(p, p))
+            });
+        AABB::new(bb.min - pad, bb.max + pad)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::specular::Specular;
+
+    fn material() -> Arc<dyn Material> {
+        Arc::new(Specular::new(Color::from(1), 0.0))
+    }
+
+    /// Straight along x from -1 to 1
+    fn straight(widths: (f32, f32)) -> Curve {
+        let p = |x: f32| Vec3::new(x, 0.0, 0.0);
+        Curve::new([p(-1.0), p(-0.2), p(0.4), p(1.0)], widths, material())
+    }
+
+    fn down_at(x: f32, y: f32) -> Ray {
+        Ray::new(Vec3::new(x, y, 5.0), Vec3::new(0.0, 0.0, -2.0))
+    }
+
+    #[test]
+    fn test_cylinder() {
+        let curve = straight((0.2, 0.2));
+        let hit = curve.hit(down_at(0.0, 0.0), 0.0, std::f32::MAX).unwrap();
+        assert!((hit.t - 2.5).abs() < 1e-5);
+        assert!((hit.normal - Vec3::new(0, 0, 1)).len() < 1e-3);
+        assert!((hit.tangent - Vec3::new(1, 0, 0)).len() < 1e-5);
+        // halfway to the edge the normal leans 30 degrees out
+        let hit = curve.hit(down_at(0.3, 0.05), 0.0, std::f32::MAX).unwrap();
+        assert!((hit.normal.y.abs() - 0.5).abs() < 1e-3);
+        assert!(hit.v < 0.3 || hit.v > 0.7);
+        assert!(curve.hit(down_at(0.3, 0.11), 0.0, std::f32::MAX).is_none());
+        assert!(curve.hit(down_at(1.1, 0.0), 0.0, std::f32::MAX).is_none());
+        // and from any other direction it's the same width
+        let side = Ray::new(Vec3::new(0.5, -3.0, 0.09), Vec3::new(0, 1, 0));
+        let hit = curve.hit(side, 0.0, std::f32::MAX).unwrap();
+        assert!((hit.t - 3.0).abs() < 1e-5);
+        assert!(hit.front_face);
+    }
+
+    #[test]
+    fn test_width() {
+        let curve = straight((0.0, 0.4));
+        let u = |x: f32| {
+            curve
+                .hit(down_at(x, 0.0), 0.0, std::f32::MAX)
+                .map(|h| h.u)
+                .unwrap()
+        };
+        assert!(u(0.5) > u(-0.5));
+        let width = curve.width(u(0.5));
+        assert!(curve
+            .hit(down_at(0.5, width / 2.0 - 0.01), 0.0, std::f32::MAX)
+            .is_some());
+        assert!(curve
+            .hit(down_at(0.5, width / 2.0 + 0.01), 0.0, std::f32::MAX)
+            .is_none());
+    }
+
+    #[test]
+    fn test_bent() {
+        // an arch over the origin
+        let curve = Curve::new(
+            [
+                Vec3::new(-1, 0, 0),
+                Vec3::new(-1, 2, 0),
+                Vec3::new(1, 2, 0),
+                Vec3::new(1, 0, 0),
+            ],
+            (0.1, 0.1),
+            material(),
+        );
+        for &x in &[-0.9, -0.5, 0.0, 0.3, 0.8] {
+            let (top, _) = eval(&curve.points, 0.5 + x / 2.0);
+            let r = Ray::new(Vec3::new(top.x, top.y, 5.0), Vec3::new(0, 0, -1));
+            let hit = curve.hit(r, 0.0, std::f32::MAX).unwrap();
+            let (center, _) = eval(&curve.points, hit.u);
+            assert!((hit.point - center).len() <= 0.05 + 1e-4);
+        }
+        assert!(curve.hit(down_at(0.0, 0.5), 0.0, std::f32::MAX).is_none());
+        assert!(curve.get_bb().max.y >= 1.5);
+    }
+
+    #[test]
+    fn test_ribbon() {
+        let up = Vec3::new(0, 1, 0);
+        let ribbon = straight((0.2, 0.2)).with_shape(CurveShape::Ribbon(up, up));
+        let r = Ray::new(Vec3::new(0.2, 4.0, 0.05), Vec3::new(0, -1, 0));
+        let hit = ribbon.hit(r, 0.0, std::f32::MAX).unwrap();
+        assert!((hit.t - 4.0).abs() < 1e-5);
+        assert_eq!(hit.normal, up);
+        assert!(hit.front_face);
+        // edge on it's too thin to hit
+        let r = Ray::new(Vec3::new(0.2, 0.0, 5.0), Vec3::new(0, 0, -1));
+        assert!(ribbon.hit(r, 0.0, std::f32::MAX).is_none());
+    }
+
+    #[test]
+    fn test_bspline() {
+        // evenly spaced points in a line make a straight segment between the
+        // middle two
+        let p = |x: i32| Vec3::new(x, 0, 0);
+        let curve = Curve::from_bspline([p(0), p(1), p(2), p(3)], (0.1, 0.1), material());
+        assert_eq!(
+            curve.points,
+            [
+                p(1),
+                Vec3::new(4.0 / 3.0, 0.0, 0.0),
+                Vec3::new(5.0 / 3.0, 0.0, 0.0),
+                p(2)
+            ]
+        );
+    }
+}
@@ -0,0 +1,157 @@
+use super::aabb::AABB;
+use super::disk::Disk;
+use super::{azimuth, solve_quadratic, HitRecord, Hitable};
+use crate::material::Material;
+use crate::ray::Ray;
+use crate::scene::Color;
+use crate::vec3::Vec3;
+use std::sync::Arc;
+
+#[macro_export]
+macro_rules! cylinder {
+    ($base:expr, $radius:expr, $height:expr, $mat:expr) => {
+        Box::new(Cylinder::new(
+            $base.into(),
+            $radius as f32,
+            $height as f32,
+            $mat,
+        ))
+    };
+}
+
+/// A tube around the y axis from `base` up to `base + height`. Open at both
+/// ends unless it's given caps.
+#[derive(Debug, Clone)]
+pub struct Cylinder {
+    base: Vec3,
+    radius: f32,
+    height: f32,
+    caps: Option<(Disk, Disk)>,
+    material: Arc<dyn Material>,
+}
+
+impl Cylinder {
+    pub fn new(
+        base: Vec3,
+        radius: f32,
+        height: f32,
+        material: Arc<dyn Material>,
+    ) -> Self {
+        Cylinder {
+            base,
+            radius,
+            height,
+            caps: None,
+            material,
+        }
+    }
+
+    /// Closes both ends with disks of the same material
+    pub fn with_caps(self, caps: bool) -> Self {
+        let caps = if caps {
+            let top = self.base + Vec3::new(0.0, self.height, 0.0);
+            Some((
+                Disk::new(self.base, self.radius, self.material.clone()).facing_down(),
+                Disk::new(top, self.radius, self.material.clone()),
+            ))
+        } else {
+            None
+        };
+        Cylinder { caps, ..self }
+    }
+
+    fn hit_side(&self, r: Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+        let o = r.origin - self.base;
+        let d = r.dir;
+        let (t0, t1) = solve_quadratic(
+            d.x * d.x + d.z * d.z,
+            2.0 * (o.x * d.x + o.z * d.z),
+            o.x * o.x + o.z * o.z - self.radius * self.radius,
+        )?;
+        for &t in &[t0, t1] {
+            let y = o.y + t * d.y;
+            if t > t_min && t < t_max && y >= 0.0 && y <= self.height {
+                let normal = Vec3::new(o.x + t * d.x, 0.0, o.z + t * d.z) / self.radius;
+                return Some(HitRecord {
+                    t,
+                    u: azimuth(normal),
+                    v: y / self.height,
+                    point: r.point_at_param(t),
+                    normal,
+                    geometric_normal: normal,
+                    front_face: r.dir.dot(&normal) < 0.0,
+                    tangent: Vec3::zero(),
+                    color: Color::from(1),
+                    material: self.material.as_ref(),
+                });
+            }
+        }
+        None
+    }
+}
+
+impl Hitable for Cylinder {
+    fn hit(&self, r: Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+        let side = self.hit_side(r, t_min, t_max);
+        match &self.caps {
+            Some((bottom, top)) => {
+                let t_max = side.as_ref().map_or(t_max, |h| h.t);
+                let top_hit = top.hit(r, t_min, t_max);
+                let t_max = top_hit.as_ref().map_or(t_max, |h| h.t);
+                bottom.hit(r, t_min, t_max).or(top_hit).or(side)
+            }
+            None => side,
+        }
+    }
+
+    fn get_bb(&self) -> AABB {
+        AABB::new(
+            self.base - Vec3::new(self.radius, 0.0, self.radius),
+            self.base + Vec3::new(self.radius, self.height, self.radius),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::specular::Specular;
+
+    fn cylinder() -> Cylinder {
+        let material = Arc::new(Specular::new(Color::from(1), 0.0));
+        Cylinder::new(Vec3::new(0, 1, 0), 2.0, 3.0, material)
+    }
+
+    #[test]
+    fn test_side() {
+        let cylinder = cylinder();
+        let r = Ray::new(Vec3::new(-5, 2, 0), Vec3::new(1, 0, 0));
+        let hit = cylinder.hit(r, 0.0, std::f32::MAX).unwrap();
+        assert_eq!(hit.t, 3.0);
+        assert_eq!(hit.normal, Vec3::new(-1, 0, 0));
+        assert!((hit.v - 1.0 / 3.0).abs() < 1e-6);
+        // from inside it hits the far wall
+        let r = Ray::new(Vec3::new(0, 2, 0), Vec3::new(1, 0, 0));
+        let hit = cylinder.hit(r, 0.0, std::f32::MAX).unwrap();
+        assert_eq!(hit.t, 2.0);
+        assert!(!hit.front_face);
+    }
+
+    #[test]
+    fn test_caps() {
+        let down = Ray::new(Vec3::new(0.5, 10.0, 0.5), Vec3::new(0, -1, 0));
+        assert!(cylinder().hit(down, 0.0, std::f32::MAX).is_none());
+        let capped = cylinder().with_caps(true);
+        let hit = capped.hit(down, 0.0, std::f32::MAX).unwrap();
+        assert_eq!(hit.t, 6.0);
+        assert_eq!(hit.normal, Vec3::new(0, 1, 0));
+        let up = Ray::new(Vec3::new(0.5, -10.0, 0.5), Vec3::new(0, 1, 0));
+        let hit = capped.hit(up, 0.0, std::f32::MAX).unwrap();
+        assert_eq!(hit.t, 11.0);
+        assert_eq!(hit.normal, Vec3::new(0, -1, 0));
+        assert_eq!(
+            capped.get_bb(),
+            AABB::new(Vec3::new(-2, 1, -2), Vec3::new(2, 4, 2))
+        );
+    }
+}
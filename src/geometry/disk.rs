@@ -0,0 +1,73 @@
+use super::aabb::AABB;
+use super::{azimuth, HitRecord, Hitable};
+use crate::material::Material;
+use crate::ray::Ray;
+use crate::scene::Color;
+use crate::vec3::Vec3;
+use std::sync::Arc;
+
+#[macro_export]
+macro_rules! disk {
+    ($center:expr, $radius:expr, $mat:expr) => {
+        Box::new(Disk::new($center.into(), $radius as f32, $mat))
+    };
+}
+
+/// A flat circle facing up the y axis, rotate it to face anywhere else
+#[derive(Debug, Clone)]
+pub struct Disk {
+    center: Vec3,
+    radius: f32,
+    /// Faces down the y axis instead, for the bottom caps of other shapes
+    down: bool,
+    material: Arc<dyn Material>,
+}
+
+impl Disk {
+    pub fn new(center: Vec3, radius: f32, material: Arc<dyn Material>) -> Self {
+        Disk {
+            center,
+            radius,
+            down: false,
+            material,
+        }
+    }
+
+    pub(crate) fn facing_down(self) -> Self {
+        Disk { down: true, ..self }
+    }
+}
+
+impl Hitable for Disk {
+    fn hit(&self, r: Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+        let t = (self.center.y - r.origin.y) / r.dir.y;
+        // also rejects the NaN from rays parallel to the disk
+        if !(t > t_min && t < t_max) {
+            return None;
+        }
+        let point = r.point_at_param(t);
+        let offset = point - self.center;
+        let dist = (offset.x * offset.x + offset.z * offset.z).sqrt();
+        if dist > self.radius {
+            return None;
+        }
+        let normal = Vec3::new(0, if self.down { -1 } else { 1 }, 0);
+        Some(HitRecord {
+            t,
+            u: azimuth(offset),
+            v: dist / self.radius,
+            point,
+            normal,
+            geometric_normal: normal,
+            front_face: r.dir.dot(&normal) < 0.0,
+            tangent: Vec3::zero(),
+            color: Color::from(1),
+            material: self.material.as_ref(),
+        })
+    }
+
+    fn get_bb(&self) -> AABB {
+        let extent = Vec3::new(self.radius, 0.0001, self.radius);
+        AABB::new(self.center - extent, self.center + extent)
+    }
+}
@@ -0,0 +1,306 @@
+use super::mesh::{MeshData, Shading};
+use crate::texture::Texture;
+use crate::vec3::Vec3;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// Pushes the surface of a mesh in or out along its normals by a texture,
+/// after splitting the triangles finely enough for the detail to show up.
+/// The height is the average of the texture's channels so grayscale height
+/// maps work as is.
+#[derive(Debug, Clone)]
+pub struct Displacement {
+    pub texture: Arc<dyn Texture>,
+    /// How far a texture value of 1 moves the surface from the midpoint
+    pub scale: f32,
+    /// The texture value that leaves the surface where it is, lower values
+    /// push it in
+    pub midpoint: f32,
+    /// How many times every triangle is split into 4 first
+    pub levels: u32,
+    pub shading: Shading,
+}
+
+impl Displacement {
+    pub fn new(texture: Arc<dyn Texture>, scale: f32) -> Self {
+        Displacement {
+            texture,
+            scale,
+            midpoint: 0.0,
+            levels: 4,
+            shading: Shading::Smooth,
+        }
+    }
+
+    pub fn with_midpoint(self, midpoint: f32) -> Self {
+        Displacement { midpoint, ..self }
+    }
+
+    pub fn with_levels(self, levels: u32) -> Self {
+        Displacement { levels, ..self }
+    }
+
+    /// How the normals of the displaced surface get generated
+    pub fn with_shading(self, shading: Shading) -> Self {
+        Displacement { shading, ..self }
+    }
+
+    /// Tessellates and displaces the mesh, then generates new normals.
+    /// Vertices at the same position move together, by their average height
+    /// along their average normal, so hard edges and texture seams don't
+    /// crack open.
+    pub fn apply(&self, data: &MeshData) -> MeshData {
+        let mut data = data.clone();
+        if data.normals.len() != data.positions.len() {
+            data.generate_normals(Shading::Smooth);
+        }
+        for _ in 0..self.levels {
+            data = tessellate(&data);
+        }
+        let (welded, count) = weld(&data.positions);
+        let mut normals = vec![Vec3::zero(); count];
+        let mut heights = vec![(0.0, 0); count];
+        for (i, &p) in data.positions.iter().enumerate() {
+            let uv = data.texcoords.get(i).cloned().unwrap_or_else(Vec3::zero);
+            let value = self.texture.value(uv.x, uv.y, p);
+            let height =
+                ((value.x + value.y + value.z) / 3.0 - self.midpoint) * self.scale;
+            normals[welded[i]] += data.normals[i];
+            let (sum, n) = heights[welded[i]];
+            heights[welded[i]] = (sum + height, n + 1);
+        }
+        for (i, p) in data.positions.iter_mut().enumerate() {
+            let (sum, n) = heights[welded[i]];
+            let normal = normals[welded[i]];
+            if normal.len() > 0.0 {
+                *p += normal.normalize() * (sum / n as f32);
+            }
+        }
+        smooth_normals(&data, &welded, count, self.shading)
+    }
+}
+
+/// Splits every triangle into 4 at the middle of its edges
+fn tessellate(data: &MeshData) -> MeshData {
+    let count = data.positions.len();
+    let matching = |v: &Vec<Vec3>| {
+        if v.len() == count {
+            v.clone()
+        } else {
+            Vec::new()
+        }
+    };
+    let mut out = MeshData {
+        positions: data.positions.clone(),
+        normals: matching(&data.normals),
+        texcoords: matching(&data.texcoords),
+        colors: matching(&data.colors),
+        ..MeshData::default()
+    };
+    let mut midpoints = HashMap::new();
+    let triangles = data.indices.len() / 3;
+    for (t, i) in data.indices.chunks(3).enumerate() {
+        let mut middle = |a: u32, b: u32| {
+            *midpoints.entry((a.min(b), a.max(b))).or_insert_with(|| {
+                let mid = |v: &mut Vec<Vec3>| {
+                    if !v.is_empty() {
+                        v.push((v[a as usize] + v[b as usize]) / 2.0);
+                    }
+                };
+                mid(&mut out.positions);
+                mid(&mut out.normals);
+                mid(&mut out.texcoords);
+                mid(&mut out.colors);
+                out.positions.len() as u32 - 1
+            })
+        };
+        let (ab, bc, ca) = (middle(i[0], i[1]), middle(i[1], i[2]), middle(i[2], i[0]));
+        out.indices
+            .extend_from_slice(&[i[0], ab, ca, i[1], bc, ab, i[2], ca, bc, ab, bc, ca]);
+        if data.smoothing_groups.len() == triangles {
+            out.smoothing_groups.extend(&[data.smoothing_groups[t]; 4]);
+        }
+    }
+    for n in &mut out.normals {
+        if n.len() > 0.0 {
+            *n = n.normalize();
+        }
+    }
+    out
+}
+
+/// Maps every vertex to the first one at the same position
+fn weld(positions: &[Vec3]) -> (Vec<usize>, usize) {
+    let mut seen = HashMap::new();
+    let welded = positions
+        .iter()
+        .map(|p| {
+            let count = seen.len();
+            *seen
+                .entry([p.x.to_bits(), p.y.to_bits(), p.z.to_bits()])
+                .or_insert(count)
+        })
+        .collect();
+    (welded, seen.len())
+}
+
+/// Generates normals as if the vertices at the same position were one, but
+/// keeps them apart wherever their texture coordinates or colors differ
+fn smooth_normals(
+    data: &MeshData,
+    welded: &[usize],
+    count: usize,
+    shading: Shading,
+) -> MeshData {
+    let mut positions = vec![Vec3::zero(); count];
+    for (i, &w) in welded.iter().enumerate() {
+        positions[w] = data.positions[i];
+    }
+    let mut shape = MeshData {
+        positions,
+        indices: data
+            .indices
+            .iter()
+            .map(|&i| welded[i as usize] as u32)
+            .collect(),
+        smoothing_groups: data.smoothing_groups.clone(),
+        ..MeshData::default()
+    };
+    shape.generate_normals(shading);
+
+    let mut out = MeshData {
+        smoothing_groups: data.smoothing_groups.clone(),
+        ..MeshData::default()
+    };
+    let mut vertices = HashMap::new();
+    for (c, &i) in data.indices.iter().enumerate() {
+        let normal = shape.normals[shape.indices[c] as usize];
+        let key = (
+            i,
+            [normal.x.to_bits(), normal.y.to_bits(), normal.z.to_bits()],
+        );
+        let index = *vertices.entry(key).or_insert_with(|| {
+            let i = i as usize;
+            out.positions.push(data.positions[i]);
+            out.normals.push(normal);
+            out.texcoords.extend(data.texcoords.get(i));
+            out.colors.extend(data.colors.get(i));
+            out.positions.len() as u32 - 1
+        });
+        out.indices.push(index);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scene::Color;
+    use crate::texture::solid::Solid;
+
+    /// A square from 0 to 1 facing up, split along a texture seam
+    fn square() -> MeshData {
+        MeshData {
+            positions: vec![
+                Vec3::new(0, 0, 0),
+                Vec3::new(1, 0, 0),
+                Vec3::new(1, 0, 1),
+                Vec3::new(0, 0, 1),
+                Vec3::new(1, 0, 1),
+                Vec3::new(0, 0, 0),
+            ],
+            texcoords: vec![
+                Vec3::new(0, 0, 0),
+                Vec3::new(1, 0, 0),
+                Vec3::new(1, 1, 0),
+                Vec3::new(0, 1, 0),
+                Vec3::new(0.5, 0.5, 0.0),
+                Vec3::new(0.5, 0.5, 0.0),
+            ],
+            indices: vec![0, 2, 1, 5, 3, 4],
+            ..MeshData::default()
+        }
+    }
+
+    #[derive(Debug)]
+    struct Ramp;
+
+    impl Texture for Ramp {
+        fn value(&self, _u: f32, _v: f32, p: Vec3) -> Color {
+            Color::from(p.x)
+        }
+    }
+
+    #[test]
+    fn test_tessellate() {
+        let data = tessellate(&square());
+        assert_eq!(data.indices.len(), 8 * 3);
+        // the midpoints of the shared edge get made twice since the seam
+        // splits it, but end up in the same place
+        assert_eq!(data.positions.len(), 6 + 6);
+        assert_eq!(data.texcoords.len(), data.positions.len());
+        let (_, count) = weld(&data.positions);
+        assert_eq!(count, 9);
+    }
+
+    #[test]
+    fn test_flat() {
+        let lift = Displacement::new(Arc::new(Solid::new(Color::from(1))), 0.5)
+            .with_midpoint(0.5)
+            .with_levels(2);
+        let data = lift.apply(&square());
+        assert_eq!(data.indices.len(), 2 * 16 * 3);
+        assert!(data.positions.iter().all(|p| (p.y - 0.25).abs() < 1e-6));
+        assert!(data.normals.iter().all(|&n| n == Vec3::new(0, 1, 0)));
+        // one vertex per position except down the seam
+        assert_eq!(data.positions.len(), 25 + 5);
+    }
+
+    #[test]
+    fn test_slope() {
+        // rising with x turns the square into a 45 degree ramp, with the
+        // normals across the seam matching
+        let ramp = Displacement::new(Arc::new(Ramp), 1.0).with_levels(3);
+        let data = ramp.apply(&square());
+        for (p, n) in data.positions.iter().zip(&data.normals) {
+            assert!((p.y - p.x).abs() < 1e-6);
+            assert!((*n - Vec3::new(-1, 1, 0).normalize()).len() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn test_hard_edges() {
+        // two faces at a right angle stay joined when pushed out
+        let corner = MeshData {
+            positions: vec![
+                Vec3::new(0, 0, 0),
+                Vec3::new(0, 0, 1),
+                Vec3::new(1, 0, 0),
+                Vec3::new(0, 0, 0),
+                Vec3::new(0, 0, 1),
+                Vec3::new(0, -1, 0),
+            ],
+            normals: vec![
+                Vec3::new(0, 1, 0),
+                Vec3::new(0, 1, 0),
+                Vec3::new(0, 1, 0),
+                Vec3::new(-1, 0, 0),
+                Vec3::new(-1, 0, 0),
+                Vec3::new(-1, 0, 0),
+            ],
+            indices: vec![0, 1, 2, 3, 5, 4],
+            ..MeshData::default()
+        };
+        let out = Displacement::new(Arc::new(Solid::new(Color::from(1))), 1.0)
+            .with_levels(1)
+            .with_shading(Shading::Crease(30.0))
+            .apply(&corner);
+        let (_, count) = weld(&out.positions);
+        assert_eq!(count, 6 + 6 - 3);
+        let edge = Vec3::new(-1, 1, 0).normalize();
+        assert!(out
+            .positions
+            .iter()
+            .any(|&p| (p - (Vec3::new(0.0, 0.0, 0.5) + edge)).len() < 1e-6));
+    }
+}
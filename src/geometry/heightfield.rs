@@ -0,0 +1,401 @@
+use super::aabb::AABB;
+use super::mesh::intersect;
+use super::{HitRecord, Hitable};
+use crate::axis::Axis;
+use crate::material::Material;
+use crate::ray::Ray;
+use crate::scene::Color;
+use crate::vec3::{ToF32, Vec3};
+use image::{ImageBuffer, Pixel, Primitive, Rgb};
+use std::sync::Arc;
+
+/// Terrain from a grid of heights, split into two triangles per cell. Rays
+/// walk the grid cell by cell instead of going through a BVH, so it's cheap
+/// even with millions of samples.
+pub struct Heightfield {
+    /// Rows of samples along x, from 0 to 1
+    heights: Vec<f32>,
+    /// One per sample, from the slope to the neighboring samples
+    normals: Vec<Vec3>,
+    /// Lowest and highest point in each cell, to skip cells the ray passes
+    /// over or under
+    bounds: Vec<(f32, f32)>,
+    columns: usize,
+    rows: usize,
+    corner: Vec3,
+    /// Heights of 1 reach up to corner.y + size.y
+    size: Vec3,
+    material: Arc<dyn Material>,
+}
+
+impl std::fmt::Debug for Heightfield {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "heightfield of {}x{} from {:?} to {:?}",
+            self.columns,
+            self.rows,
+            self.corner,
+            self.corner + self.size
+        )
+    }
+}
+
+impl Heightfield {
+    /// `heights` holds `rows` rows of `columns` samples, spread evenly from
+    /// `corner` to `corner + size` with rows going along z
+    pub fn new(
+        heights: Vec<f32>,
+        columns: usize,
+        rows: usize,
+        corner: Vec3,
+        size: Vec3,
+        material: Arc<dyn Material>,
+    ) -> Self {
+        assert!(columns >= 2 && rows >= 2, "a heightfield needs 2x2 samples");
+        assert_eq!(heights.len(), columns * rows);
+        let mut field = Heightfield {
+            heights,
+            normals: Vec::new(),
+            bounds: Vec::new(),
+            columns,
+            rows,
+            corner,
+            size,
+            material,
+        };
+        field.normals = grid(rows, columns)
+            .map(|(j, i)| {
+                let (left, right) = (i.saturating_sub(1), (i + 1).min(columns - 1));
+                let (back, front) = (j.saturating_sub(1), (j + 1).min(rows - 1));
+                let dx = field.vertex(right, j) - field.vertex(left, j);
+                let dz = field.vertex(i, front) - field.vertex(i, back);
+                dz.cross(&dx).normalize()
+            })
+            .collect();
+        field.bounds = grid(rows - 1, columns - 1)
+            .map(|(j, i)| {
+                let h = [
+                    field.height(i, j),
+                    field.height(i + 1, j),
+                    field.height(i, j + 1),
+                    field.height(i + 1, j + 1),
+                ];
+                let lo = h.iter().cloned().fold(std::f32::MAX, f32::min);
+                let hi = h.iter().cloned().fold(std::f32::MIN, f32::max);
+                (corner.y + lo * size.y, corner.y + hi * size.y)
+            })
+            .collect();
+        field
+    }
+
+    /// Uses the brightness of each pixel as the height, with the top of the
+    /// image at the low z end
+    pub fn from_image<T>(
+        image: &ImageBuffer<Rgb<T>, Vec<T>>,
+        corner: Vec3,
+        size: Vec3,
+        material: Arc<dyn Material>,
+    ) -> Self
+    where
+        T: 'static + Primitive + ToF32,
+    {
+        let heights = image
+            .pixels()
+            .map(|p| {
+                let c = p.channels();
+                (c[0].to() + c[1].to() + c[2].to()) / 3.0
+            })
+            .collect();
+        let (columns, rows) = image.dimensions();
+        Heightfield::new(
+            heights,
+            columns as usize,
+            rows as usize,
+            corner,
+            size,
+            material,
+        )
+    }
+
+    /// Samples a function of the position across the grid from 0 to 1, like
+    /// `|x, z| perlin.turb(Vec3::new(x, 0.0, z) * 4.0)`
+    pub fn from_fn(
+        f: impl Fn(f32, f32) -> f32,
+        columns: usize,
+        rows: usize,
+        corner: Vec3,
+        size: Vec3,
+        material: Arc<dyn Material>,
+    ) -> Self {
+        let heights = grid(rows, columns)
+            .map(|(j, i)| {
+                f(
+                    i as f32 / (columns - 1) as f32,
+                    j as f32 / (rows - 1) as f32,
+                )
+            })
+            .collect();
+        Heightfield::new(heights, columns, rows, corner, size, material)
+    }
+
+    fn height(&self, i: usize, j: usize) -> f32 {
+        self.heights[j * self.columns + i]
+    }
+
+    fn vertex(&self, i: usize, j: usize) -> Vec3 {
+        self.corner
+            + Vec3::new(
+                i as f32 / (self.columns - 1) as f32 * self.size.x,
+                self.height(i, j) * self.size.y,
+                j as f32 / (self.rows - 1) as f32 * self.size.z,
+            )
+    }
+
+    fn normal(&self, i: usize, j: usize) -> Vec3 {
+        self.normals[j * self.columns + i]
+    }
+
+    /// The closest hit on the two triangles of a cell
+    fn hit_cell(
+        &self,
+        i: usize,
+        j: usize,
+        r: Ray,
+        t_min: f32,
+        t_max: f32,
+    ) -> Option<HitRecord> {
+        let corners = [(i, j), (i, j + 1), (i + 1, j + 1), (i + 1, j)];
+        let mut closest = None;
+        let mut t_max = t_max;
+        for tri in &[[0, 1, 2], [0, 2, 3]] {
+            let p = |k: usize| self.vertex(corners[tri[k]].0, corners[tri[k]].1);
+            if let Some((t, u, v)) = intersect(p(0), p(1), p(2), r, t_min, t_max, false) {
+                t_max = t;
+                closest = Some((tri, t, u, v));
+            }
+        }
+        let (tri, t, u, v) = closest?;
+        let p = |k: usize| self.vertex(corners[tri[k]].0, corners[tri[k]].1);
+        let n = |k: usize| self.normal(corners[tri[k]].0, corners[tri[k]].1);
+        let geometric_normal = (p(1) - p(0)).cross(&(p(2) - p(0))).normalize();
+        let point = r.point_at_param(t);
+        Some(HitRecord {
+            t,
+            // flipped like images are so the one it came from lines up
+            u: (point.x - self.corner.x) / self.size.x,
+            v: 1.0 - (point.z - self.corner.z) / self.size.z,
+            point,
+            normal: (n(0) * (1.0 - u - v) + n(1) * u + n(2) * v).normalize(),
+            geometric_normal,
+            front_face: r.dir.dot(&geometric_normal) < 0.0,
+            tangent: Vec3::zero(),
+            color: Color::from(1),
+            material: self.material.as_ref(),
+        })
+    }
+}
+
+/// Every (j, i) for j below `rows` and i below `columns`, in row order
+fn grid(rows: usize, columns: usize) -> impl Iterator<Item = (usize, usize)> {
+    (0..rows).flat_map(move |j| (0..columns).map(move |i| (j, i)))
+}
+
+impl Hitable for Heightfield {
+    fn hit(&self, r: Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+        // clip the ray to the box around the terrain
+        let bb = self.get_bb();
+        let (mut start, mut end) = (t_min, t_max);
+        for &axis in &[Axis::X, Axis::Y, Axis::Z] {
+            let (o, d) = (r.origin.get_axis(axis), r.dir.get_axis(axis));
+            let (lo, hi) = (bb.min.get_axis(axis), bb.max.get_axis(axis));
+            if d == 0.0 {
+                if o < lo || o > hi {
+                    return None;
+                }
+                continue;
+            }
+            let (t0, t1) = ((lo - o) / d, (hi - o) / d);
+            start = start.max(t0.min(t1));
+            end = end.min(t0.max(t1));
+        }
+        if start > end {
+            return None;
+        }
+
+        // then walk the cells under it in order
+        let cells = (self.columns - 1, self.rows - 1);
+        let width = self.size.x / cells.0 as f32;
+        let depth = self.size.z / cells.1 as f32;
+        let entry = r.point_at_param(start) - self.corner;
+        let cell = |p: f32, size: f32, count: usize| {
+            ((p / size).floor().max(0.0) as usize).min(count - 1)
+        };
+        let (mut i, mut j) =
+            (cell(entry.x, width, cells.0), cell(entry.z, depth, cells.1));
+        // distance along the ray to the next cell boundary in each direction,
+        // and between boundaries
+        let crossing = |index: usize, size: f32, o: f32, d: f32| {
+            if d == 0.0 {
+                return (std::f32::INFINITY, std::f32::INFINITY);
+            }
+            let next = if d > 0.0 { index + 1 } else { index } as f32;
+            ((next * size - o) / d, size / d.abs())
+        };
+        let origin = r.origin - self.corner;
+        let (mut next_x, step_x) = crossing(i, width, origin.x, r.dir.x);
+        let (mut next_z, step_z) = crossing(j, depth, origin.z, r.dir.z);
+        let slack = 1e-4 * self.size.y.abs().max(1.0);
+        let mut enter = start;
+        loop {
+            let exit = next_x.min(next_z).min(end);
+            let (y0, y1) = (r.point_at_param(enter).y, r.point_at_param(exit).y);
+            let (lo, hi) = self.bounds[j * cells.0 + i];
+            if y0.min(y1) <= hi + slack && y0.max(y1) >= lo - slack {
+                if let Some(hit) = self.hit_cell(i, j, r, t_min, t_max) {
+                    return Some(hit);
+                }
+            }
+            if exit >= end {
+                return None;
+            }
+            if next_x < next_z {
+                if r.dir.x > 0.0 && i + 1 < cells.0 {
+                    i += 1;
+                } else if r.dir.x < 0.0 && i > 0 {
+                    i -= 1;
+                } else {
+                    return None;
+                }
+                next_x += step_x;
+            } else {
+                if r.dir.z > 0.0 && j + 1 < cells.1 {
+                    j += 1;
+                } else if r.dir.z < 0.0 && j > 0 {
+                    j -= 1;
+                } else {
+                    return None;
+                }
+                next_z += step_z;
+            }
+            enter = exit;
+        }
+    }
+
+    fn get_bb(&self) -> AABB {
+        let (lo, hi) = self
+            .bounds
+            .iter()
+            .fold((std::f32::MAX, std::f32::MIN), |(lo, hi), b| {
+                (lo.min(b.0), hi.max(b.1))
+            });
+        AABB::new(
+            Vec3::new(self.corner.x, lo - 0.0001, self.corner.z),
+            Vec3::new(
+                self.corner.x + self.size.x,
+                hi + 0.0001,
+                self.corner.z + self.size.z,
+            ),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometry::mesh::MeshData;
+    use crate::geometry::triangle_mesh::TriangleMesh;
+    use crate::material::specular::Specular;
+    use crate::matrix::Matrix4;
+    use crate::texture::perlin::{Perlin, PerlinVariant};
+
+    fn material() -> Arc<dyn Material> {
+        Arc::new(Specular::new(Color::from(1), 0.0))
+    }
+
+    /// The same triangles as a regular mesh
+    fn mesh(field: &Heightfield) -> TriangleMesh {
+        let mut data = MeshData::default();
+        for (j, i) in grid(field.rows, field.columns) {
+            data.positions.push(field.vertex(i, j));
+        }
+        for (j, i) in grid(field.rows - 1, field.columns - 1) {
+            let v = |i: usize, j: usize| (j * field.columns + i) as u32;
+            let (a, b, c, d) = (v(i, j), v(i, j + 1), v(i + 1, j + 1), v(i + 1, j));
+            data.indices.extend_from_slice(&[a, b, c, a, c, d]);
+        }
+        TriangleMesh::new(&data, Matrix4::identity(), material())
+    }
+
+    #[test]
+    fn test_flat() {
+        let field = Heightfield::from_fn(
+            |_, _| 0.5,
+            5,
+            3,
+            Vec3::new(-2, 1, -1),
+            Vec3::new(4, 2, 2),
+            material(),
+        );
+        let r = Ray::new(Vec3::new(0.3, 5.0, 0.2), Vec3::new(0, -1, 0));
+        let hit = field.hit(r, 0.0, std::f32::MAX).unwrap();
+        assert!((hit.t - 3.0).abs() < 1e-5);
+        assert_eq!(hit.normal, Vec3::new(0, 1, 0));
+        assert!(hit.front_face);
+        assert!((hit.u - 0.575).abs() < 1e-6 && (hit.v - 0.4).abs() < 1e-6);
+        // level with the ground, just above and below it
+        let r = Ray::new(Vec3::new(-5.0, 2.01, 0.0), Vec3::new(1, 0, 0));
+        assert!(field.hit(r, 0.0, std::f32::MAX).is_none());
+        let r = Ray::new(Vec3::new(-5.0, 1.99, 0.0), Vec3::new(1, 0, 0));
+        assert!(field.hit(r, 0.0, std::f32::MAX).is_none());
+        // outside the edges
+        let r = Ray::new(Vec3::new(2.5, 5.0, 0.0), Vec3::new(0, -1, 0));
+        assert!(field.hit(r, 0.0, std::f32::MAX).is_none());
+    }
+
+    #[test]
+    fn test_matches_mesh() {
+        let perlin = Perlin::new(1.0, Vec3::from(1), PerlinVariant::Noise);
+        let field = Heightfield::from_fn(
+            |x, z| perlin.turb(Vec3::new(x, 0.0, z) * 3.0),
+            33,
+            17,
+            Vec3::new(-4, 0, -2),
+            Vec3::new(8, 2, 4),
+            material(),
+        );
+        let mesh = mesh(&field);
+        for a in 0..20 {
+            for b in 0..10 {
+                let angle = a as f32 * 0.3;
+                let origin = Vec3::new(angle.cos() * 6.0, 1.0 + b as f32 * 0.3, 1.1);
+                let target = Vec3::new(b as f32 * 0.4 - 2.0, 0.3, a as f32 * 0.2 - 2.0);
+                let r = Ray::new(origin, target - origin);
+                let expected = mesh.hit(r, 0.0, std::f32::MAX);
+                let found = field.hit(r, 0.0, std::f32::MAX);
+                assert_eq!(expected.is_some(), found.is_some());
+                if let (Some(e), Some(f)) = (expected, found) {
+                    assert!((e.t - f.t).abs() < 1e-5);
+                    assert!((e.geometric_normal - f.geometric_normal).len() < 1e-5);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_from_image() {
+        let mut image = image::RgbImage::new(3, 2);
+        image.put_pixel(2, 1, Rgb([255, 255, 255]));
+        let field =
+            Heightfield::from_image(&image, Vec3::zero(), Vec3::new(2, 1, 1), material());
+        assert_eq!(field.vertex(2, 1), Vec3::new(2, 1, 1));
+        assert_eq!(field.vertex(1, 1), Vec3::new(1, 0, 1));
+        let bb = field.get_bb();
+        assert!((bb.max.y - 1.0).abs() < 1e-3 && bb.min.y < 0.0);
+        // the corner slopes up to the bright pixel
+        let r = Ray::new(Vec3::new(1.75, 5.0, 0.75), Vec3::new(0, -1, 0));
+        let hit = field.hit(r, 0.0, std::f32::MAX).unwrap();
+        assert!(hit.point.y > 0.0 && hit.point.y < 1.0);
+        assert!(hit.normal.x < 0.0 && hit.normal.z < 0.0);
+    }
+}
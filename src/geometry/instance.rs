@@ -0,0 +1,109 @@
+use super::aabb::AABB;
+use super::transform::Transform;
+use super::volume::Segment;
+use super::{HitRecord, Hitable};
+use crate::material::Material;
+use crate::matrix::Matrix4;
+use crate::ray::Ray;
+use std::sync::Arc;
+
+#[macro_export]
+macro_rules! instance {
+    ($prototype:expr, $matrix:expr) => {
+        Box::new(
+            Instance::new($prototype.clone(), $matrix)
+                .expect("instance matrix must be invertible"),
+        )
+    };
+    ($prototype:expr, $matrix:expr, $mat:expr) => {
+        Box::new(
+            Instance::new($prototype.clone(), $matrix)
+                .expect("instance matrix must be invertible")
+                .with_material($mat),
+        )
+    };
+}
+
+/// A transformed reference to a shared prototype object. The prototype (and
+/// its BVH if it has one) is only stored once no matter how many instances
+/// of it are in the scene, so a forest of the same tree mesh costs little
+/// more than a single tree.
+#[derive(Debug)]
+pub struct Instance {
+    obj: Transform<Arc<dyn Hitable>>,
+    material: Option<Arc<dyn Material>>,
+}
+
+impl Instance {
+    /// `None` when the transform flattens the prototype, like a scale of 0
+    pub fn new(prototype: Arc<dyn Hitable>, transform: Matrix4) -> Option<Self> {
+        Some(Instance {
+            obj: Transform::new(prototype, transform)?,
+            material: None,
+        })
+    }
+
+    /// Replaces the material of every surface in the prototype for just this
+    /// instance
+    pub fn with_material(mut self, material: Arc<dyn Material>) -> Self {
+        self.material = Some(material);
+        self
+    }
+}
+
+impl Hitable for Instance {
+    fn hit(&self, r: Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+        let mut rec = self.obj.hit(r, t_min, t_max)?;
+        if let Some(material) = &self.material {
+            rec.material = material.as_ref();
+        }
+        Some(rec)
+    }
+    fn get_bb(&self) -> AABB {
+        self.obj.get_bb()
+    }
+    fn has_media(&self) -> bool {
+        self.obj.has_media()
+    }
+    /// Media keep their own phase functions, `with_material` is only for
+    /// surfaces
+    fn media<'a>(&'a self, r: Ray, t_min: f32, t_max: f32, found: &mut Vec<Segment<'a>>) {
+        self.obj.media(r, t_min, t_max, found)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometry::sphere::Sphere;
+    use crate::material::{diffuse::Diffuse, light::Light};
+    use crate::texture::solid::Solid;
+    use crate::vec3::Vec3;
+
+    #[test]
+    fn test_shared_prototype() {
+        let prototype: Arc<dyn Hitable> = Arc::new(Sphere::new(
+            Vec3::zero(),
+            1.0,
+            Arc::new(Diffuse::new(Solid::new(Vec3::from(0.5)))),
+        ));
+        let glowing = Arc::new(Light::new(Solid::new(Vec3::from(4))));
+        let a = Instance::new(prototype.clone(), Matrix4::translate(Vec3::new(-5, 0, 0)))
+            .unwrap();
+        let b = Instance::new(prototype.clone(), Matrix4::translate(Vec3::new(5, 0, 0)))
+            .unwrap()
+            .with_material(glowing);
+        assert!(
+            Instance::new(prototype.clone(), Matrix4::scale(Vec3::new(1, 0, 1)))
+                .is_none()
+        );
+        assert_eq!(Arc::strong_count(&prototype), 3);
+        let r = Ray::new(Vec3::new(-10, 0, 0), Vec3::new(1, 0, 0));
+        let hit_a = a.hit(r, 0.0, std::f32::MAX).unwrap();
+        let hit_b = b.hit(r, 0.0, std::f32::MAX).unwrap();
+        assert_eq!(hit_a.point, Vec3::new(-6, 0, 0));
+        assert_eq!(hit_b.point, Vec3::new(4, 0, 0));
+        assert_eq!(hit_a.material.emit(0.0, 0.0, hit_a.point), Vec3::zero());
+        assert_eq!(hit_b.material.emit(0.0, 0.0, hit_b.point), Vec3::from(4));
+    }
+}
@@ -1,9 +1,20 @@
 use super::aabb::AABB;
-use super::{HitRecord, Hitable};
+use super::displacement::Displacement;
+use super::subdivision::{Polygons, Subdivision};
+use super::triangle_mesh::TriangleMesh;
+use super::{ply, stl, HitRecord, Hitable};
+use crate::axis::Axis;
 use crate::bvh::BVHNode;
-use crate::material::Material;
+use crate::material::{
+    dielectric::Dielectric, diffuse::Diffuse, light::Light, specular::Specular, Material,
+};
+use crate::matrix::Matrix4;
 use crate::ray::Ray;
+use crate::scene::Color;
+use crate::texture::{solid::Solid, tinted::Tinted};
 use crate::vec3::Vec3;
+use std::io::{self, Error, ErrorKind};
+use std::path::Path;
 use std::sync::Arc;
 use tobj;
 
@@ -19,56 +30,227 @@ pub struct Mesh {
     pub faces: BVHNode,
 }
 
-impl Mesh {
-    fn generate_normals(points: &[Vec3], indices: &[u32]) -> Vec<Vec3> {
-        (0..points.len())
-            .map(|p| {
-                indices
-                    .chunks(3)
-                    .filter_map(|i| {
-                        let (a, b, c) = (i[0] as usize, i[1] as usize, i[2] as usize);
-                        if a == p || b == p || c == p {
-                            Some((points[b] - points[a]).cross(&(points[c] - points[a])))
-                        } else {
-                            None
-                        }
-                    })
-                    .fold(Vec3::zero(), |a, b| a + b)
-                    .normalize()
+/// How generated normals are shared between neighboring faces
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Shading {
+    /// Faces share normals across every edge
+    Smooth,
+    /// Only faces meeting at less than this many degrees share normals, so
+    /// hard edges stay sharp
+    Crease(f32),
+    /// Every face gets its own normal
+    Flat,
+}
+
+/// Vertex buffers straight out of a model file before they're turned into
+/// triangles. The optional attributes can be left empty, otherwise they need
+/// one entry per position.
+#[derive(Debug, Default, Clone)]
+pub struct MeshData {
+    pub positions: Vec<Vec3>,
+    /// Generated from the faces if missing
+    pub normals: Vec<Vec3>,
+    pub texcoords: Vec<Vec3>,
+    /// Per vertex colors multiply whatever the material scatters
+    pub colors: Vec<Color>,
+    /// Every 3 indices form a triangle
+    pub indices: Vec<u32>,
+    /// Optional group per triangle like OBJ's `s` statement. Generated
+    /// normals are only shared within a group, and group 0 is flat shaded.
+    pub smoothing_groups: Vec<u32>,
+}
+
+impl MeshData {
+    /// Replaces the normals with ones averaged from the faces around each
+    /// vertex, weighted by the angle of each face's corner. Vertices get
+    /// split wherever faces around them end up with different normals.
+    pub fn generate_normals(&mut self, shading: Shading) {
+        let triangles = self.indices.len() / 3;
+        let corner = |c: usize| self.positions[self.indices[c] as usize];
+        let face_normals: Vec<Vec3> = (0..triangles)
+            .map(|t| {
+                let n = (corner(t * 3 + 1) - corner(t * 3))
+                    .cross(&(corner(t * 3 + 2) - corner(t * 3)));
+                if n.len() > 0.0 {
+                    n.normalize()
+                } else {
+                    Vec3::zero()
+                }
             })
-            .collect()
+            .collect();
+        let angles: Vec<f32> = (0..self.indices.len())
+            .map(|c| {
+                let t = c / 3 * 3;
+                let a = corner(t + (c + 1) % 3) - corner(c);
+                let b = corner(t + (c + 2) % 3) - corner(c);
+                if a.len() > 0.0 && b.len() > 0.0 {
+                    a.normalize().dot(&b.normalize()).max(-1.0).min(1.0).acos()
+                } else {
+                    0.0
+                }
+            })
+            .collect();
+        // corners around each vertex, stored contiguously
+        let mut start = vec![0; self.positions.len() + 1];
+        for &i in &self.indices {
+            start[i as usize + 1] += 1;
+        }
+        for v in 0..self.positions.len() {
+            start[v + 1] += start[v];
+        }
+        let mut fill = start.clone();
+        let mut corners = vec![0; self.indices.len()];
+        for (c, &i) in self.indices.iter().enumerate() {
+            corners[fill[i as usize]] = c;
+            fill[i as usize] += 1;
+        }
+
+        let groups = if self.smoothing_groups.len() == triangles {
+            self.smoothing_groups.clone()
+        } else {
+            vec![1; triangles]
+        };
+        let cos_crease = match shading {
+            Shading::Crease(angle) => angle.to_radians().cos(),
+            _ => -1.0,
+        };
+        let smooths = |a: usize, b: usize| {
+            a == b
+                || (shading != Shading::Flat
+                    && groups[a] == groups[b]
+                    && groups[a] != 0
+                    && face_normals[a].dot(&face_normals[b]) >= cos_crease)
+        };
+        let mut data = MeshData {
+            indices: vec![0; self.indices.len()],
+            smoothing_groups: self.smoothing_groups.clone(),
+            ..MeshData::default()
+        };
+        for v in 0..self.positions.len() {
+            let around = &corners[start[v]..start[v + 1]];
+            let first = data.positions.len();
+            for &c in around {
+                let normal = around
+                    .iter()
+                    .filter(|&&other| smooths(c / 3, other / 3))
+                    .fold(Vec3::zero(), |n, &other| {
+                        n + face_normals[other / 3] * angles[other]
+                    });
+                let normal = if normal.len() > 0.0 {
+                    normal.normalize()
+                } else {
+                    face_normals[c / 3]
+                };
+                // corners that ended up with the same normal share a vertex
+                let index = match data.normals[first..].iter().position(|&n| n == normal)
+                {
+                    Some(i) => first + i,
+                    None => {
+                        data.positions.push(self.positions[v]);
+                        data.normals.push(normal);
+                        if let Some(&t) = self.texcoords.get(v) {
+                            data.texcoords.push(t);
+                        }
+                        if let Some(&color) = self.colors.get(v) {
+                            data.colors.push(color);
+                        }
+                        data.positions.len() - 1
+                    }
+                };
+                data.indices[c] = index as u32;
+            }
+        }
+        if self.texcoords.len() != self.positions.len() {
+            data.texcoords.clear();
+        }
+        if self.colors.len() != self.positions.len() {
+            data.colors.clear();
+        }
+        *self = data;
     }
 
-    pub fn new(filename: &str, scale: f32, material: Arc<dyn Material>) -> Self {
-        let (model, _material) = tobj::load_obj(&std::path::Path::new(filename)).unwrap();
-        // TODO: support objs with more than 1 mesh
-        let mesh = &model[0].mesh;
-        let points: Vec<Vec3> = mesh
+    /// Bakes a transform into the vertices. Missing normals get generated and
+    /// attributes that don't line up with the positions are dropped. A
+    /// transform that flattens the mesh has no inverse to carry the normals
+    /// through, so they get generated from the flattened shape instead.
+    pub fn transformed(&self, transform: Matrix4) -> MeshData {
+        let count = self.positions.len();
+        let positions: Vec<Vec3> = self
             .positions
-            .chunks(3)
-            .map(|pos| Vec3::new(pos[0], pos[1], pos[2]) * scale)
+            .iter()
+            .map(|&p| transform.transform_point(p))
             .collect();
-        let normals: Vec<Vec3> = if mesh.normals.is_empty() {
-            Mesh::generate_normals(&points, &mesh.indices)
-        } else {
-            mesh.normals
+        // mirroring reverses the winding, so swap two corners of every
+        // triangle to keep the same side facing out
+        let axis = |x, y, z| transform.transform_vector(Vec3::new(x, y, z));
+        let mirrored = axis(1, 0, 0).cross(&axis(0, 1, 0)).dot(&axis(0, 0, 1)) < 0.0;
+        let indices: Vec<u32> = if mirrored {
+            self.indices
                 .chunks(3)
-                .map(|pos| Vec3::new(pos[0], pos[1], pos[2]))
+                .flat_map(|i| vec![i[0], i[2], i[1]])
                 .collect()
-        };
-        let texture_coords: Vec<Vec3> = if mesh.texcoords.is_empty() {
-            vec![Vec3::zero(); points.len() * 3 / 2]
         } else {
-            mesh.texcoords
-                .chunks(2)
-                .map(|pos| Vec3::new(pos[0], pos[1], 0.0))
-                .collect()
+            self.indices.clone()
+        };
+        let normal_matrix = match transform.inverse() {
+            Some(inverse) if self.normals.len() == count => inverse.transpose(),
+            _ => {
+                let mut data = MeshData {
+                    positions,
+                    normals: Vec::new(),
+                    texcoords: self.texcoords.clone(),
+                    colors: self.colors.clone(),
+                    indices,
+                    smoothing_groups: self.smoothing_groups.clone(),
+                };
+                data.generate_normals(Shading::Smooth);
+                return data;
+            }
         };
-        let vertices: Vec<Arc<Vertex>> = (0..points.len())
-            .map(|i| Arc::new(Vertex::new(points[i], normals[i], texture_coords[i])))
+        let normals = self
+            .normals
+            .iter()
+            .map(|&n| normal_matrix.transform_vector(n).normalize())
+            .collect();
+        let matching = |v: &Vec<Vec3>| {
+            if v.len() == count {
+                v.clone()
+            } else {
+                Vec::new()
+            }
+        };
+        MeshData {
+            positions,
+            normals,
+            texcoords: matching(&self.texcoords),
+            colors: matching(&self.colors),
+            indices,
+            smoothing_groups: self.smoothing_groups.clone(),
+        }
+    }
+
+    /// Individually boxed triangles, `TriangleMesh` is a lot more compact
+    pub fn triangles(
+        &self,
+        transform: Matrix4,
+        material: Arc<dyn Material>,
+    ) -> Vec<Box<dyn Hitable>> {
+        let data = self.transformed(transform);
+        let vertices: Vec<Arc<Vertex>> = (0..data.positions.len())
+            .map(|i| {
+                Arc::new(Vertex {
+                    pos: data.positions[i],
+                    normal: data.normals[i],
+                    texture: data.texcoords.get(i).cloned().unwrap_or_else(Vec3::zero),
+                    color: data
+                        .colors
+                        .get(i)
+                        .cloned()
+                        .unwrap_or_else(|| Color::from(1)),
+                })
+            })
             .collect();
-        let mut triangles = mesh
-            .indices
+        data.indices
             .chunks(3)
             .map(|i| {
                 let (a, b, c) = (i[0] as usize, i[1] as usize, i[2] as usize);
@@ -79,11 +261,321 @@ impl Mesh {
                     material.clone(),
                 )) as Box<dyn Hitable>
             })
-            .collect();
+            .collect()
+    }
+}
+
+impl Mesh {
+    pub fn new(filename: &str, scale: f32, material: Arc<dyn Material>) -> Self {
+        Mesh::with_transform(filename, Matrix4::scale(Vec3::from(scale)), material)
+    }
+
+    /// Bakes a transformation into the vertices at load time, which is
+    /// cheaper than wrapping the mesh in a `Transform`
+    pub fn with_transform(
+        filename: &str,
+        transform: Matrix4,
+        material: Arc<dyn Material>,
+    ) -> Self {
+        let (models, _materials, faces) = load_obj(filename).unwrap();
+        Mesh::from_models(
+            &models,
+            &faces,
+            transform,
+            |data, _| data,
+            |_| material.clone(),
+        )
+    }
+
+    /// Uses the materials from the obj's mtl files instead of a single
+    /// material for the whole model. Faces without a material get `fallback`.
+    /// A model where only some of the faces have texture coordinates loses
+    /// all of them, so textures on it come out a single color. Missing files,
+    /// including textures, are returned as errors.
+    pub fn with_materials(
+        filename: &str,
+        transform: Matrix4,
+        fallback: Arc<dyn Material>,
+    ) -> io::Result<Self> {
+        Mesh::obj_with_materials(filename, transform, fallback, |data, _| data)
+    }
+
+    /// Like `with_materials` but regenerates the normals, ignoring any in
+    /// the file
+    pub fn with_shading(
+        filename: &str,
+        transform: Matrix4,
+        fallback: Arc<dyn Material>,
+        shading: Shading,
+    ) -> io::Result<Self> {
+        Mesh::obj_with_materials(filename, transform, fallback, |mut data, _| {
+            data.generate_normals(shading);
+            data
+        })
+    }
+
+    /// Like `with_materials` but treats the faces in the file as the cage of
+    /// a subdivision surface. The normals are generated from the result.
+    pub fn with_subdivision(
+        filename: &str,
+        transform: Matrix4,
+        fallback: Arc<dyn Material>,
+        subdivision: &Subdivision,
+    ) -> io::Result<Self> {
+        Mesh::obj_with_materials(filename, transform, fallback, |data, faces| {
+            let sides: Vec<u32> = faces.iter().map(|f| f.sides).collect();
+            subdivision.apply(&Polygons::from_triangles(&data, Some(&sides)))
+        })
+    }
+
+    /// Like `with_materials` but tessellates the faces and displaces them
+    /// by a texture
+    pub fn with_displacement(
+        filename: &str,
+        transform: Matrix4,
+        fallback: Arc<dyn Material>,
+        displacement: &Displacement,
+    ) -> io::Result<Self> {
+        Mesh::obj_with_materials(filename, transform, fallback, |data, _| {
+            displacement.apply(&data)
+        })
+    }
+
+    fn obj_with_materials(
+        filename: &str,
+        transform: Matrix4,
+        fallback: Arc<dyn Material>,
+        prepare: impl Fn(MeshData, &[ObjFace]) -> MeshData,
+    ) -> io::Result<Self> {
+        let (models, materials, faces) = load_obj(filename)?;
+        let dir = Path::new(filename)
+            .parent()
+            .unwrap_or_else(|| Path::new(""));
+        let materials = materials
+            .iter()
+            .map(|m| obj_material(m, dir))
+            .collect::<io::Result<Vec<_>>>()?;
+        Ok(Mesh::from_models(
+            &models,
+            &faces,
+            transform,
+            prepare,
+            |id| id.map_or(fallback.clone(), |i| materials[i].clone()),
+        ))
+    }
+
+    pub fn from_ply(
+        filename: &str,
+        transform: Matrix4,
+        material: Arc<dyn Material>,
+    ) -> io::Result<Self> {
+        Ok(Mesh::from_data(&ply::load(filename)?, transform, material))
+    }
+
+    pub fn from_stl(
+        filename: &str,
+        transform: Matrix4,
+        material: Arc<dyn Material>,
+    ) -> io::Result<Self> {
+        Ok(Mesh::from_data(&stl::load(filename)?, transform, material))
+    }
+
+    pub fn from_data(
+        data: &MeshData,
+        transform: Matrix4,
+        material: Arc<dyn Material>,
+    ) -> Self {
+        let mesh: Box<dyn Hitable> =
+            Box::new(TriangleMesh::new(data, transform, material));
         Mesh {
-            faces: BVHNode::from(&mut triangles),
+            faces: BVHNode::from(&mut vec![mesh]),
         }
     }
+
+    fn from_models(
+        models: &[tobj::Model],
+        faces: &[Vec<ObjFace>],
+        transform: Matrix4,
+        prepare: impl Fn(MeshData, &[ObjFace]) -> MeshData,
+        material_for: impl Fn(Option<usize>) -> Arc<dyn Material>,
+    ) -> Self {
+        let mut parts: Vec<Box<dyn Hitable>> = Vec::new();
+        for (i, model) in models.iter().enumerate() {
+            let mesh = &model.mesh;
+            let faces = faces.get(i).map_or(&[][..], |f| f.as_slice());
+            // tobj gives every unique position/uv/normal combination its own
+            // index, but if only some faces specify uvs or normals the arrays
+            // stop lining up with positions so MeshData ignores them
+            let data = MeshData {
+                positions: mesh
+                    .positions
+                    .chunks(3)
+                    .map(|p| Vec3::new(p[0], p[1], p[2]))
+                    .collect(),
+                normals: mesh
+                    .normals
+                    .chunks(3)
+                    .map(|n| Vec3::new(n[0], n[1], n[2]))
+                    .collect(),
+                texcoords: mesh
+                    .texcoords
+                    .chunks(2)
+                    .map(|t| Vec3::new(t[0], t[1], 0.0))
+                    .collect(),
+                colors: Vec::new(),
+                indices: mesh.indices.clone(),
+                smoothing_groups: faces
+                    .iter()
+                    .filter_map(|f| {
+                        Some(std::iter::repeat(f.group?).take(f.sides as usize - 2))
+                    })
+                    .flatten()
+                    .collect(),
+            };
+            let data = prepare(data, faces);
+            if !data.indices.is_empty() {
+                let material = material_for(mesh.material_id);
+                parts.push(Box::new(TriangleMesh::new(&data, transform, material)));
+            }
+        }
+        Mesh {
+            faces: BVHNode::from(&mut parts),
+        }
+    }
+}
+
+/// The models and materials tobj reads, and the faces it leaves out
+type Obj = (Vec<tobj::Model>, Vec<tobj::Material>, Vec<Vec<ObjFace>>);
+
+fn load_obj(filename: &str) -> io::Result<Obj> {
+    // tobj gives up on anything that isn't utf-8, but exporters still write
+    // latin-1 names and comments. Everything that matters is ascii so it's
+    // decoded lossily first.
+    let read = |path: &Path| {
+        std::fs::read(path).map(|bytes| String::from_utf8_lossy(&bytes).into_owned())
+    };
+    let dir = Path::new(filename)
+        .parent()
+        .unwrap_or_else(|| Path::new(""));
+    let text = read(Path::new(filename))?;
+    let (models, materials) = tobj::load_obj_buf(&mut text.as_bytes(), |mtl| {
+        let text = read(&dir.join(mtl)).map_err(|_| tobj::LoadError::OpenFileFailed)?;
+        tobj::load_mtl_buf(&mut text.as_bytes())
+    })
+    .map_err(|e| Error::new(ErrorKind::InvalidData, format!("obj: {}", e)))?;
+    let faces = obj_faces(&text, &materials);
+    Ok((models, materials, faces))
+}
+
+/// A polygon from an obj file, which tobj only hands out as a fan of
+/// triangles
+#[derive(Debug, Copy, Clone, PartialEq)]
+struct ObjFace {
+    sides: u32,
+    /// The smoothing group, none if the file doesn't use them in which case
+    /// everything is smoothed together
+    group: Option<u32>,
+}
+
+/// tobj drops smoothing groups and polygons, so this reads the faces
+/// separately and splits them into models the same way tobj does
+fn obj_faces(text: &str, materials: &[tobj::Material]) -> Vec<Vec<ObjFace>> {
+    let mut models = Vec::new();
+    let mut faces = Vec::new();
+    let mut group = 0;
+    let mut used = false;
+    let mut material = None;
+    for line in text.lines() {
+        let mut words = line.split_whitespace();
+        match words.next() {
+            Some("f") => {
+                let sides = words.count() as u32;
+                if sides >= 3 {
+                    faces.push(ObjFace {
+                        sides,
+                        group: Some(group),
+                    });
+                }
+            }
+            Some("s") => {
+                used = true;
+                group = words.next().and_then(|g| g.parse().ok()).unwrap_or(0);
+            }
+            Some("o") | Some("g") if !faces.is_empty() => {
+                models.push(std::mem::take(&mut faces));
+            }
+            Some("usemtl") => {
+                if let Some(name) = words.next() {
+                    let id = materials.iter().rposition(|m| m.name == name);
+                    if id != material && !faces.is_empty() {
+                        models.push(std::mem::take(&mut faces));
+                    }
+                    material = id;
+                }
+            }
+            _ => {}
+        }
+    }
+    models.push(faces);
+    if !used {
+        for face in models.iter_mut().flatten() {
+            face.group = None;
+        }
+    }
+    models
+}
+
+/// Picks whichever of this crate's materials best matches an mtl definition.
+/// There's no layered material yet so a surface is either emissive, glass,
+/// metal or diffuse based on its strongest component.
+fn obj_material(mtl: &tobj::Material, dir: &Path) -> io::Result<Arc<dyn Material>> {
+    let color = |c: [f32; 3]| Color::new(c[0], c[1], c[2]);
+    let param = |name: &str| {
+        mtl.unknown_param.get(name).and_then(|s| {
+            let c: Vec<f32> = s
+                .split_whitespace()
+                .filter_map(|n| n.parse().ok())
+                .collect();
+            match c.len() {
+                1 => Some(Color::from(c[0])),
+                3 => Some(Color::new(c[0], c[1], c[2])),
+                _ => None,
+            }
+        })
+    };
+    let brightest = |c: Color| c.x.max(c.y).max(c.z);
+    if let Some(emission) = param("Ke").filter(|&ke| brightest(ke) > 0.0) {
+        return Ok(Arc::new(Light::new(Solid::new(emission))));
+    }
+    // illum 4, 6, and 7 are the refractive illumination models
+    let refractive = match mtl.illumination_model {
+        Some(4) | Some(6) | Some(7) => true,
+        _ => false,
+    };
+    if mtl.dissolve < 1.0 || refractive {
+        let tint = param("Tf").unwrap_or_else(|| Color::from(1));
+        let index = if mtl.optical_density > 1.0 {
+            mtl.optical_density
+        } else {
+            1.5
+        };
+        return Ok(Arc::new(Dielectric::new(tint, index)));
+    }
+    let diffuse = color(mtl.diffuse);
+    let specular = color(mtl.specular);
+    if brightest(specular) > brightest(diffuse) {
+        // convert the phong exponent to an approximate roughness
+        let fuzz = (2.0 / (mtl.shininess + 2.0)).sqrt();
+        return Ok(Arc::new(Specular::new(specular, fuzz)));
+    }
+    if !mtl.diffuse_texture.is_empty() {
+        let texture = dir.join(&mtl.diffuse_texture);
+        let img = image::open(&texture).map_err(|e| {
+            Error::new(ErrorKind::InvalidData, format!("{:?}: {}", texture, e))
+        })?;
+        return Ok(Arc::new(Diffuse::new(Tinted::new(img.to_rgb(), diffuse))));
+    }
+    Ok(Arc::new(Diffuse::new(Solid::new(diffuse))))
 }
 
 impl Hitable for Mesh {
@@ -101,6 +593,7 @@ pub struct Vertex {
     normal: Vec3,
     // TODO: replace with Vec2
     texture: Vec3,
+    color: Color,
 }
 
 impl Vertex {
@@ -109,8 +602,13 @@ impl Vertex {
             pos,
             normal,
             texture,
+            color: Color::from(1),
         }
     }
+
+    pub fn with_color(self, color: Color) -> Vertex {
+        Vertex { color, ..self }
+    }
 }
 
 impl From<Vec3> for Vertex {
@@ -119,6 +617,7 @@ impl From<Vec3> for Vertex {
             pos: v,
             normal: Vec3::zero(),
             texture: Vec3::zero(),
+            color: Color::from(1),
         }
     }
 }
@@ -129,6 +628,7 @@ pub struct Triangle {
     v1: Arc<Vertex>,
     v2: Arc<Vertex>,
     material: Arc<dyn Material>,
+    cull: bool,
 }
 
 impl Triangle {
@@ -143,62 +643,121 @@ impl Triangle {
             v1,
             v2,
             material,
+            cull: false,
         }
     }
+
+    /// Ignores hits from behind, where the vertices wind clockwise. That's
+    /// only safe for closed surfaces seen from outside.
+    pub fn with_culling(self, cull: bool) -> Self {
+        Triangle { cull, ..self }
+    }
+}
+
+/// Watertight ray triangle intersection from Woop, Benthin and Wald 2013.
+/// The vertices are moved into a space where the ray starts at the origin and
+/// points down +z, so the test reduces to 2D edge functions of the projected
+/// vertices:
+///
+/// U = C x B, V = A x C, W = B x A     (2D cross products)
+///
+/// The ray hits if they all have the same sign, and U, V and W scaled by
+/// their sum are the barycentric coordinates of A, B and C. Neighboring
+/// triangles evaluate a shared edge with exactly the same numbers, so a ray
+/// through the edge always hits one of them and never slips between.
+///
+/// The edge functions are positive when the ray hits the counter-clockwise
+/// side, and `cull` skips everything else.
+pub(crate) fn intersect(
+    v0: Vec3,
+    v1: Vec3,
+    v2: Vec3,
+    r: Ray,
+    t_min: f32,
+    t_max: f32,
+    cull: bool,
+) -> Option<(f32, f32, f32)> {
+    use Axis::*;
+    let d = r.dir;
+    // the largest dimension of the direction becomes z
+    let kz = if d.x.abs() > d.y.abs() && d.x.abs() > d.z.abs() {
+        X
+    } else if d.y.abs() > d.z.abs() {
+        Y
+    } else {
+        Z
+    };
+    let (kx, ky) = match kz {
+        X => (Y, Z),
+        Y => (Z, X),
+        Z => (X, Y),
+    };
+    // swapping keeps the winding when the ray points down the axis
+    let (kx, ky) = if d.get_axis(kz) < 0.0 {
+        (ky, kx)
+    } else {
+        (kx, ky)
+    };
+    let sz = 1.0 / d.get_axis(kz);
+    let sx = d.get_axis(kx) * sz;
+    let sy = d.get_axis(ky) * sz;
+
+    let (a, b, c) = (v0 - r.origin, v1 - r.origin, v2 - r.origin);
+    let shear = |p: Vec3| {
+        (
+            p.get_axis(kx) - sx * p.get_axis(kz),
+            p.get_axis(ky) - sy * p.get_axis(kz),
+        )
+    };
+    let ((ax, ay), (bx, by), (cx, cy)) = (shear(a), shear(b), shear(c));
+    let mut u = cx * by - cy * bx;
+    let mut v = ax * cy - ay * cx;
+    let mut w = bx * ay - by * ax;
+    // exactly on an edge, redo it in double precision so the sign is right
+    if u == 0.0 || v == 0.0 || w == 0.0 {
+        let cross = |x0: f32, y0: f32, x1: f32, y1: f32| {
+            (f64::from(x0) * f64::from(y1) - f64::from(y0) * f64::from(x1)) as f32
+        };
+        u = cross(cx, cy, bx, by);
+        v = cross(ax, ay, cx, cy);
+        w = cross(bx, by, ax, ay);
+    }
+    let front = u > 0.0 || v > 0.0 || w > 0.0;
+    let back = u < 0.0 || v < 0.0 || w < 0.0;
+    if (front && back) || (cull && back) {
+        return None;
+    }
+    let det = u + v + w;
+    if det == 0.0 {
+        // ray is parallel to the triangle or the triangle is degenerate
+        return None;
+    }
+    let t = (u * a.get_axis(kz) + v * b.get_axis(kz) + w * c.get_axis(kz)) * sz / det;
+    if t < t_min || t > t_max {
+        return None;
+    }
+    Some((t, v / det, w / det))
 }
 
 impl Hitable for Triangle {
-    /// Solving ray triangle intersection with Möller-Trumbore algorithm:
-    /// Triangle verticies A B and C
-    /// Triangle edges E1 = B - A and E2 = C - A
-    /// Ray origin O and direction D
-    /// Barycentric coords u and v express P = (1-u-v)A * uB * vC
-    /// Relative position of ray origin to vertex A is called T = (O-A)
-    /// Solving the system using Cramers rule:
-    ///
-    /// t        1      |T E1 E2|        (note that scalar tripple product
-    /// u  = ---------  |D T  E2|         |A B C| is equal to AxB.C and
-    /// v    |D E1 E2|  |D E1 T |         also A.BxC)
-    ///
-    /// if 0 <= u <= 1 and 0 <= u + v <= 1 then the collision is valid
     fn hit(&self, r: Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
-        let edge1 = self.v1.pos - self.v0.pos;
-        let edge2 = self.v2.pos - self.v0.pos;
-        let d_cross_e2 = r.dir.cross(&edge2);
-        let det = edge1.dot(&d_cross_e2);
-        if det.abs() <= std::f32::EPSILON {
-            // ray is parallel to plane
-            return None;
-        }
-        //TODO: only back-face cull for dielectrics
-        if det <= 0.0 {
-            // intersection is with the back-face of the triangle
-            return None;
-        }
-        let tvec = r.origin - self.v0.pos;
-        let u = tvec.dot(&d_cross_e2) / det;
-        if u <= 0.0 || u >= 1.0 {
-            return None;
-        }
-        let t_cross_e1 = tvec.cross(&edge1);
-        let v = r.dir.dot(&t_cross_e1) / det;
-        if v <= 0.0 || u + v >= 1.0 {
-            return None;
-        }
-        let t = edge2.dot(&t_cross_e1) / det;
-        if t < t_min || t > t_max {
-            return None;
-        }
+        let (v0, v1, v2) = (self.v0.pos, self.v1.pos, self.v2.pos);
+        let (t, u, v) = intersect(v0, v1, v2, r, t_min, t_max, self.cull)?;
         let w = 1.0 - u - v;
         let texture_coords =
             self.v0.texture * w + self.v1.texture * u + self.v2.texture * v;
+        let geometric_normal = (v1 - v0).cross(&(v2 - v0)).normalize();
         Some(HitRecord {
             t,
             u: texture_coords.x,
             v: texture_coords.y,
-            point: self.v0.pos + edge1 * u + edge2 * v,
+            point: self.v0.pos * w + self.v1.pos * u + self.v2.pos * v,
             // interpolate normal between vertex normals
             normal: self.v0.normal * w + self.v1.normal * u + self.v2.normal * v,
+            geometric_normal,
+            front_face: r.dir.dot(&geometric_normal) < 0.0,
+            tangent: Vec3::zero(),
+            color: self.v0.color * w + self.v1.color * u + self.v2.color * v,
             material: self.material.as_ref(),
         })
     }
@@ -221,6 +780,7 @@ impl Hitable for Triangle {
 mod tests {
     use super::*;
     use crate::geometry::aabb::AABB;
+    use crate::geometry::subdivision::Scheme;
     use crate::material::specular::Specular;
     use lazy_static::lazy_static;
 
@@ -263,21 +823,392 @@ mod tests {
     }
     #[test]
     fn test_back_hit() {
-        assert!(TRI
+        let r = Ray::new(Vec3::new(1, 1, 2), Vec3::new(0, 0, -1));
+        let hit = TRI.hit(r, 0.0, std::f32::MAX).unwrap();
+        assert_eq!(hit.t, 2.0);
+        assert!(!hit.front_face);
+        assert_eq!(hit.geometric_normal, Vec3::new(0, 0, -1));
+        let front = TRI
             .hit(
-                Ray::new(Vec3::new(1, 1, 2), Vec3::new(0, 0, -1)),
+                Ray::new(Vec3::new(1, 1, -2), Vec3::new(0, 0, 1)),
                 0.0,
                 std::f32::MAX,
             )
-            .is_none());
+            .unwrap();
+        assert!(front.front_face);
+
+        let culled = Triangle::new(
+            TRI.v0.clone(),
+            TRI.v1.clone(),
+            TRI.v2.clone(),
+            TRI.material.clone(),
+        )
+        .with_culling(true);
+        assert!(culled.hit(r, 0.0, std::f32::MAX).is_none());
+    }
+    #[test]
+    fn test_shared_edges() {
+        // a square split along its diagonal, rays through the diagonal have
+        // to hit one of the two halves
+        let (a, b, c, d) = (
+            Vec3::new(0, 0, 0),
+            Vec3::new(1, 0, 0),
+            Vec3::new(1, 1, 0),
+            Vec3::new(0, 1, 0),
+        );
+        for i in 0..=100 {
+            let x = i as f32 / 100.0;
+            for dir in &[
+                Vec3::new(0, 0, -1),
+                Vec3::new(0.3, -0.1, -1.0),
+                Vec3::new(-1, 0.7, 0.2),
+            ] {
+                let r = Ray::new(Vec3::new(x, x, 0.0) - *dir * 3.0, *dir);
+                let hits = [
+                    intersect(a, b, c, r, 0.0, 10.0, false),
+                    intersect(a, c, d, r, 0.0, 10.0, false),
+                ];
+                assert!(hits.iter().any(Option::is_some), "{} {:?}", x, dir);
+            }
+        }
+    }
+
+    #[test]
+    fn test_shared_vertex() {
+        // a fan of triangles around the origin, at irregular angles so the
+        // projected edges don't line up with any axis
+        let ring: Vec<Vec3> = (0..7)
+            .map(|i| {
+                let angle = i as f32 * 0.9 + (i * i) as f32 * 0.01;
+                Vec3::new(angle.cos(), angle.sin(), 0.1 * (i % 2) as f32)
+            })
+            .collect();
+        let center = Vec3::new(0, 0, 0);
+        for dir in &[
+            Vec3::new(0, 0, -1),
+            Vec3::new(0.1, 0.2, -1.0),
+            Vec3::new(-0.3, 0.05, -0.5),
+        ] {
+            let r = Ray::new(center - *dir, *dir);
+            let hit = (0..ring.len()).any(|i| {
+                let next = ring[(i + 1) % ring.len()];
+                intersect(center, ring[i], next, r, 0.0, 10.0, false).is_some()
+            });
+            assert!(hit, "{:?}", dir);
+        }
     }
+
     #[test]
     fn test_miss() {
         let hit = TRI.hit(
-            Ray::new(Vec3::new(1, 1, 2), Vec3::new(0, 0, -1)),
+            Ray::new(Vec3::new(5, 1, 2), Vec3::new(0, 0, -1)),
             0.0,
             std::f32::MAX,
         );
         assert!(hit.is_none());
     }
+
+    fn cube() -> MeshData {
+        let mut data = MeshData::default();
+        for i in 0..8 {
+            data.positions
+                .push(Vec3::new(i & 1, (i >> 1) & 1, (i >> 2) & 1));
+        }
+        #[rustfmt::skip]
+        let faces = [
+            0, 2, 3, 1, 4, 5, 7, 6, 0, 1, 5, 4,
+            2, 6, 7, 3, 0, 4, 6, 2, 1, 3, 7, 5,
+        ];
+        for f in faces.chunks(4) {
+            data.indices
+                .extend_from_slice(&[f[0], f[1], f[2], f[0], f[2], f[3]]);
+        }
+        data
+    }
+
+    #[test]
+    fn test_generate_normals() {
+        let mut smooth = cube();
+        smooth.generate_normals(Shading::Smooth);
+        assert_eq!(smooth.positions.len(), 8);
+        let corner = smooth.positions.iter().position(|&p| p == Vec3::from(1));
+        assert!(
+            (smooth.normals[corner.unwrap()] - Vec3::from(1).normalize()).len() < 1e-5
+        );
+
+        for &shading in &[Shading::Crease(30.0), Shading::Flat] {
+            let mut hard = cube();
+            hard.generate_normals(shading);
+            assert_eq!(hard.positions.len(), 24);
+            assert_eq!(hard.indices.len(), 36);
+            for t in hard.indices.chunks(3) {
+                let p = |i: usize| hard.positions[t[i] as usize];
+                let face = (p(1) - p(0)).cross(&(p(2) - p(0))).normalize();
+                for &i in t {
+                    assert!((hard.normals[i as usize] - face).len() < 1e-5);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_flattened() {
+        // a tilted square squashed onto the z = 0 plane, its normals can't
+        // be carried through so they come from the squashed shape
+        let data = MeshData {
+            positions: vec![
+                Vec3::new(0, 0, 0),
+                Vec3::new(1, 0, 0),
+                Vec3::new(1, 1, 1),
+                Vec3::new(0, 1, 1),
+            ],
+            normals: vec![Vec3::new(0, -1, 1).normalize(); 4],
+            indices: vec![0, 1, 2, 0, 2, 3],
+            ..MeshData::default()
+        };
+        let flat = data.transformed(Matrix4::scale(Vec3::new(1, 1, 0)));
+        assert!(flat.positions.iter().all(|p| p.z == 0.0));
+        for &n in &flat.normals {
+            assert!((n - Vec3::new(0, 0, 1)).len() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn test_smoothing_groups() {
+        // two triangles folded along the y axis, each in its own group
+        let mut data = MeshData {
+            positions: vec![
+                Vec3::new(0, 0, 0),
+                Vec3::new(0, 1, 0),
+                Vec3::new(1, 0, 1),
+                Vec3::new(-1, 0, 1),
+            ],
+            indices: vec![0, 2, 1, 0, 1, 3],
+            smoothing_groups: vec![1, 2],
+            ..MeshData::default()
+        };
+        let mut merged = data.clone();
+        merged.smoothing_groups = vec![1, 1];
+        merged.generate_normals(Shading::Smooth);
+        assert_eq!(merged.positions.len(), 4);
+        data.generate_normals(Shading::Smooth);
+        assert_eq!(data.positions.len(), 6);
+        assert_eq!(data.smoothing_groups, vec![1, 2]);
+    }
+
+    #[test]
+    fn test_obj_smoothing_groups() {
+        let dir = std::env::temp_dir().join("ray_obj_smoothing");
+        std::fs::create_dir_all(&dir).unwrap();
+        // two quads folded along a shared edge, only the first one smoothed
+        std::fs::write(
+            dir.join("fold.obj"),
+            "v 0 0 0\nv 1 0 0\nv 1 1 1\nv 0 1 1\nv 1 2 0\nv 0 2 0\n\
+             o fold\ns 1\nf 1 2 3 4\ns off\nf 4 3 5 6\n",
+        )
+        .unwrap();
+        let text = std::fs::read_to_string(dir.join("fold.obj")).unwrap();
+        let face = |group| ObjFace { sides: 4, group };
+        assert_eq!(
+            obj_faces(&text, &[]),
+            vec![vec![face(Some(1)), face(Some(0))]]
+        );
+        assert_eq!(
+            obj_faces("v 0 0 0\nf 1 1 1\n", &[]),
+            vec![vec![ObjFace {
+                sides: 3,
+                group: None
+            }]]
+        );
+
+        let material = Arc::new(Specular::new(Vec3::from(1), 0.0));
+        let mesh = Mesh::with_shading(
+            dir.join("fold.obj").to_str().unwrap(),
+            Matrix4::identity(),
+            material,
+            Shading::Smooth,
+        )
+        .unwrap();
+        // the flat half keeps its face normal right up to the fold
+        let hit = mesh
+            .hit(
+                Ray::new(Vec3::new(0.5, 1.1, 5.0), Vec3::new(0, 0, -1)),
+                0.0,
+                std::f32::MAX,
+            )
+            .unwrap();
+        let face = Vec3::new(0, 1, 1).normalize();
+        assert!((hit.normal - face).len() < 1e-5);
+    }
+
+    #[test]
+    fn test_missing_files() {
+        let material: Arc<dyn Material> = Arc::new(Specular::new(Vec3::from(1), 0.0));
+        let ply =
+            Mesh::from_ply("no such file.ply", Matrix4::identity(), material.clone());
+        let stl = Mesh::from_stl("no such file.stl", Matrix4::identity(), material);
+        assert!(ply.is_err() && stl.is_err());
+    }
+
+    #[test]
+    fn test_obj_latin1() {
+        let dir = std::env::temp_dir().join("ray_obj_latin1");
+        std::fs::create_dir_all(&dir).unwrap();
+        // "café" in latin-1 isn't valid utf-8
+        std::fs::write(dir.join("cafe.mtl"), b"# caf\xe9\nnewmtl red\nKd 1 0 0\n")
+            .unwrap();
+        std::fs::write(
+            dir.join("cafe.obj"),
+            b"# caf\xe9\nmtllib cafe.mtl\nv 0 0 0\nv 1 0 0\nv 1 1 0\n\
+              usemtl red\ns 1\nf 1 2 3\n",
+        )
+        .unwrap();
+        let mesh = Mesh::with_materials(
+            dir.join("cafe.obj").to_str().unwrap(),
+            Matrix4::identity(),
+            Arc::new(Specular::new(Vec3::from(1), 0.0)),
+        )
+        .unwrap();
+        let r = Ray::new(Vec3::new(0.8, 0.3, 1.0), Vec3::new(0, 0, -1));
+        let hit = mesh.hit(r, 0.0, std::f32::MAX).unwrap();
+        let (albedo, _) = hit
+            .material
+            .scatter(r, hit.normal, hit.point, hit.u, hit.v)
+            .unwrap();
+        assert_eq!(albedo, Vec3::new(1, 0, 0));
+    }
+
+    #[test]
+    fn test_obj_subdivision() {
+        let dir = std::env::temp_dir().join("ray_obj_subdivision");
+        std::fs::create_dir_all(&dir).unwrap();
+        // a cube from -1 to 1 made of quads
+        std::fs::write(
+            dir.join("cube.obj"),
+            "v -1 -1 -1\nv 1 -1 -1\nv -1 1 -1\nv 1 1 -1\n\
+             v -1 -1 1\nv 1 -1 1\nv -1 1 1\nv 1 1 1\n\
+             f 1 3 4 2\nf 5 6 8 7\nf 1 2 6 5\nf 3 7 8 4\nf 1 5 7 3\nf 2 4 8 6\n",
+        )
+        .unwrap();
+        let material = Arc::new(Specular::new(Vec3::from(1), 0.0));
+        let mesh = Mesh::with_subdivision(
+            dir.join("cube.obj").to_str().unwrap(),
+            Matrix4::identity(),
+            material,
+            &Subdivision::new(Scheme::CatmullClark, 3),
+        )
+        .unwrap();
+        // the rounded cube sits inside the cage, and is smooth enough that
+        // the middle of a face still faces straight out
+        let hit = mesh
+            .hit(
+                Ray::new(Vec3::new(5.0, 0.01, 0.02), Vec3::new(-1, 0, 0)),
+                0.0,
+                std::f32::MAX,
+            )
+            .unwrap();
+        assert!(hit.t > 4.0 && hit.t < 4.5);
+        assert!((hit.normal - Vec3::new(1, 0, 0)).len() < 0.05);
+        // the corners get cut off
+        let diagonal = Ray::new(Vec3::from(5), Vec3::from(-1));
+        let hit = mesh.hit(diagonal, 0.0, std::f32::MAX).unwrap();
+        assert!(hit.point.x < 0.9);
+    }
+
+    #[test]
+    fn test_obj_materials() {
+        let dir = std::env::temp_dir().join("ray_obj_materials");
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(
+            dir.join("parts.mtl"),
+            "newmtl glass\nKd 0.1 0.1 0.1\nd 0.5\nNi 1.3\n\
+             newmtl lamp\nKd 0 0 0\nKe 2 2 2\n\
+             newmtl painted\nKd 0.5 0.25 1\nmap_Kd white.png\n",
+        )
+        .unwrap();
+        image::RgbImage::from_pixel(2, 2, image::Rgb([255, 255, 255]))
+            .save(dir.join("white.png"))
+            .unwrap();
+        std::fs::write(
+            dir.join("parts.obj"),
+            "mtllib parts.mtl\n\
+             v 0 0 0\nv 1 0 0\nv 1 1 0\nv 5 0 0\nv 6 0 0\nv 6 1 0\n\
+             v 10 0 0\nv 11 0 0\nv 11 1 0\nv 15 0 0\nv 16 0 0\nv 16 1 0\n\
+             v 20 0 0\nv 21 0 0\nv 21 1 0\n\
+             vt 0 0\nvt 1 0\nvt 1 1\n\
+             o mixed\nf 7/1 8/2 9/3\nf 10 11 12\n\
+             o glass\nusemtl glass\nf 1/1 2/2 3/3\n\
+             o lamp\nusemtl lamp\nf 4 5 6\n\
+             o painted\nusemtl painted\nf 13/1 14/2 15/3\n",
+        )
+        .unwrap();
+        let fallback = Arc::new(Specular::new(Vec3::from(1), 0.0));
+        let mesh = Mesh::with_materials(
+            dir.join("parts.obj").to_str().unwrap(),
+            Matrix4::identity(),
+            fallback,
+        )
+        .unwrap();
+        let down = |x, z| Ray::new(Vec3::new(x, 0.3, z), Vec3::new(0, 0, -1));
+        let glass = mesh.hit(down(0.8, 1.0), 0.0, 1.5).unwrap();
+        assert!((glass.u - 0.8).abs() < 1e-5 && (glass.v - 0.3).abs() < 1e-5);
+        // rays that get through bend by the index of refraction
+        let slanted = Ray::new(Vec3::new(-0.2, 0.3, 1.0), Vec3::new(1, 0, -1));
+        let refracted = (0..1000)
+            .filter_map(|_| {
+                glass.material.scatter(
+                    slanted,
+                    glass.normal,
+                    glass.point,
+                    glass.u,
+                    glass.v,
+                )
+            })
+            .map(|(_, scattered)| scattered.dir.normalize())
+            .find(|dir| dir.z < 0.0)
+            .unwrap();
+        assert!((refracted.x - 0.5f32.sqrt() / 1.3).abs() < 1e-4);
+        let lamp = mesh.hit(down(5.8, 1.0), 0.0, std::f32::MAX).unwrap();
+        assert_eq!(lamp.material.emit(0.0, 0.0, lamp.point), Vec3::from(2));
+        // uvs from a model where only some faces have them aren't usable
+        let mixed = mesh.hit(down(10.8, 1.0), 0.0, std::f32::MAX).unwrap();
+        assert_eq!((mixed.u, mixed.v), (0.0, 0.0));
+        // and with no material it falls back to the mirror
+        let (_, reflected) = mixed
+            .material
+            .scatter(down(10.8, 1.0), mixed.normal, mixed.point, 0.0, 0.0)
+            .unwrap();
+        assert!((reflected.dir - Vec3::new(0, 0, 1)).len() < 1e-5);
+        // the diffuse color tints the texture
+        let painted = mesh.hit(down(20.8, 1.0), 0.0, std::f32::MAX).unwrap();
+        let (albedo, _) = painted
+            .material
+            .scatter(
+                down(20.8, 1.0),
+                painted.normal,
+                painted.point,
+                painted.u,
+                painted.v,
+            )
+            .unwrap();
+        assert_eq!(albedo, Vec3::new(0.5, 0.25, 1.0));
+
+        // a texture that's not there is an error rather than a warning
+        std::fs::write(
+            dir.join("missing.mtl"),
+            "newmtl gone\nKd 1 1 1\nmap_Kd gone.png\n",
+        )
+        .unwrap();
+        std::fs::write(
+            dir.join("missing.obj"),
+            "mtllib missing.mtl\nv 0 0 0\nv 1 0 0\nv 1 1 0\nusemtl gone\nf 1 2 3\n",
+        )
+        .unwrap();
+        let missing = Mesh::with_materials(
+            dir.join("missing.obj").to_str().unwrap(),
+            Matrix4::identity(),
+            Arc::new(Specular::new(Vec3::from(1), 0.0)),
+        );
+        assert!(missing.is_err());
+    }
 }
@@ -1,15 +1,38 @@
 pub mod aabb;
+pub mod capsule;
+pub mod cone;
+pub mod csg;
+pub mod curve;
+pub mod cylinder;
+pub mod disk;
+pub mod displacement;
+pub mod heightfield;
+pub mod instance;
 pub mod mesh;
+pub mod plane;
+pub mod ply;
 pub mod prism;
+pub mod quad;
 pub mod rect;
+pub mod sdf;
 pub mod sphere;
+pub mod stl;
+pub mod strands;
+pub mod subdivision;
+pub mod torus;
 pub mod transform;
+pub mod triangle_mesh;
 pub mod volume;
+pub mod voxel;
 
 use crate::material::Material;
 use crate::ray::Ray;
+use crate::scene::Color;
 use crate::vec3::Vec3;
 use aabb::AABB;
+use std::f32::consts::PI;
+use std::sync::Arc;
+use volume::Segment;
 
 /// The relevant geometric information for a ray collision with an object
 #[derive(Clone)]
@@ -18,11 +41,102 @@ pub struct HitRecord<'a> {
     pub u: f32,
     pub v: f32,
     pub point: Vec3,
+    /// Shading normal on the outside of the surface, interpolated from the
+    /// vertices on meshes
     pub normal: Vec3,
+    /// The true normal of the surface, on the same side as `normal`
+    pub geometric_normal: Vec3,
+    /// Whether the ray hit the outside of the surface rather than coming
+    /// from behind or inside it
+    pub front_face: bool,
+    /// The direction along the surface that fibers like curves run in, zero
+    /// for everything else
+    pub tangent: Vec3,
+    /// Tint from per vertex colors, white for everything else
+    pub color: Color,
     pub material: &'a dyn Material,
 }
 
 pub trait Hitable: std::fmt::Debug + Send + Sync {
     fn hit(&self, r: Ray, t_min: f32, t_max: f32) -> Option<HitRecord>;
     fn get_bb(&self) -> AABB;
+    /// Whether there's a medium like a `Volume` anywhere in this, so
+    /// everything else can skip looking for them
+    fn has_media(&self) -> bool {
+        false
+    }
+    /// Adds the stretches of the ray between `t_min` and `t_max` that are
+    /// inside media. Media don't get hit like surfaces, rays get tracked
+    /// through them up to the closest surface instead.
+    fn media<'a>(
+        &'a self,
+        _r: Ray,
+        _t_min: f32,
+        _t_max: f32,
+        _found: &mut Vec<Segment<'a>>,
+    ) {
+    }
+    /// A direction from `origin` towards a random point on this, for lights
+    /// that get sampled directly. Only spheres, quads and rects have one.
+    fn sample_towards(&self, _origin: Vec3) -> Option<Vec3> {
+        None
+    }
+    /// How likely `sample_towards` is to pick `dir` from `origin`, per
+    /// steradian
+    fn pdf_towards(&self, _origin: Vec3, _dir: Vec3) -> f32 {
+        0.0
+    }
+}
+
+/// Lets one object be shared between several parents, see `Instance`
+impl<T: Hitable + ?Sized> Hitable for Arc<T> {
+    fn hit(&self, r: Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+        self.as_ref().hit(r, t_min, t_max)
+    }
+    fn get_bb(&self) -> AABB {
+        self.as_ref().get_bb()
+    }
+    fn has_media(&self) -> bool {
+        self.as_ref().has_media()
+    }
+    fn media<'a>(&'a self, r: Ray, t_min: f32, t_max: f32, found: &mut Vec<Segment<'a>>) {
+        self.as_ref().media(r, t_min, t_max, found)
+    }
+    fn sample_towards(&self, origin: Vec3) -> Option<Vec3> {
+        self.as_ref().sample_towards(origin)
+    }
+    fn pdf_towards(&self, origin: Vec3, dir: Vec3) -> f32 {
+        self.as_ref().pdf_towards(origin, dir)
+    }
+}
+
+/// Real roots of a t^2 + b t + c = 0, smallest first
+pub(crate) fn solve_quadratic(a: f32, b: f32, c: f32) -> Option<(f32, f32)> {
+    let discriminant = b * b - 4.0 * a * c;
+    if discriminant < 0.0 || a == 0.0 {
+        return None;
+    }
+    // picking the sign of the root that matches b avoids cancellation
+    let q = -0.5 * (b + b.signum() * discriminant.sqrt());
+    if q == 0.0 {
+        return Some((0.0, 0.0));
+    }
+    let (t0, t1) = (q / a, c / q);
+    Some((t0.min(t1), t0.max(t1)))
+}
+
+/// Angle around the y axis mapped to 0..1, the same way as `Sphere::get_uv`
+pub(crate) fn azimuth(p: Vec3) -> f32 {
+    1.0 - (p.z.atan2(p.x) + PI) / (2.0 * PI)
+}
+
+/// Two unit vectors perpendicular to `n` and to each other
+pub(crate) fn tangents(n: Vec3) -> (Vec3, Vec3) {
+    let other = if n.x.abs() > 0.9 {
+        Vec3::new(0, 1, 0)
+    } else {
+        Vec3::new(1, 0, 0)
+    };
+    let a = n.cross(&other).normalize();
+    (a, n.cross(&a).normalize())
 }
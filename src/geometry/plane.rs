@@ -0,0 +1,109 @@
+use super::aabb::AABB;
+use super::{tangents, HitRecord, Hitable};
+use crate::axis::Axis;
+use crate::material::Material;
+use crate::ray::Ray;
+use crate::scene::Color;
+use crate::vec3::Vec3;
+use std::sync::Arc;
+
+#[macro_export]
+macro_rules! plane {
+    ($point:expr, $normal:expr, $mat:expr) => {
+        Box::new(Plane::new($point.into(), $normal.into(), $mat))
+    };
+}
+
+/// An infinite plane through `point`. The texture coordinates are distances
+/// along the plane so textures tile across it.
+#[derive(Debug, Clone)]
+pub struct Plane {
+    point: Vec3,
+    normal: Vec3,
+    tangent: Vec3,
+    bitangent: Vec3,
+    material: Arc<dyn Material>,
+}
+
+impl Plane {
+    pub fn new(point: Vec3, normal: Vec3, material: Arc<dyn Material>) -> Self {
+        let normal = normal.normalize();
+        let (tangent, bitangent) = tangents(normal);
+        Plane {
+            point,
+            normal,
+            tangent,
+            bitangent,
+            material,
+        }
+    }
+}
+
+impl Hitable for Plane {
+    fn hit(&self, r: Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+        let t = (self.point - r.origin).dot(&self.normal) / r.dir.dot(&self.normal);
+        // also rejects the NaN from rays parallel to the plane
+        if !(t > t_min && t < t_max) {
+            return None;
+        }
+        let point = r.point_at_param(t);
+        let offset = point - self.point;
+        Some(HitRecord {
+            t,
+            u: offset.dot(&self.tangent),
+            v: offset.dot(&self.bitangent),
+            point,
+            normal: self.normal,
+            geometric_normal: self.normal,
+            front_face: r.dir.dot(&self.normal) < 0.0,
+            tangent: Vec3::zero(),
+            color: Color::from(1),
+            material: self.material.as_ref(),
+        })
+    }
+
+    /// Unbounded except along the normal when the plane lines up with an axis
+    fn get_bb(&self) -> AABB {
+        let unbounded = AABB::default();
+        for &axis in &[Axis::X, Axis::Y, Axis::Z] {
+            if self.normal.get_axis(axis).abs() == 1.0 {
+                let k = self.point.get_axis(axis);
+                return AABB::new(
+                    unbounded.min.set_axis(axis, k - 0.0001),
+                    unbounded.max.set_axis(axis, k + 0.0001),
+                );
+            }
+        }
+        unbounded
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometry::transform::{Rotate, Translate};
+    use crate::material::specular::Specular;
+
+    #[test]
+    fn test_hit() {
+        let material = Arc::new(Specular::new(Color::from(1), 0.0));
+        let floor = Plane::new(Vec3::new(0, -1, 0), Vec3::new(0, 2, 0), material);
+        assert_eq!(floor.get_bb().min.y, -1.0001);
+        let r = Ray::new(Vec3::new(100, 3, -50), Vec3::new(1, -2, 0));
+        let hit = floor.hit(r, 0.0, std::f32::MAX).unwrap();
+        assert_eq!(hit.t, 2.0);
+        assert_eq!(hit.point, Vec3::new(102, -1, -50));
+        assert!(hit.front_face);
+        let along = Ray::new(Vec3::new(0, 0, 0), Vec3::new(1, 0, 0));
+        assert!(floor.hit(along, 0.0, std::f32::MAX).is_none());
+
+        // tipped over into a wall and moved, it still gets hit through the
+        // unbounded box
+        let wall = Translate::new(Rotate::new(floor, Axis::Z, 90.0), Vec3::new(5, 0, 0));
+        let r = Ray::new(Vec3::new(10, 0.5, 3.0), Vec3::new(-1, 0, 0));
+        let hit = wall.hit(r, 0.0, std::f32::MAX).unwrap();
+        assert!((hit.t - 4.0).abs() < 1e-4);
+        assert!((hit.normal - Vec3::new(-1, 0, 0)).len() < 1e-5);
+        assert!(!hit.front_face);
+    }
+}
@@ -0,0 +1,311 @@
+use super::mesh::MeshData;
+use crate::vec3::Vec3;
+use std::io::{self, Error, ErrorKind};
+
+/// Loads ascii or binary (either endianness) PLY files. Vertices can have
+/// normals, colors and texture coordinates, faces can be any polygon and are
+/// split into triangles. Other elements are skipped.
+pub fn load(filename: &str) -> io::Result<MeshData> {
+    parse(&std::fs::read(filename)?)
+}
+
+fn invalid(msg: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, format!("ply: {}", msg))
+}
+
+#[derive(Copy, Clone, Debug)]
+enum Scalar {
+    I8,
+    U8,
+    I16,
+    U16,
+    I32,
+    U32,
+    F32,
+    F64,
+}
+
+impl Scalar {
+    fn parse(name: &str) -> io::Result<Self> {
+        use Scalar::*;
+        Ok(match name {
+            "char" | "int8" => I8,
+            "uchar" | "uint8" => U8,
+            "short" | "int16" => I16,
+            "ushort" | "uint16" => U16,
+            "int" | "int32" => I32,
+            "uint" | "uint32" => U32,
+            "float" | "float32" => F32,
+            "double" | "float64" => F64,
+            _ => return Err(invalid(&format!("unknown type {}", name))),
+        })
+    }
+
+    fn size(self) -> usize {
+        use Scalar::*;
+        match self {
+            I8 | U8 => 1,
+            I16 | U16 => 2,
+            I32 | U32 | F32 => 4,
+            F64 => 8,
+        }
+    }
+
+    /// Integer colors use the full range of their type
+    fn color_scale(self) -> f64 {
+        match self {
+            Scalar::U8 => 255.0,
+            Scalar::U16 => 65535.0,
+            _ => 1.0,
+        }
+    }
+}
+
+#[derive(Debug)]
+enum Property {
+    Scalar(String, Scalar),
+    /// Name, type of the length, type of the items
+    List(String, Scalar, Scalar),
+}
+
+#[derive(Debug)]
+struct Element {
+    name: String,
+    count: usize,
+    properties: Vec<Property>,
+}
+
+enum Body<'a> {
+    Ascii(std::str::SplitWhitespace<'a>),
+    Binary { data: &'a [u8], big_endian: bool },
+}
+
+impl<'a> Body<'a> {
+    fn read(&mut self, kind: Scalar) -> io::Result<f64> {
+        match self {
+            Body::Ascii(tokens) => tokens
+                .next()
+                .ok_or_else(|| invalid("unexpected end of file"))?
+                .parse()
+                .map_err(|_| invalid("bad number")),
+            Body::Binary { data, big_endian } => {
+                if data.len() < kind.size() {
+                    return Err(invalid("unexpected end of file"));
+                }
+                let (bytes, rest) = data.split_at(kind.size());
+                *data = rest;
+                let mut b = [0u8; 8];
+                b[..bytes.len()].copy_from_slice(bytes);
+                if *big_endian {
+                    b[..bytes.len()].reverse();
+                }
+                use Scalar::*;
+                Ok(match kind {
+                    I8 => f64::from(b[0] as i8),
+                    U8 => f64::from(b[0]),
+                    I16 => f64::from(i16::from_le_bytes([b[0], b[1]])),
+                    U16 => f64::from(u16::from_le_bytes([b[0], b[1]])),
+                    I32 => f64::from(i32::from_le_bytes([b[0], b[1], b[2], b[3]])),
+                    U32 => f64::from(u32::from_le_bytes([b[0], b[1], b[2], b[3]])),
+                    F32 => f64::from(f32::from_le_bytes([b[0], b[1], b[2], b[3]])),
+                    F64 => f64::from_le_bytes(b),
+                })
+            }
+        }
+    }
+}
+
+pub fn parse(bytes: &[u8]) -> io::Result<MeshData> {
+    let marker = b"end_header";
+    let header_end = bytes
+        .windows(marker.len())
+        .position(|w| w == marker)
+        .ok_or_else(|| invalid("missing end_header"))?;
+    // the body starts after the newline following end_header
+    let body_start = bytes[header_end..]
+        .iter()
+        .position(|&b| b == b'\n')
+        .map_or(bytes.len(), |i| header_end + i + 1);
+    let header = std::str::from_utf8(&bytes[..header_end])
+        .map_err(|_| invalid("header isn't valid text"))?;
+    let mut lines = header.lines().map(str::trim);
+    if lines.next() != Some("ply") {
+        return Err(invalid("missing magic number"));
+    }
+    let mut format = None;
+    let mut elements: Vec<Element> = Vec::new();
+    for line in lines {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match words.as_slice() {
+            ["format", kind, _] => format = Some(kind.to_string()),
+            ["element", name, count] => elements.push(Element {
+                name: name.to_string(),
+                count: count.parse().map_err(|_| invalid("bad element count"))?,
+                properties: Vec::new(),
+            }),
+            ["property", "list", len, item, name] => elements
+                .last_mut()
+                .ok_or_else(|| invalid("property before element"))?
+                .properties
+                .push(Property::List(
+                    name.to_string(),
+                    Scalar::parse(len)?,
+                    Scalar::parse(item)?,
+                )),
+            ["property", kind, name] => elements
+                .last_mut()
+                .ok_or_else(|| invalid("property before element"))?
+                .properties
+                .push(Property::Scalar(name.to_string(), Scalar::parse(kind)?)),
+            _ => {} // comments, obj_info, and blank lines
+        }
+    }
+    let data = &bytes[body_start..];
+    let mut body = match format.as_ref().map(String::as_str) {
+        Some("ascii") => Body::Ascii(
+            std::str::from_utf8(data)
+                .map_err(|_| invalid("body isn't valid text"))?
+                .split_whitespace(),
+        ),
+        Some("binary_little_endian") => Body::Binary {
+            data,
+            big_endian: false,
+        },
+        Some("binary_big_endian") => Body::Binary {
+            data,
+            big_endian: true,
+        },
+        _ => return Err(invalid("unknown format")),
+    };
+
+    let mut mesh = MeshData::default();
+    for element in &elements {
+        for _ in 0..element.count {
+            let mut values = Vec::new();
+            let mut list = Vec::new();
+            for property in &element.properties {
+                match property {
+                    Property::Scalar(name, kind) => {
+                        values.push((name.as_str(), body.read(*kind)?, *kind))
+                    }
+                    Property::List(name, len_kind, item_kind) => {
+                        let len = body.read(*len_kind)? as usize;
+                        let items = (0..len)
+                            .map(|_| body.read(*item_kind))
+                            .collect::<io::Result<Vec<f64>>>()?;
+                        if name == "vertex_indices" || name == "vertex_index" {
+                            list = items;
+                        }
+                    }
+                }
+            }
+            match element.name.as_str() {
+                "vertex" => add_vertex(&mut mesh, &values),
+                "face" => {
+                    // fan triangulation for polygons with more than 3 sides
+                    for i in 1..list.len().saturating_sub(1) {
+                        mesh.indices.push(list[0] as u32);
+                        mesh.indices.push(list[i] as u32);
+                        mesh.indices.push(list[i + 1] as u32);
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+    if mesh
+        .indices
+        .iter()
+        .any(|&i| i as usize >= mesh.positions.len())
+    {
+        return Err(invalid("face index out of range"));
+    }
+    Ok(mesh)
+}
+
+fn add_vertex(mesh: &mut MeshData, values: &[(&str, f64, Scalar)]) {
+    let get = |names: &[&str]| {
+        values
+            .iter()
+            .find(|(n, _, _)| names.contains(n))
+            .map(|&(_, v, kind)| (v as f32, kind))
+    };
+    let vec = |a: &[&str], b: &[&str], c: &[&str]| match (get(a), get(b), get(c)) {
+        (Some(x), Some(y), Some(z)) => Some((Vec3::new(x.0, y.0, z.0), x.1)),
+        _ => None,
+    };
+    let position = vec(&["x"], &["y"], &["z"]).map_or(Vec3::zero(), |p| p.0);
+    mesh.positions.push(position);
+    if let Some((normal, _)) = vec(&["nx"], &["ny"], &["nz"]) {
+        mesh.normals.push(normal);
+    }
+    if let Some((color, kind)) = vec(
+        &["red", "r", "diffuse_red"],
+        &["green", "g", "diffuse_green"],
+        &["blue", "b", "diffuse_blue"],
+    ) {
+        mesh.colors.push(color / kind.color_scale() as f32);
+    }
+    if let (Some(u), Some(v)) = (
+        get(&["u", "s", "texture_u", "texture_s"]),
+        get(&["v", "t", "texture_v", "texture_t"]),
+    ) {
+        mesh.texcoords.push(Vec3::new(u.0, v.0, 0.0));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scene::Color;
+
+    #[test]
+    fn test_ascii() {
+        let mesh = parse(
+            b"ply\nformat ascii 1.0\ncomment a colored quad\n\
+              element vertex 4\nproperty float x\nproperty float y\nproperty float z\n\
+              property uchar red\nproperty uchar green\nproperty uchar blue\n\
+              element face 1\nproperty list uchar int vertex_indices\nend_header\n\
+              0 0 0 255 0 0\n1 0 0 0 255 0\n1 1 0 0 0 255\n0 1 0 255 255 255\n\
+              4 0 1 2 3\n",
+        )
+        .unwrap();
+        assert_eq!(mesh.positions.len(), 4);
+        assert_eq!(mesh.positions[2], Vec3::new(1, 1, 0));
+        assert_eq!(mesh.colors[1], Color::new(0, 1, 0));
+        assert!(mesh.normals.is_empty());
+        assert_eq!(mesh.indices, vec![0, 1, 2, 0, 2, 3]);
+    }
+
+    #[test]
+    fn test_binary() {
+        let mut bytes = b"ply\nformat binary_big_endian 1.0\n\
+              element vertex 3\nproperty float x\nproperty float y\nproperty float z\n\
+              property float nx\nproperty float ny\nproperty float nz\n\
+              element face 1\nproperty list uchar uint vertex_index\nend_header\n"
+            .to_vec();
+        for p in &[[0.0f32, 0.0, 0.0], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0]] {
+            for v in p.iter().chain(&[0.0, 0.0, 1.0]) {
+                bytes.extend_from_slice(&v.to_be_bytes());
+            }
+        }
+        bytes.push(3);
+        for i in 0u32..3 {
+            bytes.extend_from_slice(&i.to_be_bytes());
+        }
+        let mesh = parse(&bytes).unwrap();
+        assert_eq!(mesh.positions[1], Vec3::new(1, 0, 0));
+        assert_eq!(mesh.normals[2], Vec3::new(0, 0, 1));
+        assert!(mesh.colors.is_empty());
+        assert_eq!(mesh.indices, vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn test_truncated() {
+        assert!(parse(
+            b"ply\nformat ascii 1.0\nelement vertex 2\nproperty float x\nend_header\n1\n"
+        )
+        .is_err());
+        assert!(parse(b"not a ply file").is_err());
+    }
+}
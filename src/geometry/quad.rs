@@ -0,0 +1,155 @@
+use super::aabb::AABB;
+use super::{HitRecord, Hitable};
+use crate::material::Material;
+use crate::ray::Ray;
+use crate::scene::Color;
+use crate::vec3::Vec3;
+use rand::random;
+use std::sync::Arc;
+
+#[macro_export]
+macro_rules! quad {
+    ($corner:expr, $u:expr, $v:expr, $mat:expr) => {
+        Box::new(Quad::new($corner.into(), $u.into(), $v.into(), $mat))
+    };
+}
+
+/// A parallelogram spanned by the edges `u` and `v` from `corner`, facing
+/// towards u x v. Unlike `Rect` it can be at any angle.
+#[derive(Debug, Clone)]
+pub struct Quad {
+    corner: Vec3,
+    u: Vec3,
+    v: Vec3,
+    normal: Vec3,
+    /// u x v scaled so dotting it with a cross product against one edge gives
+    /// the coordinate along the other
+    w: Vec3,
+    material: Arc<dyn Material>,
+}
+
+impl Quad {
+    pub fn new(corner: Vec3, u: Vec3, v: Vec3, material: Arc<dyn Material>) -> Self {
+        let n = u.cross(&v);
+        Quad {
+            corner,
+            u,
+            v,
+            normal: n.normalize(),
+            w: n / n.dot(&n),
+            material,
+        }
+    }
+}
+
+impl Hitable for Quad {
+    fn hit(&self, r: Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+        let t = (self.corner - r.origin).dot(&self.normal) / r.dir.dot(&self.normal);
+        // also rejects the NaN from rays parallel to the quad
+        if !(t > t_min && t < t_max) {
+            return None;
+        }
+        let point = r.point_at_param(t);
+        let offset = point - self.corner;
+        let a = self.w.dot(&offset.cross(&self.v));
+        let b = self.w.dot(&self.u.cross(&offset));
+        if a < 0.0 || a > 1.0 || b < 0.0 || b > 1.0 {
+            return None;
+        }
+        Some(HitRecord {
+            t,
+            u: a,
+            v: b,
+            point,
+            normal: self.normal,
+            geometric_normal: self.normal,
+            front_face: r.dir.dot(&self.normal) < 0.0,
+            tangent: Vec3::zero(),
+            color: Color::from(1),
+            material: self.material.as_ref(),
+        })
+    }
+
+    fn get_bb(&self) -> AABB {
+        let corners = [self.u, self.v, self.u + self.v];
+        let bb = corners
+            .iter()
+            .fold(AABB::new(self.corner, self.corner), |bb, &c| {
+                let p = self.corner + c;
+                bb.combine(&AABB::new(p, p))
+            });
+        // pad it so quads lined up with an axis don't get a flat box
+        AABB::new(bb.min - Vec3::from(0.0001), bb.max + Vec3::from(0.0001))
+    }
+    /// Evenly over its area
+    fn sample_towards(&self, origin: Vec3) -> Option<Vec3> {
+        let point = self.corner + self.u * random::<f32>() + self.v * random::<f32>();
+        Some((point - origin).normalize())
+    }
+    fn pdf_towards(&self, origin: Vec3, dir: Vec3) -> f32 {
+        let dir = dir.normalize();
+        match self.hit(Ray::new(origin, dir), 0.0, std::f32::MAX) {
+            Some(hit) => {
+                let area = self.u.cross(&self.v).len();
+                hit.t * hit.t / (dir.dot(&self.normal).abs() * area)
+            }
+            None => 0.0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::specular::Specular;
+
+    #[test]
+    fn test_hit() {
+        let material = Arc::new(Specular::new(Color::from(1), 0.0));
+        // a sloped, sheared parallelogram
+        let quad = Quad::new(
+            Vec3::new(1, 0, 0),
+            Vec3::new(2, 0, 2),
+            Vec3::new(1, 2, 0),
+            material,
+        );
+        let target =
+            Vec3::new(1, 0, 0) + Vec3::new(2, 0, 2) * 0.25 + Vec3::new(1, 2, 0) * 0.75;
+        let r = Ray::new(target - quad.normal * 3.0, quad.normal);
+        let hit = quad.hit(r, 0.0, std::f32::MAX).unwrap();
+        assert!((hit.t - 3.0).abs() < 1e-5);
+        assert!((hit.u - 0.25).abs() < 1e-5 && (hit.v - 0.75).abs() < 1e-5);
+        assert!(!hit.front_face);
+        // just outside the v edge
+        let outside = target + Vec3::new(1, 2, 0) * 0.3;
+        let r = Ray::new(outside - quad.normal * 3.0, quad.normal);
+        assert!(quad.hit(r, 0.0, std::f32::MAX).is_none());
+    }
+
+    #[test]
+    fn test_sample_towards() {
+        let material = Arc::new(Specular::new(Color::from(1), 0.0));
+        let quad = Quad::new(
+            Vec3::new(-1, 1, -1),
+            Vec3::new(2, 0, 0),
+            Vec3::new(1, 0, 2),
+            material,
+        );
+        let origin = Vec3::new(0.3, 0.0, 0.2);
+        for _ in 0..100 {
+            let dir = quad.sample_towards(origin).unwrap();
+            assert!(quad
+                .hit(Ray::new(origin, dir), 0.0, std::f32::MAX)
+                .is_some());
+        }
+        // the pdf over every direction adds up to 1
+        let samples = 50_000;
+        let total = (0..samples)
+            .map(|_| quad.pdf_towards(origin, Vec3::rand_in_unit_sphere()))
+            .sum::<f32>()
+            * 4.0
+            * std::f32::consts::PI
+            / samples as f32;
+        assert!((total - 1.0).abs() < 0.05, "{}", total);
+    }
+}
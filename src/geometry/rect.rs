@@ -3,7 +3,9 @@ use super::{HitRecord, Hitable};
 use crate::axis::Axis;
 use crate::material::Material;
 use crate::ray::Ray;
+use crate::scene::Color;
 use crate::vec3::Vec3;
+use rand::random;
 use std::sync::Arc;
 
 #[macro_export]
@@ -104,12 +106,17 @@ impl Hitable for Rect {
         if a < self.a0 || a > self.a1 || b < self.b0 || b > self.b1 {
             return None;
         }
+        let normal = Vec3::zero().set_axis(other_axis, 1.0);
         Some(HitRecord {
             t,
             u: (a - self.a0) / (self.a1 - self.a0),
             v: (b - self.b0) / (self.b1 - self.b0),
             point: r.point_at_param(t),
-            normal: Vec3::zero().set_axis(other_axis, 1.0),
+            normal,
+            geometric_normal: normal,
+            front_face: r.dir.dot(&normal) < 0.0,
+            tangent: Vec3::zero(),
+            color: Color::from(1),
             material: self.material.as_ref(),
         })
     }
@@ -127,4 +134,23 @@ impl Hitable for Rect {
                 .set_axis(other_axis, self.k + 0.0001),
         )
     }
+    /// Evenly over its area
+    fn sample_towards(&self, origin: Vec3) -> Option<Vec3> {
+        let other_axis = Axis::other(self.axis_a, self.axis_b);
+        let point = Vec3::zero()
+            .set_axis(self.axis_a, self.a0 + random::<f32>() * (self.a1 - self.a0))
+            .set_axis(self.axis_b, self.b0 + random::<f32>() * (self.b1 - self.b0))
+            .set_axis(other_axis, self.k);
+        Some((point - origin).normalize())
+    }
+    fn pdf_towards(&self, origin: Vec3, dir: Vec3) -> f32 {
+        let dir = dir.normalize();
+        match self.hit(Ray::new(origin, dir), 0.0, std::f32::MAX) {
+            Some(hit) => {
+                let area = (self.a1 - self.a0) * (self.b1 - self.b0);
+                hit.t * hit.t / (dir.dot(&hit.normal).abs() * area)
+            }
+            None => 0.0,
+        }
+    }
 }
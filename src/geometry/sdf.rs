@@ -0,0 +1,378 @@
+use super::aabb::AABB;
+use super::{HitRecord, Hitable};
+use crate::material::Material;
+use crate::ray::Ray;
+use crate::scene::Color;
+use crate::vec3::Vec3;
+use std::fmt;
+use std::sync::Arc;
+
+#[macro_export]
+macro_rules! sdf {
+    ($field:expr, ($min:expr, $max:expr), $mat:expr) => {
+        Box::new(Sdf::new(
+            $field,
+            $crate::geometry::aabb::AABB::new($min.into(), $max.into()),
+            $mat,
+        ))
+    };
+}
+
+/// Gives the distance from a point to the closest surface, negative inside.
+/// Distance estimators like the ones for fractals can return anything that's
+/// never more than the true distance, it just takes more steps.
+pub trait DistanceField: Send + Sync {
+    fn distance(&self, p: Vec3) -> f32;
+}
+
+impl<F: Fn(Vec3) -> f32 + Send + Sync> DistanceField for F {
+    fn distance(&self, p: Vec3) -> f32 {
+        self(p)
+    }
+}
+
+/// Steps past this many and the ray is taken to have missed, which is what
+/// happens to rays that graze a surface for a long way
+const MAX_STEPS: usize = 512;
+
+/// A surface defined by where a distance field is zero, found by sphere
+/// tracing. The field can be anything so the bounding box has to be given,
+/// and the surface outside of it is never found.
+pub struct Sdf<T: DistanceField> {
+    field: T,
+    bb: AABB,
+    /// Surfaces closer than this count as hit
+    epsilon: f32,
+    /// Fraction of the distance to step each time
+    step_scale: f32,
+    material: Arc<dyn Material>,
+}
+
+impl<T: DistanceField> fmt::Debug for Sdf<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Sdf")
+            .field("bb", &self.bb)
+            .field("epsilon", &self.epsilon)
+            .field("step_scale", &self.step_scale)
+            .field("material", &self.material)
+            .finish()
+    }
+}
+
+impl<T: DistanceField> Sdf<T> {
+    pub fn new(field: T, bb: AABB, material: Arc<dyn Material>) -> Self {
+        Sdf {
+            field,
+            bb,
+            epsilon: 0.0001,
+            step_scale: 1.0,
+            material,
+        }
+    }
+
+    /// How close counts as touching the surface, bigger values are faster
+    /// but round off fine detail
+    pub fn with_epsilon(self, epsilon: f32) -> Self {
+        Sdf { epsilon, ..self }
+    }
+
+    /// Takes shorter steps for fields that can overestimate the distance,
+    /// like twisted ones, which would otherwise step through thin parts
+    pub fn with_step_scale(self, step_scale: f32) -> Self {
+        Sdf { step_scale, ..self }
+    }
+
+    /// Where the ray is inside the bounding box, clipped to t_min and t_max
+    fn clip(&self, r: Ray, t_min: f32, t_max: f32) -> Option<(f32, f32)> {
+        let a = (self.bb.min - r.origin) / r.dir;
+        let b = (self.bb.max - r.origin) / r.dir;
+        let near = a.piecewise_min(&b);
+        let far = a.piecewise_max(&b);
+        let start = near.x.max(near.y).max(near.z).max(t_min);
+        let end = far.x.min(far.y).min(far.z).min(t_max);
+        if start <= end {
+            Some((start, end))
+        } else {
+            None
+        }
+    }
+
+    /// The gradient from central differences at the 4 corners of a
+    /// tetrahedron, which only takes 4 samples
+    fn normal(&self, p: Vec3) -> Vec3 {
+        let h = self.epsilon;
+        let corners = [
+            Vec3::new(1, -1, -1),
+            Vec3::new(-1, -1, 1),
+            Vec3::new(-1, 1, -1),
+            Vec3::new(1, 1, 1),
+        ];
+        let gradient = corners
+            .iter()
+            .fold(Vec3::zero(), |n, &k| n + k * self.field.distance(p + k * h));
+        if gradient.len() > 0.0 {
+            gradient.normalize()
+        } else {
+            Vec3::new(0, 1, 0)
+        }
+    }
+}
+
+impl<T: DistanceField> Hitable for Sdf<T> {
+    fn hit(&self, r: Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+        let (mut t, end) = self.clip(r, t_min, t_max)?;
+        // distances are in world units but t is in units of the direction
+        let len = r.dir.len();
+        // a ray leaving a surface starts right on it, so step off it first
+        let mut d = self.field.distance(r.point_at_param(t));
+        let mut steps = 0;
+        while d.abs() < self.epsilon && steps < MAX_STEPS {
+            t += self.epsilon / len;
+            d = self.field.distance(r.point_at_param(t));
+            steps += 1;
+        }
+        // from inside the surface is where the distance turns positive
+        let side = d.signum();
+        while steps < MAX_STEPS && t <= end {
+            let d = side * self.field.distance(r.point_at_param(t));
+            if d < self.epsilon {
+                let point = r.point_at_param(t);
+                let normal = self.normal(point);
+                return Some(HitRecord {
+                    t,
+                    u: 0.0, // arbitrary
+                    v: 0.0, // arbitrary
+                    point,
+                    normal,
+                    geometric_normal: normal,
+                    front_face: r.dir.dot(&normal) < 0.0,
+                    tangent: Vec3::zero(),
+                    color: Color::from(1),
+                    material: self.material.as_ref(),
+                });
+            }
+            t += d * self.step_scale / len;
+            steps += 1;
+        }
+        None
+    }
+
+    fn get_bb(&self) -> AABB {
+        self.bb
+    }
+}
+
+/// A sphere around the origin
+pub fn sphere(radius: f32) -> impl DistanceField {
+    move |p: Vec3| p.len() - radius
+}
+
+/// A box around the origin reaching `half_size` out in each direction
+pub fn cuboid(half_size: Vec3) -> impl DistanceField {
+    move |p: Vec3| {
+        let q = Vec3::new(p.x.abs(), p.y.abs(), p.z.abs()) - half_size;
+        let outside = q.piecewise_max(&Vec3::zero()).len();
+        let inside = q.x.max(q.y).max(q.z).min(0.0);
+        outside + inside
+    }
+}
+
+/// A ring in the xz plane around the origin like `Torus`
+pub fn torus(major: f32, minor: f32) -> impl DistanceField {
+    move |p: Vec3| {
+        let ring = (p.x * p.x + p.z * p.z).sqrt() - major;
+        (ring * ring + p.y * p.y).sqrt() - minor
+    }
+}
+
+/// A capped cylinder around the y axis, centered on the origin
+pub fn cylinder(radius: f32, half_height: f32) -> impl DistanceField {
+    move |p: Vec3| {
+        let radial = (p.x * p.x + p.z * p.z).sqrt() - radius;
+        let vertical = p.y.abs() - half_height;
+        let outside = (radial.max(0.0).powi(2) + vertical.max(0.0).powi(2)).sqrt();
+        outside + radial.max(vertical).min(0.0)
+    }
+}
+
+/// Merges two fields with a fillet of about size `k` where they meet
+pub fn smooth_union<A, B>(a: A, b: B, k: f32) -> impl DistanceField
+where
+    A: DistanceField,
+    B: DistanceField,
+{
+    move |p: Vec3| {
+        let (da, db) = (a.distance(p), b.distance(p));
+        let h = (0.5 + 0.5 * (db - da) / k).max(0.0).min(1.0);
+        db * (1.0 - h) + da * h - k * h * (1.0 - h)
+    }
+}
+
+/// Twists a field around the y axis by `rate` radians per unit of height.
+/// This stretches distances so it needs a step scale below 1 on the `Sdf`.
+pub fn twist<A: DistanceField>(a: A, rate: f32) -> impl DistanceField {
+    move |p: Vec3| {
+        let (sin, cos) = (rate * p.y).sin_cos();
+        a.distance(Vec3::new(cos * p.x - sin * p.z, p.y, sin * p.x + cos * p.z))
+    }
+}
+
+/// Repeats a field forever on a grid with the given spacing. Components that
+/// are zero don't repeat. The field should fit within one cell.
+pub fn repeat<A: DistanceField>(a: A, period: Vec3) -> impl DistanceField {
+    let wrap = |x: f32, period: f32| {
+        if period > 0.0 {
+            x - period * (x / period).round()
+        } else {
+            x
+        }
+    };
+    move |p: Vec3| {
+        a.distance(Vec3::new(
+            wrap(p.x, period.x),
+            wrap(p.y, period.y),
+            wrap(p.z, period.z),
+        ))
+    }
+}
+
+/// Grows a field outwards by `radius`, which rounds off its edges
+pub fn round<A: DistanceField>(a: A, radius: f32) -> impl DistanceField {
+    move |p: Vec3| a.distance(p) - radius
+}
+
+/// The Mandelbulb fractal around the origin, which fits within a radius of
+/// about 1.2. The usual power is 8.
+#[derive(Debug, Clone)]
+pub struct Mandelbulb {
+    pub power: f32,
+    pub iterations: usize,
+}
+
+impl Default for Mandelbulb {
+    fn default() -> Self {
+        Mandelbulb {
+            power: 8.0,
+            iterations: 12,
+        }
+    }
+}
+
+impl DistanceField for Mandelbulb {
+    /// Iterates z -> z^power + p in spherical coordinates, tracking the
+    /// derivative to estimate the distance
+    fn distance(&self, p: Vec3) -> f32 {
+        let mut z = p;
+        let mut dr = 1.0;
+        let mut r = z.len();
+        for _ in 0..self.iterations {
+            if r > 2.0 || r == 0.0 {
+                break;
+            }
+            let theta = (z.z / r).acos() * self.power;
+            let phi = z.y.atan2(z.x) * self.power;
+            dr = r.powf(self.power - 1.0) * self.power * dr + 1.0;
+            let zr = r.powf(self.power);
+            z = Vec3::new(
+                theta.sin() * phi.cos(),
+                theta.sin() * phi.sin(),
+                theta.cos(),
+            ) * zr
+                + p;
+            r = z.len();
+        }
+        if r == 0.0 {
+            return 0.0;
+        }
+        0.5 * r.ln() * r / dr
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::specular::Specular;
+
+    fn material() -> Arc<dyn Material> {
+        Arc::new(Specular::new(Color::from(1), 0.0))
+    }
+
+    fn unit_box() -> AABB {
+        AABB::new(Vec3::from(-2), Vec3::from(2))
+    }
+
+    #[test]
+    fn test_sphere() {
+        let ball = Sdf::new(sphere(1.0), unit_box(), material());
+        let r = Ray::new(Vec3::new(-5.0, 0.3, 0.0), Vec3::new(2, 0, 0));
+        let hit = ball.hit(r, 0.0, std::f32::MAX).unwrap();
+        let x = -(1.0f32 - 0.09).sqrt();
+        assert!((hit.point - Vec3::new(x, 0.3, 0.0)).len() < 1e-3);
+        assert!((hit.t - (x + 5.0) / 2.0).abs() < 1e-3);
+        assert!((hit.normal - hit.point.normalize()).len() < 1e-3);
+        assert!(hit.front_face);
+        // leaving from that surface it finds the far side
+        let inside = Ray::new(hit.point, Vec3::new(1, 0, 0));
+        let hit = ball.hit(inside, 0.0, std::f32::MAX).unwrap();
+        assert!((hit.point.x + x).abs() < 1e-3);
+        assert!(!hit.front_face);
+        let above = Ray::new(Vec3::new(-5.0, 1.1, 0.0), Vec3::new(1, 0, 0));
+        assert!(ball.hit(above, 0.0, std::f32::MAX).is_none());
+    }
+
+    #[test]
+    fn test_operators() {
+        let corner = Vec3::new(1, 1, 1);
+        assert!(
+            (cuboid(Vec3::from(1)).distance(corner * 2.0) - 3f32.sqrt()).abs() < 1e-6
+        );
+        let rounded = round(cuboid(Vec3::from(0.5)), 0.5);
+        assert!(
+            (rounded.distance(corner * 2.0) - (3f32.sqrt() * 1.5 - 0.5)).abs() < 1e-5
+        );
+        assert!((rounded.distance(Vec3::new(2, 0, 0)) - 1.0).abs() < 1e-6);
+
+        // smooth union fills in between the two spheres
+        let left = |p: Vec3| (p - Vec3::new(-1, 0, 0)).len() - 0.8;
+        let right = |p: Vec3| (p - Vec3::new(1, 0, 0)).len() - 0.8;
+        let blob = smooth_union(left, right, 0.5);
+        assert!(blob.distance(Vec3::zero()) < 0.2_f32.min(left(Vec3::zero())));
+        assert!((blob.distance(Vec3::new(-3, 0, 0)) - 1.2).abs() < 1e-6);
+
+        let twisted = twist(cuboid(Vec3::new(1.0, 5.0, 0.1)), std::f32::consts::PI / 2.0);
+        // a quarter turn one unit up lines the box up with z instead of x
+        assert!(twisted.distance(Vec3::new(0.0, 1.0, 0.9)) < 0.0);
+        assert!(twisted.distance(Vec3::new(0.9, 1.0, 0.0)) > 0.0);
+
+        let grid = repeat(sphere(0.5), Vec3::new(4, 0, 4));
+        assert_eq!(grid.distance(Vec3::new(8, 0, -4)), -0.5);
+        assert_eq!(grid.distance(Vec3::new(0, 4, 0)), 3.5);
+    }
+
+    #[test]
+    fn test_repeated() {
+        let bb = AABB::new(Vec3::new(-100, -1, -1), Vec3::new(100, 1, 1));
+        let row = Sdf::new(repeat(sphere(0.5), Vec3::new(3, 0, 0)), bb, material());
+        let r = Ray::new(Vec3::new(6, 5, 0), Vec3::new(0, -1, 0));
+        let hit = row.hit(r, 0.0, std::f32::MAX).unwrap();
+        assert!((hit.t - 4.5).abs() < 1e-3);
+        let between = Ray::new(Vec3::new(7.5, 5.0, 0.0), Vec3::new(0, -1, 0));
+        assert!(row.hit(between, 0.0, std::f32::MAX).is_none());
+    }
+
+    #[test]
+    fn test_mandelbulb() {
+        let bulb = Mandelbulb::default();
+        assert!(bulb.distance(Vec3::new(3, 0, 0)) > 1.0);
+        assert!(bulb.distance(Vec3::zero()) <= 0.0);
+        let fractal = Sdf::new(
+            bulb,
+            AABB::new(Vec3::from(-1.2), Vec3::from(1.2)),
+            material(),
+        )
+        .with_epsilon(0.001);
+        let r = Ray::new(Vec3::new(0.05, 0.1, -3.0), Vec3::new(0, 0, 1));
+        let hit = fractal.hit(r, 0.0, std::f32::MAX).unwrap();
+        assert!(hit.t > 1.8 && hit.t < 3.0);
+    }
+}
@@ -1,8 +1,10 @@
 use super::aabb::AABB;
-use super::{HitRecord, Hitable};
+use super::{tangents, HitRecord, Hitable};
 use crate::material::Material;
 use crate::ray::Ray;
+use crate::scene::Color;
 use crate::vec3::Vec3;
+use rand::random;
 use std::f32::consts::PI;
 use std::sync::Arc;
 
@@ -33,6 +35,19 @@ impl Sphere {
         let theta = p.y.asin();
         (1.0 - (phi + PI) / (2.0 * PI), (theta + PI / 2.0) / PI)
     }
+
+    /// The direction to the center and 1 - cos of the angle the sphere takes
+    /// up around it as seen from `origin`, which has to be outside
+    fn cone(&self, origin: Vec3) -> Option<(Vec3, f32)> {
+        let to_center = self.center - origin;
+        let ratio = self.radius * self.radius / to_center.dot(&to_center);
+        if ratio >= 1.0 {
+            return None;
+        }
+        // written so it doesn't cancel out for small far away spheres
+        let one_minus_cos = ratio / (1.0 + (1.0 - ratio).sqrt());
+        Some((to_center.normalize(), one_minus_cos))
+    }
 }
 
 impl Hitable for Sphere {
@@ -55,6 +70,10 @@ impl Hitable for Sphere {
                         v,
                         point,
                         normal,
+                        geometric_normal: normal,
+                        front_face: r.dir.dot(&normal) < 0.0,
+                        tangent: Vec3::zero(),
+                        color: Color::from(1),
                         material: self.material.as_ref(),
                     });
                 }
@@ -70,4 +89,59 @@ impl Hitable for Sphere {
             self.center + Vec3::from(self.radius),
         )
     }
+    /// Evenly over the directions that hit it, so only from outside of it
+    fn sample_towards(&self, origin: Vec3) -> Option<Vec3> {
+        let (axis, one_minus_cos) = self.cone(origin)?;
+        let cos_theta = 1.0 - random::<f32>() * one_minus_cos;
+        let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+        let phi = 2.0 * PI * random::<f32>();
+        let (a, b) = tangents(axis);
+        Some(axis * cos_theta + (a * phi.cos() + b * phi.sin()) * sin_theta)
+    }
+    fn pdf_towards(&self, origin: Vec3, dir: Vec3) -> f32 {
+        match self.cone(origin) {
+            Some((_, one_minus_cos))
+                if self
+                    .hit(Ray::new(origin, dir), 0.0, std::f32::MAX)
+                    .is_some() =>
+            {
+                1.0 / (2.0 * PI * one_minus_cos)
+            }
+            _ => 0.0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::light::Light;
+    use crate::texture::solid::Solid;
+
+    #[test]
+    fn test_sample_towards() {
+        let lamp = Sphere::new(
+            Vec3::new(1, 2, 3),
+            0.5,
+            Arc::new(Light::new(Solid::new(Color::from(1)))),
+        );
+        let origin = Vec3::new(1, 2, 2);
+        let samples = 50_000;
+        for _ in 0..100 {
+            let dir = lamp.sample_towards(origin).unwrap();
+            assert!(lamp
+                .hit(Ray::new(origin, dir), 0.0, std::f32::MAX)
+                .is_some());
+        }
+        // the pdf over every direction adds up to 1
+        let total = (0..samples)
+            .map(|_| lamp.pdf_towards(origin, Vec3::rand_in_unit_sphere()))
+            .sum::<f32>()
+            * 4.0
+            * PI
+            / samples as f32;
+        assert!((total - 1.0).abs() < 0.1, "{}", total);
+        // there's no cone to pick from inside it
+        assert!(lamp.sample_towards(Vec3::new(1, 2, 3.2)).is_none());
+    }
 }
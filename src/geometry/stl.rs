@@ -0,0 +1,147 @@
+use super::mesh::MeshData;
+use crate::vec3::Vec3;
+use std::io::{self, Error, ErrorKind};
+
+/// Loads ascii or binary STL files. STL has no shared vertices so every facet
+/// gets its own 3 vertices with the facet normal, which renders flat shaded.
+pub fn load(filename: &str) -> io::Result<MeshData> {
+    parse(&std::fs::read(filename)?)
+}
+
+fn invalid(msg: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, format!("stl: {}", msg))
+}
+
+pub fn parse(bytes: &[u8]) -> io::Result<MeshData> {
+    // binary files are allowed to start with "solid" too, so the only
+    // reliable test is whether the size matches the triangle count
+    if bytes.len() >= 84 {
+        let count = u32::from_le_bytes([bytes[80], bytes[81], bytes[82], bytes[83]]);
+        if bytes.len() == 84 + 50 * count as usize {
+            return Ok(parse_binary(&bytes[84..]));
+        }
+    }
+    if bytes.starts_with(b"solid") {
+        parse_ascii(
+            std::str::from_utf8(bytes).map_err(|_| invalid("file isn't valid text"))?,
+        )
+    } else {
+        Err(invalid("not an stl file"))
+    }
+}
+
+fn add_facet(mesh: &mut MeshData, normal: Vec3, points: [Vec3; 3]) {
+    // lots of exporters leave the normal as zero so fall back to the winding
+    let winding = (points[1] - points[0]).cross(&(points[2] - points[0]));
+    let normal = if normal.len() > 0.0 {
+        normal.normalize()
+    } else if winding.len() > 0.0 {
+        winding.normalize()
+    } else {
+        // a facet without any area can't be hit anyway
+        return;
+    };
+    for &p in &points {
+        mesh.indices.push(mesh.positions.len() as u32);
+        mesh.positions.push(p);
+        mesh.normals.push(normal);
+    }
+}
+
+fn parse_binary(data: &[u8]) -> MeshData {
+    let mut mesh = MeshData::default();
+    for facet in data.chunks(50) {
+        let vec = |i: usize| {
+            let f = |j: usize| {
+                let o = i * 12 + j * 4;
+                f32::from_le_bytes([facet[o], facet[o + 1], facet[o + 2], facet[o + 3]])
+            };
+            Vec3::new(f(0), f(1), f(2))
+        };
+        add_facet(&mut mesh, vec(0), [vec(1), vec(2), vec(3)]);
+    }
+    mesh
+}
+
+fn parse_ascii(text: &str) -> io::Result<MeshData> {
+    let mut mesh = MeshData::default();
+    let mut normal = Vec3::zero();
+    let mut points = Vec::new();
+    let read_vec = |words: &[&str]| -> io::Result<Vec3> {
+        let n: Vec<f32> = words
+            .iter()
+            .map(|w| w.parse().map_err(|_| invalid("bad number")))
+            .collect::<io::Result<_>>()?;
+        if n.len() != 3 {
+            return Err(invalid("expected 3 coordinates"));
+        }
+        Ok(Vec3::new(n[0], n[1], n[2]))
+    };
+    for line in text.lines() {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match words.first() {
+            Some(&"facet") => normal = read_vec(words.get(2..).unwrap_or(&[]))?,
+            Some(&"vertex") => points.push(read_vec(&words[1..])?),
+            Some(&"endfacet") => {
+                if points.len() != 3 {
+                    return Err(invalid("facet without 3 vertices"));
+                }
+                add_facet(&mut mesh, normal, [points[0], points[1], points[2]]);
+                points.clear();
+            }
+            _ => {}
+        }
+    }
+    Ok(mesh)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ascii() {
+        let mesh = parse(
+            b"solid test\n\
+              facet normal 0 0 0\n outer loop\n\
+              vertex 0 0 0\n vertex 1 0 0\n vertex 0 1 0\n\
+              endloop\nendfacet\nendsolid test\n",
+        )
+        .unwrap();
+        assert_eq!(
+            mesh.positions,
+            vec![Vec3::new(0, 0, 0), Vec3::new(1, 0, 0), Vec3::new(0, 1, 0)]
+        );
+        // the zero normal gets replaced with one from the winding
+        assert_eq!(mesh.normals[0], Vec3::new(0, 0, 1));
+        assert_eq!(mesh.indices, vec![0, 1, 2]);
+        // facets squashed into a line with no normal are left out
+        let mesh = parse(
+            b"solid test\n\
+              facet normal 0 0 0\n outer loop\n\
+              vertex 0 0 0\n vertex 1 0 0\n vertex 2 0 0\n\
+              endloop\nendfacet\nendsolid test\n",
+        )
+        .unwrap();
+        assert!(mesh.positions.is_empty() && mesh.indices.is_empty());
+    }
+
+    #[test]
+    fn test_binary() {
+        // the header starting with "solid" shouldn't confuse the parser
+        let mut bytes = b"solid but actually binary".to_vec();
+        bytes.resize(80, 0);
+        bytes.extend_from_slice(&2u32.to_le_bytes());
+        for z in &[0.0f32, 5.0] {
+            for v in &[0.0, 0.0, -1.0, 0.0, 0.0, *z, 0.0, 1.0, *z, 1.0, 0.0, *z] {
+                bytes.extend_from_slice(&(*v as f32).to_le_bytes());
+            }
+            bytes.extend_from_slice(&[0, 0]);
+        }
+        let mesh = parse(&bytes).unwrap();
+        assert_eq!(mesh.positions.len(), 6);
+        assert_eq!(mesh.positions[4], Vec3::new(0, 1, 5));
+        assert_eq!(mesh.normals[3], Vec3::new(0, 0, -1));
+        assert_eq!(mesh.indices, vec![0, 1, 2, 3, 4, 5]);
+    }
+}
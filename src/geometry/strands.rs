@@ -0,0 +1,297 @@
+use super::aabb::AABB;
+use super::curve::{Curve, CurveShape};
+use super::{HitRecord, Hitable};
+use crate::bvh::BVHNode;
+use crate::material::Material;
+use crate::matrix::Matrix4;
+use crate::ray::Ray;
+use crate::vec3::Vec3;
+use std::convert::TryInto;
+use std::io::{self, Error, ErrorKind};
+use std::sync::Arc;
+
+/// A polyline of points with the width at each one
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Strand {
+    pub points: Vec<Vec3>,
+    pub widths: Vec<f32>,
+}
+
+/// Loads Cem Yuksel's binary `.hair` files, or for anything else a text
+/// format with a strand on each line as `x y z width` for every point. Lines
+/// starting with `#` are comments.
+pub fn load(filename: &str) -> io::Result<Vec<Strand>> {
+    if filename.ends_with(".hair") {
+        parse_hair(&std::fs::read(filename)?)
+    } else {
+        parse_text(&std::fs::read_to_string(filename)?)
+    }
+}
+
+fn invalid(msg: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, format!("strands: {}", msg))
+}
+
+pub fn parse_text(text: &str) -> io::Result<Vec<Strand>> {
+    let mut strands = Vec::new();
+    for line in text.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let values = line
+            .split_whitespace()
+            .map(|w| w.parse::<f32>())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|_| invalid("expected a number"))?;
+        if values.len() % 4 != 0 {
+            return Err(invalid("expected x y z width for each point"));
+        }
+        strands.push(Strand {
+            points: values
+                .chunks(4)
+                .map(|v| Vec3::new(v[0], v[1], v[2]))
+                .collect(),
+            widths: values.chunks(4).map(|v| v[3]).collect(),
+        });
+    }
+    Ok(strands)
+}
+
+/// The header says which arrays follow, anything missing uses the defaults
+/// from the header instead. Transparency and colors are skipped.
+pub fn parse_hair(bytes: &[u8]) -> io::Result<Vec<Strand>> {
+    if bytes.len() < 128 || &bytes[..4] != b"HAIR" {
+        return Err(invalid("missing HAIR header"));
+    }
+    let u32_at = |i: usize| u32::from_le_bytes(bytes[i..i + 4].try_into().unwrap());
+    let f32_at = |i: usize| f32::from_le_bytes(bytes[i..i + 4].try_into().unwrap());
+    let strand_count = u32_at(4) as usize;
+    let point_count = u32_at(8) as usize;
+    let flags = u32_at(12);
+    let default_segments = u32_at(16) as usize;
+    let default_width = f32_at(20);
+    let (has_segments, has_points, has_widths) =
+        (flags & 1 != 0, flags & 2 != 0, flags & 4 != 0);
+    if !has_points {
+        return Err(invalid("no points"));
+    }
+
+    let mut offset = 128;
+    let mut take = |size: usize| -> io::Result<&[u8]> {
+        let data = bytes
+            .get(offset..offset + size)
+            .ok_or_else(|| invalid("unexpected end of file"))?;
+        offset += size;
+        Ok(data)
+    };
+    let segments: Vec<usize> = if has_segments {
+        take(strand_count * 2)?
+            .chunks(2)
+            .map(|b| u16::from_le_bytes([b[0], b[1]]) as usize)
+            .collect()
+    } else {
+        vec![default_segments; strand_count]
+    };
+    let floats = |data: &[u8]| -> Vec<f32> {
+        data.chunks(4)
+            .map(|b| f32::from_le_bytes(b.try_into().unwrap()))
+            .collect()
+    };
+    let points = floats(take(point_count * 12)?);
+    let widths = if has_widths {
+        floats(take(point_count * 4)?)
+    } else {
+        vec![default_width; point_count]
+    };
+
+    let mut strands = Vec::with_capacity(strand_count);
+    let mut start = 0;
+    for count in segments {
+        let end = start + count + 1;
+        if end > point_count {
+            return Err(invalid("more segments than points"));
+        }
+        strands.push(Strand {
+            points: (start..end)
+                .map(|i| Vec3::new(points[i * 3], points[i * 3 + 1], points[i * 3 + 2]))
+                .collect(),
+            widths: widths[start..end].to_vec(),
+        });
+        start = end;
+    }
+    Ok(strands)
+}
+
+/// Curves running smoothly through the points of each strand, in a BVH
+#[derive(Debug)]
+pub struct Strands {
+    pub curves: BVHNode,
+}
+
+impl Strands {
+    /// Widths get scaled by how much the transform scales on average. There
+    /// has to be at least one strand with 2 or more points.
+    pub fn new(
+        strands: &[Strand],
+        transform: Matrix4,
+        shape: CurveShape,
+        material: Arc<dyn Material>,
+    ) -> Self {
+        let axes = [Vec3::new(1, 0, 0), Vec3::new(0, 1, 0), Vec3::new(0, 0, 1)];
+        let scale = axes
+            .iter()
+            .map(|&axis| transform.transform_vector(axis).len())
+            .sum::<f32>()
+            / 3.0;
+        let mut curves: Vec<Box<dyn Hitable>> = Vec::new();
+        for strand in strands {
+            let points: Vec<Vec3> = strand
+                .points
+                .iter()
+                .map(|&p| transform.transform_point(p))
+                .collect();
+            let n = points.len();
+            let width = |i: usize| strand.widths.get(i).cloned().unwrap_or(0.0) * scale;
+            for i in 1..n {
+                // Catmull-Rom through the points, with the ends extended
+                // straight out
+                let (a, b) = (points[i - 1], points[i]);
+                let before = if i >= 2 { points[i - 2] } else { a * 2.0 - b };
+                let after = if i + 1 < n {
+                    points[i + 1]
+                } else {
+                    b * 2.0 - a
+                };
+                let bezier = [a, a + (b - before) / 6.0, b - (after - a) / 6.0, b];
+                curves.push(Box::new(
+                    Curve::new(bezier, (width(i - 1), width(i)), material.clone())
+                        .with_shape(shape),
+                ));
+            }
+        }
+        assert!(!curves.is_empty(), "no strands with at least 2 points");
+        Strands {
+            curves: BVHNode::from(&mut curves),
+        }
+    }
+
+    /// Errors on files without any strands long enough to make a curve
+    pub fn from_file(
+        filename: &str,
+        transform: Matrix4,
+        shape: CurveShape,
+        material: Arc<dyn Material>,
+    ) -> io::Result<Self> {
+        let strands = load(filename)?;
+        if strands.iter().all(|s| s.points.len() < 2) {
+            return Err(invalid("no strands with at least 2 points"));
+        }
+        Ok(Strands::new(&strands, transform, shape, material))
+    }
+}
+
+impl Hitable for Strands {
+    fn hit(&self, r: Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+        self.curves.hit(r, t_min, t_max)
+    }
+
+    fn get_bb(&self) -> AABB {
+        self.curves.get_bb()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::specular::Specular;
+    use crate::scene::Color;
+
+    #[test]
+    fn test_text() {
+        let text =
+            "# two strands\n0 0 0 0.1  0 1 0 0.05  0 2 0 0.01\n\n1 0 0 0.2 1 1 0 0.2\n";
+        let strands = parse_text(text).unwrap();
+        assert_eq!(strands.len(), 2);
+        assert_eq!(strands[0].points[2], Vec3::new(0, 2, 0));
+        assert_eq!(strands[0].widths, vec![0.1, 0.05, 0.01]);
+        assert!(parse_text("0 0 0\n").is_err());
+        assert!(parse_text("0 0 x 1\n").is_err());
+    }
+
+    /// A .hair file with the given header flags and arrays
+    fn hair_file(
+        flags: u32,
+        segments: &[u16],
+        points: &[f32],
+        widths: &[f32],
+    ) -> Vec<u8> {
+        let mut bytes = b"HAIR".to_vec();
+        bytes.extend(&2u32.to_le_bytes());
+        bytes.extend(&((points.len() / 3) as u32).to_le_bytes());
+        bytes.extend(&flags.to_le_bytes());
+        bytes.extend(&2u32.to_le_bytes());
+        bytes.extend(&0.5f32.to_le_bytes());
+        bytes.resize(128, 0);
+        for s in segments {
+            bytes.extend(&s.to_le_bytes());
+        }
+        for f in points.iter().chain(widths) {
+            bytes.extend(&f.to_le_bytes());
+        }
+        bytes
+    }
+
+    #[test]
+    fn test_hair() {
+        let points: Vec<f32> = (0..15).map(|i| i as f32).collect();
+        let bytes = hair_file(1 | 2 | 4, &[1, 2], &points, &[1.0, 2.0, 3.0, 4.0, 5.0]);
+        let strands = parse_hair(&bytes).unwrap();
+        assert_eq!(strands.len(), 2);
+        assert_eq!(
+            strands[0].points,
+            vec![Vec3::new(0, 1, 2), Vec3::new(3, 4, 5)]
+        );
+        assert_eq!(strands[1].widths, vec![3.0, 4.0, 5.0]);
+        // default segment counts and widths from the header
+        let points: Vec<f32> = (0..18).map(|i| i as f32).collect();
+        let strands = parse_hair(&hair_file(2, &[], &points, &[])).unwrap();
+        assert_eq!(strands[1].points.len(), 3);
+        assert_eq!(strands[1].widths, vec![0.5; 3]);
+        assert!(parse_hair(&bytes[..140]).is_err());
+        assert!(parse_hair(b"NOPE").is_err());
+    }
+
+    #[test]
+    fn test_strands() {
+        let strand = Strand {
+            points: vec![Vec3::new(0, 0, 0), Vec3::new(0, 1, 0), Vec3::new(1, 2, 0)],
+            widths: vec![0.1, 0.1, 0.1],
+        };
+        let strands = Strands::new(
+            &[strand],
+            Matrix4::scale(Vec3::from(2)),
+            CurveShape::Cylinder,
+            Arc::new(Specular::new(Color::from(1), 0.0)),
+        );
+        // it passes through the points, and the widths get scaled too
+        for &(x, hits) in &[(0.0, true), (0.05, true), (0.2, false)] {
+            let r = Ray::new(Vec3::new(x, 2.0, 5.0), Vec3::new(0, 0, -1));
+            assert_eq!(strands.hit(r, 0.0, std::f32::MAX).is_some(), hits);
+        }
+        let bb = strands.get_bb();
+        assert!(bb.max.x >= 2.0 && bb.max.y >= 4.0);
+
+        // nothing to build a curve from is an error rather than a panic
+        let path = std::env::temp_dir().join("ray_strands_empty.txt");
+        std::fs::write(&path, "# just a comment\n0 0 0 0.1\n").unwrap();
+        let material = Arc::new(Specular::new(Color::from(1), 0.0));
+        let empty = Strands::from_file(
+            path.to_str().unwrap(),
+            Matrix4::identity(),
+            CurveShape::Cylinder,
+            material,
+        );
+        assert!(empty.is_err());
+    }
+}
@@ -0,0 +1,611 @@
+use super::mesh::MeshData;
+use crate::scene::Color;
+use crate::vec3::Vec3;
+use std::collections::HashMap;
+
+/// Which rules to refine a cage with
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Scheme {
+    /// Splits every face into quads. It takes any polygons but is meant for
+    /// cages made of quads.
+    CatmullClark,
+    /// Splits every triangle into 4, polygons get split into triangles first
+    Loop,
+}
+
+/// Smooths a coarse cage into a finer mesh by repeatedly splitting its faces
+/// and averaging the vertices with their neighbors. Boundaries and creased
+/// edges stay sharp.
+#[derive(Debug, Clone)]
+pub struct Subdivision {
+    pub scheme: Scheme,
+    pub levels: u32,
+    /// Pairs of position indices in the cage with how many levels the edge
+    /// between them stays sharp for. Fractional values blend so creases can
+    /// be softened gradually.
+    pub creases: Vec<(u32, u32, f32)>,
+    /// Edges where faces meet at more than this many degrees stay sharp
+    pub crease_angle: Option<f32>,
+}
+
+impl Subdivision {
+    pub fn new(scheme: Scheme, levels: u32) -> Self {
+        Subdivision {
+            scheme,
+            levels,
+            creases: Vec::new(),
+            crease_angle: None,
+        }
+    }
+
+    pub fn with_crease(mut self, a: u32, b: u32, sharpness: f32) -> Self {
+        self.creases.push((a, b, sharpness));
+        self
+    }
+
+    pub fn with_crease_angle(self, degrees: f32) -> Self {
+        Subdivision {
+            crease_angle: Some(degrees),
+            ..self
+        }
+    }
+
+    /// Subdivides the cage and splits the result into triangles. The normals
+    /// are left for `TriangleMesh` to generate, use `Shading::Crease` to keep
+    /// creases looking sharp as well.
+    pub fn apply(&self, cage: &Polygons) -> MeshData {
+        let mut mesh = if self.scheme == Scheme::Loop {
+            cage.triangulated()
+        } else {
+            cage.clone()
+        };
+        let mut sharpness = HashMap::new();
+        if let Some(degrees) = self.crease_angle {
+            let cos = degrees.to_radians().cos();
+            let normals: Vec<Vec3> =
+                (0..mesh.faces.len()).map(|f| mesh.normal(f)).collect();
+            for (edge, faces) in mesh.edge_faces() {
+                if faces.len() == 2 && normals[faces[0]].dot(&normals[faces[1]]) < cos {
+                    sharpness.insert(edge, std::f32::INFINITY);
+                }
+            }
+        }
+        for &(a, b, s) in &self.creases {
+            sharpness.insert(edge_key(a, b), s);
+        }
+        for _ in 0..self.levels {
+            let (refined, creases) = self.refine(&mesh, &sharpness);
+            mesh = refined;
+            sharpness = creases;
+        }
+        mesh.to_triangles()
+    }
+
+    /// One level of subdivision. New positions are the moved cage vertices,
+    /// then a point per edge, then for Catmull-Clark a point per face.
+    fn refine(
+        &self,
+        mesh: &Polygons,
+        sharpness: &HashMap<(u32, u32), f32>,
+    ) -> (Polygons, HashMap<(u32, u32), f32>) {
+        let p = &mesh.positions;
+        let mut edges: Vec<(u32, u32)> = Vec::new();
+        let mut edge_faces: Vec<Vec<usize>> = Vec::new();
+        let mut edge_ids = HashMap::new();
+        for (f, face) in mesh.faces.iter().enumerate() {
+            for i in 0..face.len() {
+                let key = edge_key(face[i], face[(i + 1) % face.len()]);
+                let id = *edge_ids.entry(key).or_insert_with(|| {
+                    edges.push(key);
+                    edge_faces.push(Vec::new());
+                    edges.len() - 1
+                });
+                edge_faces[id].push(f);
+            }
+        }
+        // boundaries and edges shared by more than 2 faces are always sharp
+        let sharp: Vec<f32> = (0..edges.len())
+            .map(|e| {
+                if edge_faces[e].len() != 2 {
+                    std::f32::INFINITY
+                } else {
+                    sharpness.get(&edges[e]).cloned().unwrap_or(0.0)
+                }
+            })
+            .collect();
+        let mut vertex_edges = vec![Vec::new(); p.len()];
+        for (e, &(a, b)) in edges.iter().enumerate() {
+            vertex_edges[a as usize].push(e);
+            vertex_edges[b as usize].push(e);
+        }
+        let mut vertex_faces = vec![Vec::new(); p.len()];
+        for (f, face) in mesh.faces.iter().enumerate() {
+            for &v in face {
+                vertex_faces[v as usize].push(f);
+            }
+        }
+        let face_points: Vec<Vec3> = mesh
+            .faces
+            .iter()
+            .map(|face| average(face.iter().map(|&v| p[v as usize])))
+            .collect();
+        let midpoint = |e: usize| (p[edges[e].0 as usize] + p[edges[e].1 as usize]) / 2.0;
+
+        let edge_points = (0..edges.len()).map(|e| {
+            let (a, b) = edges[e];
+            if sharp[e] >= 1.0 {
+                return midpoint(e);
+            }
+            let smooth = match self.scheme {
+                Scheme::CatmullClark => {
+                    (p[a as usize]
+                        + p[b as usize]
+                        + face_points[edge_faces[e][0]]
+                        + face_points[edge_faces[e][1]])
+                        / 4.0
+                }
+                Scheme::Loop => {
+                    let opposite = |f: usize| {
+                        let v = mesh.faces[f].iter().find(|&&v| v != a && v != b);
+                        p[*v.unwrap() as usize]
+                    };
+                    (p[a as usize] + p[b as usize]) * 0.375
+                        + (opposite(edge_faces[e][0]) + opposite(edge_faces[e][1]))
+                            * 0.125
+                }
+            };
+            lerp(smooth, midpoint(e), sharp[e])
+        });
+
+        let vertex_points = (0..p.len()).map(|v| {
+            let around = &vertex_edges[v];
+            let point = p[v];
+            if around.is_empty() {
+                return point;
+            }
+            let other = |e: usize| {
+                let (a, b) = edges[e];
+                p[if a as usize == v { b } else { a } as usize]
+            };
+            let n = around.len() as f32;
+            let smooth = match self.scheme {
+                Scheme::CatmullClark => {
+                    let faces = average(vertex_faces[v].iter().map(|&f| face_points[f]));
+                    let mids = average(around.iter().map(|&e| midpoint(e)));
+                    (faces + mids * 2.0 + point * (n - 3.0)) / n
+                }
+                Scheme::Loop => {
+                    let beta = if around.len() == 3 {
+                        3.0 / 16.0
+                    } else {
+                        3.0 / (8.0 * n)
+                    };
+                    point * (1.0 - n * beta)
+                        + around.iter().fold(Vec3::zero(), |s, &e| s + other(e)) * beta
+                }
+            };
+            let creased: Vec<usize> =
+                around.iter().cloned().filter(|&e| sharp[e] > 0.0).collect();
+            // the corners of a boundary stay put
+            let rule = if creased.len() > 2 || vertex_faces[v].len() == 1 {
+                point
+            } else if creased.len() == 2 {
+                (other(creased[0]) + point * 6.0 + other(creased[1])) / 8.0
+            } else {
+                return smooth;
+            };
+            let weight =
+                creased.iter().map(|&e| sharp[e]).sum::<f32>() / creased.len() as f32;
+            lerp(smooth, rule, weight)
+        });
+
+        let vertex_count = p.len() as u32;
+        let edge_index = |a: u32, b: u32| vertex_count + edge_ids[&edge_key(a, b)] as u32;
+        let mut positions: Vec<Vec3> = vertex_points.collect();
+        positions.extend(edge_points);
+        let mut refined = Polygons {
+            positions,
+            ..Polygons::default()
+        };
+        match self.scheme {
+            Scheme::CatmullClark => {
+                let face_start = refined.positions.len() as u32;
+                refined.positions.extend(face_points);
+                for (f, face) in mesh.faces.iter().enumerate() {
+                    let k = face.len();
+                    for i in 0..k {
+                        let (prev, next) = ((i + k - 1) % k, (i + 1) % k);
+                        refined.faces.push(vec![
+                            face[i],
+                            edge_index(face[i], face[next]),
+                            face_start + f as u32,
+                            edge_index(face[prev], face[i]),
+                        ]);
+                    }
+                }
+            }
+            Scheme::Loop => {
+                for face in &mesh.faces {
+                    let (a, b, c) = (face[0], face[1], face[2]);
+                    let (ab, bc, ca) =
+                        (edge_index(a, b), edge_index(b, c), edge_index(c, a));
+                    refined.faces.push(vec![a, ab, ca]);
+                    refined.faces.push(vec![b, bc, ab]);
+                    refined.faces.push(vec![c, ca, bc]);
+                    refined.faces.push(vec![ab, bc, ca]);
+                }
+            }
+        }
+        // texture coordinates and colors just get interpolated across each
+        // face, the same way the new faces were cut
+        let split = |corners: &[Vec<Vec3>]| -> Vec<Vec<Vec3>> {
+            if corners.is_empty() {
+                return Vec::new();
+            }
+            let mut split = Vec::new();
+            for c in corners {
+                let k = c.len();
+                let mid = |i: usize, j: usize| (c[i] + c[j]) / 2.0;
+                match self.scheme {
+                    Scheme::CatmullClark => {
+                        let center = average(c.iter().cloned());
+                        for (i, &corner) in c.iter().enumerate() {
+                            let (prev, next) = ((i + k - 1) % k, (i + 1) % k);
+                            split.push(vec![corner, mid(i, next), center, mid(prev, i)]);
+                        }
+                    }
+                    Scheme::Loop => {
+                        let (ab, bc, ca) = (mid(0, 1), mid(1, 2), mid(2, 0));
+                        split.push(vec![c[0], ab, ca]);
+                        split.push(vec![c[1], bc, ab]);
+                        split.push(vec![c[2], ca, bc]);
+                        split.push(vec![ab, bc, ca]);
+                    }
+                }
+            }
+            split
+        };
+        refined.texcoords = split(&mesh.texcoords);
+        refined.colors = split(&mesh.colors);
+
+        // each half of a crease is one level less sharp
+        let mut creases = HashMap::new();
+        for (&(a, b), &s) in sharpness {
+            if s > 1.0 && edge_ids.contains_key(&(a, b)) {
+                let middle = edge_index(a, b);
+                creases.insert(edge_key(a, middle), s - 1.0);
+                creases.insert(edge_key(middle, b), s - 1.0);
+            }
+        }
+        (refined, creases)
+    }
+}
+
+/// Faces with any number of sides, like the cage an artist models.
+/// Positions are shared between faces while texture coordinates and colors
+/// belong to each corner, so seams in them don't tear the surface apart.
+#[derive(Debug, Default, Clone)]
+pub struct Polygons {
+    pub positions: Vec<Vec3>,
+    /// Indices into positions, counter-clockwise
+    pub faces: Vec<Vec<u32>>,
+    /// Empty, or one per corner of each face
+    pub texcoords: Vec<Vec<Vec3>>,
+    /// Empty, or one per corner of each face
+    pub colors: Vec<Vec<Color>>,
+}
+
+impl Polygons {
+    /// Puts polygons back together from the fans of triangles they were
+    /// split into, `sides` has the number of sides of each polygon in order.
+    /// Without it every triangle is a face. Vertices at the same position
+    /// are merged since files split them wherever texture coordinates or
+    /// normals change.
+    pub fn from_triangles(data: &MeshData, sides: Option<&[u32]>) -> Self {
+        let mut welded = HashMap::new();
+        let mut positions = Vec::new();
+        let remap: Vec<u32> = data
+            .positions
+            .iter()
+            .map(|p| {
+                let key = [p.x.to_bits(), p.y.to_bits(), p.z.to_bits()];
+                *welded.entry(key).or_insert_with(|| {
+                    positions.push(*p);
+                    positions.len() as u32 - 1
+                })
+            })
+            .collect();
+        let triangles = data.indices.len() / 3;
+        let sides: Vec<u32> = match sides {
+            Some(s) if s.iter().map(|&n| n as usize - 2).sum::<usize>() == triangles => {
+                s.to_vec()
+            }
+            _ => vec![3; triangles],
+        };
+        let mut polygons = Polygons {
+            positions,
+            ..Polygons::default()
+        };
+        let mut triangle = 0;
+        for &n in &sides {
+            // a fan around the first corner adds one corner per triangle
+            let mut corners = data.indices[triangle * 3..triangle * 3 + 3].to_vec();
+            for t in triangle + 1..triangle + n as usize - 2 {
+                corners.push(data.indices[t * 3 + 2]);
+            }
+            triangle += n as usize - 2;
+            let attribute = |values: &[Vec3]| -> Vec<Vec3> {
+                corners.iter().map(|&i| values[i as usize]).collect()
+            };
+            if data.texcoords.len() == data.positions.len() {
+                polygons.texcoords.push(attribute(&data.texcoords));
+            }
+            if data.colors.len() == data.positions.len() {
+                polygons.colors.push(attribute(&data.colors));
+            }
+            polygons
+                .faces
+                .push(corners.iter().map(|&i| remap[i as usize]).collect());
+        }
+        polygons
+    }
+
+    fn triangulated(&self) -> Polygons {
+        let fan = |face: &Vec<Vec3>| -> Vec<Vec<Vec3>> {
+            (1..face.len() - 1)
+                .map(|i| vec![face[0], face[i], face[i + 1]])
+                .collect()
+        };
+        Polygons {
+            positions: self.positions.clone(),
+            faces: self
+                .faces
+                .iter()
+                .flat_map(|f| (1..f.len() - 1).map(move |i| vec![f[0], f[i], f[i + 1]]))
+                .collect(),
+            texcoords: self.texcoords.iter().flat_map(fan).collect(),
+            colors: self.colors.iter().flat_map(fan).collect(),
+        }
+    }
+
+    /// Newell's method, which works for polygons that aren't quite flat
+    fn normal(&self, f: usize) -> Vec3 {
+        let face = &self.faces[f];
+        let mut n = Vec3::zero();
+        for i in 0..face.len() {
+            let a = self.positions[face[i] as usize];
+            let b = self.positions[face[(i + 1) % face.len()] as usize];
+            n += Vec3::new(
+                (a.y - b.y) * (a.z + b.z),
+                (a.z - b.z) * (a.x + b.x),
+                (a.x - b.x) * (a.y + b.y),
+            );
+        }
+        if n.len() > 0.0 {
+            n.normalize()
+        } else {
+            n
+        }
+    }
+
+    fn edge_faces(&self) -> HashMap<(u32, u32), Vec<usize>> {
+        let mut edges: HashMap<(u32, u32), Vec<usize>> = HashMap::new();
+        for (f, face) in self.faces.iter().enumerate() {
+            for i in 0..face.len() {
+                let key = edge_key(face[i], face[(i + 1) % face.len()]);
+                edges.entry(key).or_default().push(f);
+            }
+        }
+        edges
+    }
+
+    /// Splits the faces into fans of triangles. Corners only share a vertex
+    /// if their texture coordinates and colors match as well.
+    fn to_triangles(&self) -> MeshData {
+        let mut data = MeshData::default();
+        let mut vertices = HashMap::new();
+        for (f, face) in self.faces.iter().enumerate() {
+            let corner: Vec<u32> = (0..face.len())
+                .map(|i| {
+                    let uv = self.texcoords.get(f).map(|c| c[i]);
+                    let color = self.colors.get(f).map(|c| c[i]);
+                    let bits = |v: Option<Vec3>| {
+                        v.map_or([0; 3], |v| {
+                            [v.x.to_bits(), v.y.to_bits(), v.z.to_bits()]
+                        })
+                    };
+                    let key = (face[i], bits(uv), bits(color));
+                    *vertices.entry(key).or_insert_with(|| {
+                        data.positions.push(self.positions[face[i] as usize]);
+                        data.texcoords.extend(uv);
+                        data.colors.extend(color);
+                        data.positions.len() as u32 - 1
+                    })
+                })
+                .collect();
+            for i in 1..corner.len() - 1 {
+                data.indices
+                    .extend_from_slice(&[corner[0], corner[i], corner[i + 1]]);
+            }
+        }
+        data
+    }
+}
+
+fn edge_key(a: u32, b: u32) -> (u32, u32) {
+    (a.min(b), a.max(b))
+}
+
+fn average(points: impl Iterator<Item = Vec3>) -> Vec3 {
+    let (sum, count) = points.fold((Vec3::zero(), 0), |(s, n), p| (s + p, n + 1));
+    sum / count as f32
+}
+
+/// From a at 0 to b at 1 and beyond, which is how sharpness blends rules
+fn lerp(a: Vec3, b: Vec3, t: f32) -> Vec3 {
+    let t = t.min(1.0);
+    a * (1.0 - t) + b * t
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A cube from -1 to 1 made of quads
+    fn cube() -> Polygons {
+        Polygons {
+            positions: (0..8)
+                .map(|i| {
+                    Vec3::new(i & 1, (i >> 1) & 1, (i >> 2) & 1) * 2.0 - Vec3::from(1)
+                })
+                .collect(),
+            faces: vec![
+                vec![0, 2, 3, 1],
+                vec![4, 5, 7, 6],
+                vec![0, 1, 5, 4],
+                vec![2, 6, 7, 3],
+                vec![0, 4, 6, 2],
+                vec![1, 3, 7, 5],
+            ],
+            ..Polygons::default()
+        }
+    }
+
+    fn max_abs(p: Vec3) -> f32 {
+        p.x.abs().max(p.y.abs()).max(p.z.abs())
+    }
+
+    #[test]
+    fn test_catmull_clark() {
+        let once = Subdivision::new(Scheme::CatmullClark, 1).apply(&cube());
+        // a point per vertex, edge and face, and 4 quads per face
+        assert_eq!(once.positions.len(), 8 + 12 + 6);
+        assert_eq!(once.indices.len(), 24 * 2 * 3);
+        // the corners move in by the standard valence 3 weights
+        let corner = once
+            .positions
+            .iter()
+            .find(|p| p.x > 0.0 && p.x == p.y && p.y == p.z);
+        assert!((*corner.unwrap() - Vec3::from(5.0 / 9.0)).len() < 1e-6);
+
+        let smooth = Subdivision::new(Scheme::CatmullClark, 3).apply(&cube());
+        assert_eq!(smooth.positions.len(), 6 * 64 + 2);
+        for &p in &smooth.positions {
+            assert!(max_abs(p) < 1.0);
+            assert!(p.len() > 0.5);
+        }
+    }
+
+    #[test]
+    fn test_creases() {
+        // with every edge creased it stays a cube
+        let hard = Subdivision::new(Scheme::CatmullClark, 2)
+            .with_crease_angle(45.0)
+            .apply(&cube());
+        for &p in &hard.positions {
+            assert!((max_abs(p) - 1.0).abs() < 1e-6);
+        }
+        // a single creased edge keeps its midpoint where a smooth one doesn't
+        let mid = |s: Subdivision| {
+            let data = s.apply(&cube());
+            *data
+                .positions
+                .iter()
+                .find(|p| p.x.abs() < 1e-6 && p.y > 0.5 && p.z > 0.5)
+                .unwrap()
+        };
+        let smooth = mid(Subdivision::new(Scheme::CatmullClark, 1));
+        let sharp = mid(Subdivision::new(Scheme::CatmullClark, 1).with_crease(6, 7, 1.0));
+        let half = mid(Subdivision::new(Scheme::CatmullClark, 1).with_crease(6, 7, 0.5));
+        assert_eq!(sharp, Vec3::new(0, 1, 1));
+        assert!(smooth.y < 1.0);
+        assert!((half - (smooth + sharp) / 2.0).len() < 1e-6);
+    }
+
+    #[test]
+    fn test_loop() {
+        // an octahedron
+        let octahedron = Polygons {
+            positions: vec![
+                Vec3::new(1, 0, 0),
+                Vec3::new(-1, 0, 0),
+                Vec3::new(0, 1, 0),
+                Vec3::new(0, -1, 0),
+                Vec3::new(0, 0, 1),
+                Vec3::new(0, 0, -1),
+            ],
+            faces: vec![
+                vec![0, 2, 4],
+                vec![2, 1, 4],
+                vec![1, 3, 4],
+                vec![3, 0, 4],
+                vec![2, 0, 5],
+                vec![1, 2, 5],
+                vec![3, 1, 5],
+                vec![0, 3, 5],
+            ],
+            ..Polygons::default()
+        };
+        let once = Subdivision::new(Scheme::Loop, 1).apply(&octahedron);
+        assert_eq!(once.positions.len(), 6 + 12);
+        assert_eq!(once.indices.len(), 32 * 3);
+        let twice = Subdivision::new(Scheme::Loop, 2).apply(&octahedron);
+        // it rounds out towards a sphere
+        let lengths: Vec<f32> = twice.positions.iter().map(|p| p.len()).collect();
+        let min = lengths.iter().cloned().fold(std::f32::MAX, f32::min);
+        let max = lengths.iter().cloned().fold(0.0, f32::max);
+        assert!(max < 1.0 && max - min < 0.15);
+    }
+
+    #[test]
+    fn test_boundary() {
+        // a flat grid of 2x2 quads stays flat and keeps its corners
+        let mut grid = Polygons::default();
+        for y in 0..3 {
+            for x in 0..3 {
+                grid.positions.push(Vec3::new(x, y, 0));
+            }
+        }
+        for y in 0..2 {
+            for x in 0..2 {
+                let i = (y * 3 + x) as u32;
+                grid.faces.push(vec![i, i + 1, i + 4, i + 3]);
+                grid.texcoords.push(vec![
+                    Vec3::new(x, y, 0),
+                    Vec3::new(x + 1, y, 0),
+                    Vec3::new(x + 1, y + 1, 0),
+                    Vec3::new(x, y + 1, 0),
+                ]);
+            }
+        }
+        let data = Subdivision::new(Scheme::CatmullClark, 2).apply(&grid);
+        assert!(data.positions.iter().all(|p| p.z == 0.0));
+        assert!(data.positions.contains(&Vec3::new(2, 2, 0)));
+        assert!(data.positions.contains(&Vec3::new(0, 2, 0)));
+        assert!(data.positions.iter().all(|p| p.x >= 0.0 && p.x <= 2.0));
+        assert_eq!(data.texcoords.len(), data.positions.len());
+    }
+
+    #[test]
+    fn test_from_triangles() {
+        // a quad split into a fan, with a uv seam down one edge
+        let data = MeshData {
+            positions: vec![
+                Vec3::new(0, 0, 0),
+                Vec3::new(1, 0, 0),
+                Vec3::new(1, 1, 0),
+                Vec3::new(0, 1, 0),
+                Vec3::new(0, 0, 0),
+            ],
+            texcoords: vec![Vec3::zero(); 5],
+            indices: vec![0, 1, 2, 4, 2, 3],
+            ..MeshData::default()
+        };
+        let quads = Polygons::from_triangles(&data, Some(&[4]));
+        assert_eq!(quads.positions.len(), 4);
+        assert_eq!(quads.faces, vec![vec![0, 1, 2, 3]]);
+        assert_eq!(quads.texcoords.len(), 1);
+        let triangles = Polygons::from_triangles(&data, None);
+        assert_eq!(triangles.faces, vec![vec![0, 1, 2], vec![0, 2, 3]]);
+    }
+}
@@ -0,0 +1,269 @@
+use super::aabb::AABB;
+use super::{azimuth, HitRecord, Hitable};
+use crate::material::Material;
+use crate::ray::Ray;
+use crate::scene::Color;
+use crate::vec3::Vec3;
+use std::f32::consts::PI;
+use std::sync::Arc;
+
+#[macro_export]
+macro_rules! torus {
+    ($center:expr, $major:expr, $minor:expr, $mat:expr) => {
+        Box::new(Torus::new(
+            $center.into(),
+            $major as f32,
+            $minor as f32,
+            $mat,
+        ))
+    };
+}
+
+/// A ring lying flat in the xz plane. `major` is the distance from the center
+/// to the middle of the tube and `minor` is the radius of the tube.
+#[derive(Debug, Clone)]
+pub struct Torus {
+    center: Vec3,
+    major: f32,
+    minor: f32,
+    material: Arc<dyn Material>,
+}
+
+impl Torus {
+    pub fn new(
+        center: Vec3,
+        major: f32,
+        minor: f32,
+        material: Arc<dyn Material>,
+    ) -> Self {
+        Torus {
+            center,
+            major,
+            minor,
+            material,
+        }
+    }
+}
+
+impl Hitable for Torus {
+    /// Points on the torus satisfy (|p|^2 + R^2 - r^2)^2 = 4 R^2 (x^2 + z^2),
+    /// which along a ray is a quartic in t
+    fn hit(&self, r: Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+        if !self.get_bb().hit(r, t_min, t_max) {
+            return None;
+        }
+        // starting the ray just outside the torus and giving it unit length
+        // keeps the coefficients small, which the quartic needs for precision
+        let len = r.dir.len();
+        let dir = r.dir / len;
+        let origin = r.origin - self.center;
+        let start = (-origin.dot(&dir) - self.major - self.minor).max(0.0);
+        let o = origin + dir * start;
+        let (ox, oy, oz) = (f64::from(o.x), f64::from(o.y), f64::from(o.z));
+        let (dx, dy, dz) = (f64::from(dir.x), f64::from(dir.y), f64::from(dir.z));
+        let big = f64::from(self.major).powi(2);
+        let small = f64::from(self.minor).powi(2);
+        let od = ox * dx + oy * dy + oz * dz;
+        let k = ox * ox + oy * oy + oz * oz + big - small;
+        let coefficients = [
+            k * k - 4.0 * big * (ox * ox + oz * oz),
+            4.0 * od * k - 8.0 * big * (ox * dx + oz * dz),
+            4.0 * od * od + 2.0 * k - 4.0 * big * (dx * dx + dz * dz),
+            4.0 * od,
+            1.0,
+        ];
+        let t = solve_quartic(coefficients)
+            .into_iter()
+            .map(|s| (f64::from(start) + s) as f32 / len)
+            .filter(|&t| t > t_min && t < t_max)
+            .fold(None, |closest: Option<f32>, t| {
+                Some(closest.map_or(t, |c| c.min(t)))
+            })?;
+        let point = r.point_at_param(t);
+        let p = point - self.center;
+        let ring = (p.x * p.x + p.z * p.z).sqrt();
+        // away from the closest point on the circle through the tube
+        let normal = if ring > 0.0 {
+            (p - Vec3::new(p.x, 0.0, p.z) * (self.major / ring)).normalize()
+        } else {
+            Vec3::new(0, p.y.signum(), 0)
+        };
+        Some(HitRecord {
+            t,
+            u: azimuth(p),
+            v: (p.y.atan2(ring - self.major) + PI) / (2.0 * PI),
+            point,
+            normal,
+            geometric_normal: normal,
+            front_face: r.dir.dot(&normal) < 0.0,
+            tangent: Vec3::zero(),
+            color: Color::from(1),
+            material: self.material.as_ref(),
+        })
+    }
+
+    fn get_bb(&self) -> AABB {
+        let outer = self.major + self.minor;
+        let extent = Vec3::new(outer, self.minor, outer);
+        AABB::new(self.center - extent, self.center + extent)
+    }
+}
+
+const EPSILON: f64 = 1e-9;
+
+fn is_zero(x: f64) -> bool {
+    x.abs() < EPSILON
+}
+
+/// Real roots of c[2] x^2 + c[1] x + c[0]
+fn solve_quadratic(c: [f64; 3]) -> Vec<f64> {
+    let p = c[1] / (2.0 * c[2]);
+    let q = c[0] / c[2];
+    let discriminant = p * p - q;
+    if is_zero(discriminant) {
+        vec![-p]
+    } else if discriminant < 0.0 {
+        Vec::new()
+    } else {
+        let root = discriminant.sqrt();
+        vec![root - p, -root - p]
+    }
+}
+
+/// Real roots of c[3] x^3 + c[2] x^2 + c[1] x + c[0] with Cardano's method
+fn solve_cubic(c: [f64; 4]) -> Vec<f64> {
+    let a = c[2] / c[3];
+    let b = c[1] / c[3];
+    let c = c[0] / c[3];
+    // substitute x = y - a/3 to get y^3 + 3p y + 2q = 0
+    let p = (b - a * a / 3.0) / 3.0;
+    let q = (2.0 / 27.0 * a * a * a - a * b / 3.0 + c) / 2.0;
+    let discriminant = q * q + p * p * p;
+    let roots = if is_zero(discriminant) {
+        if is_zero(q) {
+            vec![0.0]
+        } else {
+            let u = (-q).cbrt();
+            vec![2.0 * u, -u]
+        }
+    } else if discriminant < 0.0 {
+        // three real roots
+        let phi = (-q / (-p * p * p).sqrt()).acos() / 3.0;
+        let t = 2.0 * (-p).sqrt();
+        vec![
+            t * phi.cos(),
+            -t * (phi + std::f64::consts::PI / 3.0).cos(),
+            -t * (phi - std::f64::consts::PI / 3.0).cos(),
+        ]
+    } else {
+        let root = discriminant.sqrt();
+        vec![(root - q).cbrt() - (root + q).cbrt()]
+    };
+    roots.into_iter().map(|y| y - a / 3.0).collect()
+}
+
+/// Real roots of c[4] x^4 + ... + c[0] with Ferrari's method, after Jochen
+/// Schwarze's solver in Graphics Gems. Each root gets a couple of Newton
+/// steps since the closed form loses a few digits.
+fn solve_quartic(c: [f64; 5]) -> Vec<f64> {
+    let a = c[3] / c[4];
+    let b = c[2] / c[4];
+    let cc = c[1] / c[4];
+    let d = c[0] / c[4];
+    // substitute x = y - a/4 to get y^4 + p y^2 + q y + r = 0
+    let a2 = a * a;
+    let p = -3.0 / 8.0 * a2 + b;
+    let q = a2 * a / 8.0 - a * b / 2.0 + cc;
+    let r = -3.0 / 256.0 * a2 * a2 + a2 * b / 16.0 - a * cc / 4.0 + d;
+    let roots = if is_zero(r) {
+        // y (y^3 + p y + q) = 0
+        let mut roots = solve_cubic([q, p, 0.0, 1.0]);
+        roots.push(0.0);
+        roots
+    } else {
+        // any root of the resolvent cubic splits it into two quadratics
+        let z = solve_cubic([r * p / 2.0 - q * q / 8.0, -r, -p / 2.0, 1.0])[0];
+        let u = z * z - r;
+        let v = 2.0 * z - p;
+        let u = if is_zero(u) {
+            0.0
+        } else if u > 0.0 {
+            u.sqrt()
+        } else {
+            return Vec::new();
+        };
+        let v = if is_zero(v) {
+            0.0
+        } else if v > 0.0 {
+            v.sqrt()
+        } else {
+            return Vec::new();
+        };
+        let v = if q < 0.0 { -v } else { v };
+        let mut roots = solve_quadratic([z - u, v, 1.0]);
+        roots.extend(solve_quadratic([z + u, -v, 1.0]));
+        roots
+    };
+    let f = |x: f64| (((c[4] * x + c[3]) * x + c[2]) * x + c[1]) * x + c[0];
+    let df = |x: f64| ((4.0 * c[4] * x + 3.0 * c[3]) * x + 2.0 * c[2]) * x + c[1];
+    roots
+        .into_iter()
+        .map(|y| {
+            let mut x = y - a / 4.0;
+            for _ in 0..2 {
+                let slope = df(x);
+                if slope != 0.0 {
+                    x -= f(x) / slope;
+                }
+            }
+            x
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::specular::Specular;
+
+    #[test]
+    fn test_quartic() {
+        // (x - 1)(x + 2)(x - 3)(x + 0.5)
+        let mut roots = solve_quartic([3.0, 3.5, -6.0, -1.5, 1.0]);
+        roots.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        let expected = [-2.0, -0.5, 1.0, 3.0];
+        assert_eq!(roots.len(), 4);
+        for (root, expected) in roots.iter().zip(&expected) {
+            assert!((root - expected).abs() < 1e-9);
+        }
+        // x^4 + 1 has no real roots
+        assert!(solve_quartic([1.0, 0.0, 0.0, 0.0, 1.0]).is_empty());
+    }
+
+    #[test]
+    fn test_hit() {
+        let material = Arc::new(Specular::new(Color::from(1), 0.0));
+        let torus = Torus::new(Vec3::new(0, 1, 0), 2.0, 0.5, material);
+        // straight through the hole
+        let down = Ray::new(Vec3::new(0, 5, 0), Vec3::new(0, -1, 0));
+        assert!(torus.hit(down, 0.0, std::f32::MAX).is_none());
+        // onto the top of the tube
+        let down = Ray::new(Vec3::new(2, 5, 0), Vec3::new(0, -2, 0));
+        let hit = torus.hit(down, 0.0, std::f32::MAX).unwrap();
+        assert!((hit.t - 1.75).abs() < 1e-5);
+        assert!((hit.normal - Vec3::new(0, 1, 0)).len() < 1e-4);
+        // across the whole ring from far away, hitting the outside first
+        let across = Ray::new(Vec3::new(-1000, 1, 0), Vec3::new(1, 0, 0));
+        let hit = torus.hit(across, 0.0, std::f32::MAX).unwrap();
+        assert!((hit.t - 997.5).abs() < 1e-3);
+        assert!((hit.normal - Vec3::new(-1, 0, 0)).len() < 1e-4);
+        // and from inside the tube it hits the inner wall of the hole
+        let hit = torus.hit(across, 998.0, std::f32::MAX).unwrap();
+        assert!((hit.t - 998.5).abs() < 1e-3);
+        assert!(!hit.front_face);
+        assert_eq!(
+            torus.get_bb(),
+            AABB::new(Vec3::new(-2.5, 0.5, -2.5), Vec3::new(2.5, 1.5, 2.5))
+        );
+    }
+}
@@ -1,6 +1,9 @@
 use super::aabb::AABB;
+use super::volume::Segment;
 use super::{HitRecord, Hitable};
 use crate::axis::Axis;
+use crate::matrix::Matrix4;
+use crate::quaternion::Quaternion;
 use crate::ray::Ray;
 use crate::vec3::Vec3;
 use itertools::iproduct;
@@ -26,6 +29,40 @@ macro_rules! rotate {
     };
 }
 
+#[macro_export]
+macro_rules! orient {
+    ($obj:expr, $rotation:expr) => {
+        Box::new(Orient::new(*$obj, $rotation))
+    };
+}
+
+#[macro_export]
+macro_rules! transform {
+    ($obj:expr, $matrix:expr) => {
+        Box::new(
+            Transform::new(*$obj, $matrix).expect("transform matrix must be invertible"),
+        )
+    };
+}
+
+/// Bounds an object's box after moving it through `f` by transforming each of
+/// its 8 corners. This contains everything in the box for affine maps, but
+/// it's the bound of the moved box rather than of the object, so rotations
+/// make it looser than the object's own.
+fn transform_bb(bb: AABB, f: impl Fn(Vec3) -> Vec3) -> AABB {
+    let corners = iproduct!(0..2, 0..2, 0..2).map(|(i, j, k)| {
+        f(Vec3::new(
+            i as f32 * bb.max.x + (1 - i) as f32 * bb.min.x,
+            j as f32 * bb.max.y + (1 - j) as f32 * bb.min.y,
+            k as f32 * bb.max.z + (1 - k) as f32 * bb.min.z,
+        ))
+    });
+    let first = f(bb.min);
+    corners.fold(AABB::new(first, first), |acc, v| {
+        acc.combine(&AABB::new(v, v))
+    })
+}
+
 #[derive(Debug)]
 pub struct FlipNormal<T: Hitable> {
     obj: T,
@@ -41,6 +78,8 @@ impl<T: Hitable> Hitable for FlipNormal<T> {
     fn hit(&self, r: Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
         if let Some(mut temp) = self.obj.hit(r, t_min, t_max) {
             temp.normal = -temp.normal;
+            temp.geometric_normal = -temp.geometric_normal;
+            temp.front_face = !temp.front_face;
             Some(temp)
         } else {
             None
@@ -49,6 +88,18 @@ impl<T: Hitable> Hitable for FlipNormal<T> {
     fn get_bb(&self) -> AABB {
         self.obj.get_bb()
     }
+    fn has_media(&self) -> bool {
+        self.obj.has_media()
+    }
+    fn media<'a>(&'a self, r: Ray, t_min: f32, t_max: f32, found: &mut Vec<Segment<'a>>) {
+        self.obj.media(r, t_min, t_max, found)
+    }
+    fn sample_towards(&self, origin: Vec3) -> Option<Vec3> {
+        self.obj.sample_towards(origin)
+    }
+    fn pdf_towards(&self, origin: Vec3, dir: Vec3) -> f32 {
+        self.obj.pdf_towards(origin, dir)
+    }
 }
 
 #[derive(Debug)]
@@ -61,13 +112,15 @@ impl<T: Hitable> Translate<T> {
     pub fn new(obj: T, offset: Vec3) -> Self {
         Translate { obj, offset }
     }
+
+    fn local(&self, r: Ray) -> Ray {
+        Ray::new(r.origin - self.offset, r.dir)
+    }
 }
 
 impl<T: Hitable> Hitable for Translate<T> {
     fn hit(&self, r: Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
-        let mut moved_r = r;
-        moved_r.origin -= self.offset;
-        if let Some(mut temp) = self.obj.hit(moved_r, t_min, t_max) {
+        if let Some(mut temp) = self.obj.hit(self.local(r), t_min, t_max) {
             temp.point += self.offset;
             Some(temp)
         } else {
@@ -78,6 +131,18 @@ impl<T: Hitable> Hitable for Translate<T> {
         let temp = self.obj.get_bb();
         AABB::new(temp.min + self.offset, temp.max + self.offset)
     }
+    fn has_media(&self) -> bool {
+        self.obj.has_media()
+    }
+    fn media<'a>(&'a self, r: Ray, t_min: f32, t_max: f32, found: &mut Vec<Segment<'a>>) {
+        self.obj.media(self.local(r), t_min, t_max, found)
+    }
+    fn sample_towards(&self, origin: Vec3) -> Option<Vec3> {
+        self.obj.sample_towards(origin - self.offset)
+    }
+    fn pdf_towards(&self, origin: Vec3, dir: Vec3) -> f32 {
+        self.obj.pdf_towards(origin - self.offset, dir)
+    }
 }
 
 #[derive(Debug)]
@@ -93,37 +158,80 @@ impl<T: Hitable> Rotate<T> {
     /// angle is in degrees
     pub fn new(obj: T, axis: Axis, angle: f32) -> Self {
         let rad = angle * std::f32::consts::PI / 180.0;
-        let mut temp = Rotate {
+        let (sin_theta, cos_theta) = (rad.sin(), rad.cos());
+        let bb = transform_bb(obj.get_bb(), |p| p.rotate(axis, cos_theta, sin_theta));
+        Rotate {
             obj,
             axis,
-            sin_theta: rad.sin(),
-            cos_theta: rad.cos(),
-            bb: AABB::default(),
-        };
-        // go through every point in the bounding box and compute its rotated position
-        // then form a new bounding box by expanding one to fit all those points
-        iproduct!(0..2, 0..2, 0..2).for_each(|(i, j, k)| {
-            let point = Vec3::new(
-                i as f32 * temp.bb.max.x + (1 - i) as f32 * temp.bb.min.x,
-                j as f32 * temp.bb.max.y + (1 - j) as f32 * temp.bb.min.y,
-                k as f32 * temp.bb.max.z + (1 - k) as f32 * temp.bb.min.z,
-            );
-            let v = point.rotate(axis, temp.cos_theta, temp.sin_theta);
-            temp.bb = temp.bb.combine(&AABB::new(v, v));
-        });
-        temp
+            sin_theta,
+            cos_theta,
+            bb,
+        }
+    }
+
+    fn local(&self, r: Ray) -> Ray {
+        Ray::new(
+            r.origin.rotate(self.axis, self.cos_theta, -self.sin_theta),
+            r.dir.rotate(self.axis, self.cos_theta, -self.sin_theta),
+        )
     }
 }
 
 impl<T: Hitable> Hitable for Rotate<T> {
     fn hit(&self, r: Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
-        let rotated_r = Ray::new(
-            r.origin.rotate(self.axis, self.cos_theta, -self.sin_theta),
-            r.dir.rotate(self.axis, self.cos_theta, -self.sin_theta),
-        );
-        if let Some(mut rec) = self.obj.hit(rotated_r, t_min, t_max) {
+        if let Some(mut rec) = self.obj.hit(self.local(r), t_min, t_max) {
             rec.point = rec.point.rotate(self.axis, self.cos_theta, self.sin_theta);
             rec.normal = rec.normal.rotate(self.axis, self.cos_theta, self.sin_theta);
+            rec.geometric_normal =
+                rec.geometric_normal
+                    .rotate(self.axis, self.cos_theta, self.sin_theta);
+            rec.tangent = rec
+                .tangent
+                .rotate(self.axis, self.cos_theta, self.sin_theta);
+            Some(rec)
+        } else {
+            None
+        }
+    }
+    fn get_bb(&self) -> AABB {
+        self.bb
+    }
+    fn has_media(&self) -> bool {
+        self.obj.has_media()
+    }
+    fn media<'a>(&'a self, r: Ray, t_min: f32, t_max: f32, found: &mut Vec<Segment<'a>>) {
+        self.obj.media(self.local(r), t_min, t_max, found)
+    }
+}
+
+/// Rotates an object by an arbitrary quaternion rather than about a single axis
+#[derive(Debug)]
+pub struct Orient<T: Hitable> {
+    obj: T,
+    rotation: Quaternion,
+    bb: AABB,
+}
+
+impl<T: Hitable> Orient<T> {
+    pub fn new(obj: T, rotation: Quaternion) -> Self {
+        let rotation = rotation.normalize();
+        let bb = transform_bb(obj.get_bb(), |p| rotation.rotate(p));
+        Orient { obj, rotation, bb }
+    }
+
+    fn local(&self, r: Ray) -> Ray {
+        let inverse = self.rotation.conjugate();
+        Ray::new(inverse.rotate(r.origin), inverse.rotate(r.dir))
+    }
+}
+
+impl<T: Hitable> Hitable for Orient<T> {
+    fn hit(&self, r: Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+        if let Some(mut rec) = self.obj.hit(self.local(r), t_min, t_max) {
+            rec.point = self.rotation.rotate(rec.point);
+            rec.normal = self.rotation.rotate(rec.normal);
+            rec.geometric_normal = self.rotation.rotate(rec.geometric_normal);
+            rec.tangent = self.rotation.rotate(rec.tangent);
             Some(rec)
         } else {
             None
@@ -132,4 +240,150 @@ impl<T: Hitable> Hitable for Rotate<T> {
     fn get_bb(&self) -> AABB {
         self.bb
     }
+    fn has_media(&self) -> bool {
+        self.obj.has_media()
+    }
+    fn media<'a>(&'a self, r: Ray, t_min: f32, t_max: f32, found: &mut Vec<Segment<'a>>) {
+        self.obj.media(self.local(r), t_min, t_max, found)
+    }
+}
+
+/// Applies an arbitrary affine transformation. Rays are brought into object
+/// space with the inverse matrix, and since the direction isn't renormalized
+/// the hit distance is the same in both spaces.
+#[derive(Debug)]
+pub struct Transform<T: Hitable> {
+    obj: T,
+    matrix: Matrix4,
+    inverse: Matrix4,
+    /// inverse transpose, which keeps normals perpendicular to the surface
+    /// under non-uniform scale and shear
+    normal_matrix: Matrix4,
+    bb: AABB,
+}
+
+impl<T: Hitable> Transform<T> {
+    /// `None` when the matrix flattens things, like a scale of 0, since rays
+    /// can't be brought back into object space
+    pub fn new(obj: T, matrix: Matrix4) -> Option<Self> {
+        let inverse = matrix.inverse()?;
+        let bb = transform_bb(obj.get_bb(), |p| matrix.transform_point(p));
+        Some(Transform {
+            obj,
+            matrix,
+            inverse,
+            normal_matrix: inverse.transpose(),
+            bb,
+        })
+    }
+
+    fn local(&self, r: Ray) -> Ray {
+        Ray::new(
+            self.inverse.transform_point(r.origin),
+            self.inverse.transform_vector(r.dir),
+        )
+    }
+}
+
+impl<T: Hitable> Hitable for Transform<T> {
+    fn hit(&self, r: Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+        if let Some(mut rec) = self.obj.hit(self.local(r), t_min, t_max) {
+            rec.point = self.matrix.transform_point(rec.point);
+            rec.normal = self.normal_matrix.transform_vector(rec.normal).normalize();
+            // the dot product with the ray is unchanged by the inverse
+            // transpose, so front_face still holds
+            rec.geometric_normal = self
+                .normal_matrix
+                .transform_vector(rec.geometric_normal)
+                .normalize();
+            // tangents run along the surface so they stretch with it
+            let tangent = self.matrix.transform_vector(rec.tangent);
+            if tangent.len() > 0.0 {
+                rec.tangent = tangent.normalize();
+            }
+            Some(rec)
+        } else {
+            None
+        }
+    }
+    fn get_bb(&self) -> AABB {
+        self.bb
+    }
+    fn has_media(&self) -> bool {
+        self.obj.has_media()
+    }
+    fn media<'a>(&'a self, r: Ray, t_min: f32, t_max: f32, found: &mut Vec<Segment<'a>>) {
+        self.obj.media(self.local(r), t_min, t_max, found)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometry::sphere::Sphere;
+    use crate::material::specular::Specular;
+    use std::sync::Arc;
+
+    fn unit_sphere() -> Sphere {
+        Sphere::new(
+            Vec3::zero(),
+            1.0,
+            Arc::new(Specular::new(Vec3::from(1), 0.0)),
+        )
+    }
+
+    #[test]
+    fn test_scaled_hit() {
+        let stretched =
+            Transform::new(unit_sphere(), Matrix4::scale(Vec3::new(2, 1, 1))).unwrap();
+        let hit = stretched
+            .hit(
+                Ray::new(Vec3::new(-5, 0, 0), Vec3::new(1, 0, 0)),
+                0.0,
+                std::f32::MAX,
+            )
+            .unwrap();
+        assert!((hit.t - 3.0).abs() < 1e-5);
+        assert!((hit.point - Vec3::new(-2, 0, 0)).len() < 1e-5);
+        assert!((hit.normal - Vec3::new(-1, 0, 0)).len() < 1e-5);
+    }
+
+    #[test]
+    fn test_stretched_normal() {
+        // the normal of an ellipsoid is its gradient, not the sphere's normal
+        // stretched along with the surface
+        let stretched =
+            Transform::new(unit_sphere(), Matrix4::scale(Vec3::new(4, 1, 1))).unwrap();
+        let hit = stretched
+            .hit(
+                Ray::new(Vec3::new(2, 5, 0), Vec3::new(0, -1, 0)),
+                0.0,
+                std::f32::MAX,
+            )
+            .unwrap();
+        let gradient = Vec3::new(hit.point.x / 16.0, hit.point.y, 0.0).normalize();
+        assert!((hit.normal - gradient).len() < 1e-4);
+    }
+
+    #[test]
+    fn test_bb() {
+        let moved = Transform::new(
+            unit_sphere(),
+            Matrix4::translate(Vec3::new(5, 0, 0))
+                * Matrix4::rotate(Vec3::new(0, 0, 1), 45.0)
+                * Matrix4::scale(Vec3::new(2, 1, 1)),
+        )
+        .unwrap();
+        let bb = moved.get_bb();
+        let extent = 3.0 / 2.0f32.sqrt();
+        assert!((bb.max - Vec3::new(5.0 + extent, extent, 1.0)).len() < 1e-4);
+        assert!((bb.min - Vec3::new(5.0 - extent, -extent, -1.0)).len() < 1e-4);
+    }
+
+    #[test]
+    fn test_singular() {
+        assert!(
+            Transform::new(unit_sphere(), Matrix4::scale(Vec3::new(1, 1, 0))).is_none()
+        );
+    }
 }
@@ -0,0 +1,331 @@
+use super::aabb::AABB;
+use super::mesh::{intersect, MeshData};
+use super::{HitRecord, Hitable};
+use crate::axis::Axis;
+use crate::material::Material;
+use crate::matrix::Matrix4;
+use crate::ray::Ray;
+use crate::scene::Color;
+use crate::vec3::Vec3;
+use std::sync::Arc;
+
+#[macro_export]
+macro_rules! triangle_mesh {
+    ($data:expr, $transform:expr, $mat:expr) => {
+        Box::new(TriangleMesh::new(&$data, $transform, $mat))
+    };
+}
+
+/// Triangles in a leaf before the BVH stops splitting
+const LEAF_SIZE: usize = 4;
+/// Past this depth nodes are split in half, which keeps the depth bounded
+/// for the traversal stack no matter how lopsided the surface area splits get
+const MAX_SAH_DEPTH: usize = 64;
+
+#[derive(Debug, Copy, Clone)]
+struct Node {
+    bb: AABB,
+    /// First triangle for leaves, index of the second child for interior
+    /// nodes. The first child always comes right after its parent.
+    offset: u32,
+    /// Number of triangles in a leaf, 0 for interior nodes
+    count: u32,
+}
+
+/// A whole mesh as one object. Vertex attributes live in shared arrays,
+/// triangles are 3 indices, and the BVH is a flat array of nodes over ranges
+/// of triangles, so there's no per triangle allocation or dynamic dispatch.
+#[derive(Debug)]
+pub struct TriangleMesh {
+    positions: Vec<Vec3>,
+    normals: Vec<Vec3>,
+    /// Empty if the mesh doesn't have any
+    texcoords: Vec<Vec3>,
+    /// Empty if the mesh doesn't have any
+    colors: Vec<Color>,
+    /// Sorted so each leaf's triangles are contiguous
+    triangles: Vec<[u32; 3]>,
+    nodes: Vec<Node>,
+    material: Arc<dyn Material>,
+    /// Skip hits on the back of triangles, see `Triangle::with_culling`
+    cull: bool,
+}
+
+struct Primitive {
+    triangle: [u32; 3],
+    bb: AABB,
+    centroid: Vec3,
+}
+
+impl TriangleMesh {
+    pub fn new(data: &MeshData, transform: Matrix4, material: Arc<dyn Material>) -> Self {
+        let data = data.transformed(transform);
+        let mut primitives: Vec<Primitive> = data
+            .indices
+            .chunks(3)
+            .map(|i| {
+                let p = |j: usize| data.positions[i[j] as usize];
+                let bb = AABB::new(p(0).piecewise_min(&p(1)), p(0).piecewise_max(&p(1)))
+                    .combine(&AABB::new(p(2), p(2)));
+                Primitive {
+                    triangle: [i[0], i[1], i[2]],
+                    bb,
+                    centroid: (p(0) + p(1) + p(2)) / 3.0,
+                }
+            })
+            .collect();
+        let mut nodes = Vec::new();
+        if !primitives.is_empty() {
+            TriangleMesh::build(&mut nodes, &mut primitives, 0, 0);
+        }
+        TriangleMesh {
+            positions: data.positions,
+            normals: data.normals,
+            texcoords: data.texcoords,
+            colors: data.colors,
+            triangles: primitives.iter().map(|p| p.triangle).collect(),
+            nodes,
+            material,
+            cull: false,
+        }
+    }
+
+    pub fn with_culling(self, cull: bool) -> Self {
+        TriangleMesh { cull, ..self }
+    }
+
+    pub fn triangle_count(&self) -> usize {
+        self.triangles.len()
+    }
+
+    /// Splits along the widest axis of the centroids wherever the surface
+    /// area heuristic is lowest, same as `BVHNode`
+    fn build(
+        nodes: &mut Vec<Node>,
+        primitives: &mut [Primitive],
+        start: usize,
+        depth: usize,
+    ) {
+        let bb = primitives
+            .iter()
+            .fold(primitives[0].bb, |a, p| a.combine(&p.bb));
+        let index = nodes.len();
+        nodes.push(Node {
+            bb,
+            offset: start as u32,
+            count: primitives.len() as u32,
+        });
+        let n = primitives.len();
+        if n <= LEAF_SIZE {
+            return;
+        }
+        let centroids = primitives.iter().fold(
+            AABB::new(primitives[0].centroid, primitives[0].centroid),
+            |a, p| a.combine(&AABB::new(p.centroid, p.centroid)),
+        );
+        let size = centroids.max - centroids.min;
+        let axis = if size.x > size.y && size.x > size.z {
+            Axis::X
+        } else if size.y > size.z {
+            Axis::Y
+        } else {
+            Axis::Z
+        };
+        primitives.sort_unstable_by(|a, b| {
+            a.centroid
+                .get_axis(axis)
+                .partial_cmp(&b.centroid.get_axis(axis))
+                .unwrap_or(std::cmp::Ordering::Equal)
+        });
+        let split = if depth < MAX_SAH_DEPTH {
+            // right_area[i] covers primitives i.., the split puts i.. on the right
+            let mut right_area = vec![0.0; n];
+            let mut right = primitives[n - 1].bb;
+            for i in (1..n).rev() {
+                right = right.combine(&primitives[i].bb);
+                right_area[i] = right.surface_area();
+            }
+            let mut left = primitives[0].bb;
+            let mut best = (std::f32::MAX, n / 2);
+            for (i, area) in right_area.iter().enumerate().skip(1) {
+                let sah = i as f32 * left.surface_area() + (n - i) as f32 * area;
+                if sah < best.0 {
+                    best = (sah, i);
+                }
+                left = left.combine(&primitives[i].bb);
+            }
+            best.1
+        } else {
+            n / 2
+        };
+        let (left, right) = primitives.split_at_mut(split);
+        TriangleMesh::build(nodes, left, start, depth + 1);
+        let second = nodes.len();
+        TriangleMesh::build(nodes, right, start + split, depth + 1);
+        nodes[index].offset = second as u32;
+        nodes[index].count = 0;
+    }
+}
+
+impl Hitable for TriangleMesh {
+    fn hit(&self, r: Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+        if self.nodes.is_empty() {
+            return None;
+        }
+        let mut closest = None;
+        let mut t_max = t_max;
+        // the depth is bounded by MAX_SAH_DEPTH plus halving 2^32 triangles
+        let mut stack = [0u32; MAX_SAH_DEPTH + 34];
+        let mut len = 1;
+        while len > 0 {
+            len -= 1;
+            let index = stack[len] as usize;
+            let node = &self.nodes[index];
+            if !node.bb.hit(r, t_min, t_max) {
+                continue;
+            }
+            if node.count == 0 {
+                stack[len] = node.offset;
+                stack[len + 1] = index as u32 + 1;
+                len += 2;
+                continue;
+            }
+            let start = node.offset as usize;
+            for (i, tri) in self.triangles[start..start + node.count as usize]
+                .iter()
+                .enumerate()
+            {
+                let p = |j: usize| self.positions[tri[j] as usize];
+                if let Some((t, u, v)) =
+                    intersect(p(0), p(1), p(2), r, t_min, t_max, self.cull)
+                {
+                    t_max = t;
+                    closest = Some((start + i, t, u, v));
+                }
+            }
+        }
+        closest.map(|(i, t, u, v)| {
+            let tri = self.triangles[i];
+            let lerp = |values: &[Vec3]| {
+                values[tri[0] as usize] * (1.0 - u - v)
+                    + values[tri[1] as usize] * u
+                    + values[tri[2] as usize] * v
+            };
+            let p = |j: usize| self.positions[tri[j] as usize];
+            let geometric_normal = (p(1) - p(0)).cross(&(p(2) - p(0))).normalize();
+            let texture_coords = if self.texcoords.is_empty() {
+                Vec3::zero()
+            } else {
+                lerp(&self.texcoords)
+            };
+            HitRecord {
+                t,
+                u: texture_coords.x,
+                v: texture_coords.y,
+                point: lerp(&self.positions),
+                normal: lerp(&self.normals),
+                geometric_normal,
+                front_face: r.dir.dot(&geometric_normal) < 0.0,
+                tangent: Vec3::zero(),
+                color: if self.colors.is_empty() {
+                    Color::from(1)
+                } else {
+                    lerp(&self.colors)
+                },
+                material: self.material.as_ref(),
+            }
+        })
+    }
+
+    fn get_bb(&self) -> AABB {
+        self.nodes
+            .first()
+            .map_or_else(|| AABB::new(Vec3::zero(), Vec3::zero()), |n| n.bb)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bvh::BVHNode;
+    use crate::material::specular::Specular;
+
+    /// A bumpy grid with enough triangles to get a few levels of BVH
+    fn grid() -> MeshData {
+        let n = 20;
+        let mut data = MeshData::default();
+        for y in 0..=n {
+            for x in 0..=n {
+                let height = ((x * 7 + y * 3) % 5) as f32 / 10.0;
+                data.positions.push(Vec3::new(x as f32, y as f32, height));
+                data.colors.push(Color::new(x as f32, y as f32, 0.0));
+            }
+        }
+        for y in 0..n {
+            for x in 0..n {
+                let i = y * (n + 1) + x;
+                data.indices.extend_from_slice(&[
+                    i,
+                    i + 1,
+                    i + n + 2,
+                    i,
+                    i + n + 2,
+                    i + n + 1,
+                ]);
+            }
+        }
+        data
+    }
+
+    #[test]
+    fn test_matches_triangles() {
+        let material: Arc<dyn Material> = Arc::new(Specular::new(Color::from(1), 0.0));
+        let transform = Matrix4::rotate(Vec3::new(1, 2, 3), 20.0);
+        let mesh = TriangleMesh::new(&grid(), transform, material.clone());
+        assert_eq!(mesh.triangle_count(), 800);
+        let reference = BVHNode::from(&mut grid().triangles(transform, material));
+        for i in 0..200 {
+            let origin = transform.transform_point(Vec3::new(
+                (i % 17) as f32 * 1.3 - 1.0,
+                (i % 23) as f32 * 0.9 - 1.0,
+                -5.0,
+            ));
+            let dir = transform.transform_vector(Vec3::new(0.1, 0.05, 1));
+            let r = Ray::new(origin, dir);
+            let a = mesh.hit(r, 0.0, std::f32::MAX);
+            let b = reference.hit(r, 0.0, std::f32::MAX);
+            assert_eq!(a.is_some(), b.is_some());
+            if let (Some(a), Some(b)) = (a, b) {
+                assert!((a.t - b.t).abs() < 1e-4);
+                assert!((a.normal - b.normal).len() < 1e-4);
+                assert!((a.color - b.color).len() < 1e-3);
+            }
+        }
+    }
+
+    #[test]
+    fn test_culling() {
+        let material: Arc<dyn Material> = Arc::new(Specular::new(Color::from(1), 0.0));
+        let mesh = TriangleMesh::new(&grid(), Matrix4::identity(), material);
+        let below = Ray::new(Vec3::new(3.3, 4.6, -1.0), Vec3::new(0, 0, 1));
+        let hit = mesh.hit(below, 0.0, std::f32::MAX).unwrap();
+        assert!(!hit.front_face);
+        assert!(hit.geometric_normal.z > 0.0);
+        let above = Ray::new(Vec3::new(3.3, 4.6, 1.0), Vec3::new(0, 0, -1));
+        assert!(mesh.hit(above, 0.0, std::f32::MAX).unwrap().front_face);
+
+        let mesh = mesh.with_culling(true);
+        assert!(mesh.hit(below, 0.0, std::f32::MAX).is_none());
+        assert!(mesh.hit(above, 0.0, std::f32::MAX).is_some());
+    }
+
+    #[test]
+    fn test_bb() {
+        let material = Arc::new(Specular::new(Color::from(1), 0.0));
+        let mesh = TriangleMesh::new(&grid(), Matrix4::identity(), material);
+        assert_eq!(
+            mesh.get_bb(),
+            AABB::new(Vec3::new(0, 0, 0), Vec3::new(20, 20, 0.4))
+        );
+    }
+}
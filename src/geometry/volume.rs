@@ -1,7 +1,11 @@
 use super::aabb::AABB;
+use super::csg::intervals;
+use super::voxel::VoxelGrid;
 use super::{HitRecord, Hitable};
 use crate::material::Material;
 use crate::ray::Ray;
+use crate::scene::Color;
+use crate::texture::Texture;
 use crate::vec3::Vec3;
 use rand::random;
 use std::sync::Arc;
@@ -13,14 +17,63 @@ macro_rules! volume {
     };
 }
 
+/// How thick a volume is at each point, on top of its overall density
+#[derive(Debug, Clone)]
+pub enum Density {
+    Uniform,
+    /// The brightness of a texture like `Perlin`, which must never go over
+    /// the given maximum
+    Texture(Arc<dyn Texture>, f32),
+    /// Voxel values blended between voxel centers, with 255 as 1
+    Voxels(Arc<VoxelGrid>),
+}
+
+impl Density {
+    fn at(&self, p: Vec3) -> f32 {
+        match self {
+            Density::Uniform => 1.0,
+            Density::Texture(texture, max) => {
+                let c = texture.value(0.0, 0.0, p);
+                ((c.x + c.y + c.z) / 3.0).max(0.0).min(*max)
+            }
+            Density::Voxels(grid) => grid.sample(p),
+        }
+    }
+
+    fn max(&self) -> f32 {
+        match self {
+            Density::Uniform => 1.0,
+            Density::Texture(_, max) => *max,
+            Density::Voxels(grid) => grid.highest() as f32 / 255.0,
+        }
+    }
+}
+
+/// A medium like fog or smoke filling a closed boundary of any shape. Rays
+/// can scatter anywhere inside it, in a direction picked by the phase
+/// function, which is `Isotropic` or one of the ones in `material::phase`.
+///
+/// It never gets hit like a surface. Instead rays get tracked through it up
+/// to whatever they do hit, so anything inside it or behind it gets tinted
+/// by it too. Distances to the next scattering are sampled with delta
+/// tracking against the densest point, so the density can vary. When
+/// absorption and scattering differ between channels, the colors are carried
+/// along with spectral tracking instead.
 #[derive(Debug)]
 pub struct Volume {
     density: f32,
+    field: Density,
+    /// How much of each channel gets absorbed and scattered per unit of
+    /// length at a density of 1
+    absorption: Color,
+    scattering: Color,
     boundary: Box<dyn Hitable>,
     phase_function: Arc<dyn Material>,
 }
 
 impl Volume {
+    /// A uniform volume that only scatters, leaving the color up to the
+    /// phase function
     pub fn new(
         density: f32,
         boundary: Box<dyn Hitable>,
@@ -28,39 +81,345 @@ impl Volume {
     ) -> Self {
         Volume {
             density,
+            field: Density::Uniform,
+            absorption: Color::zero(),
+            scattering: Color::from(1),
             boundary,
             phase_function,
         }
     }
+
+    /// Scales the density at each point by a texture or voxel grid
+    pub fn with_field(mut self, field: Density) -> Self {
+        self.field = field;
+        self
+    }
+
+    /// Smoke mostly absorbs while clouds barely do. With colored
+    /// scattering, the phase function should usually be white.
+    pub fn with_coefficients(mut self, absorption: Color, scattering: Color) -> Self {
+        self.absorption = absorption;
+        self.scattering = scattering;
+        self
+    }
+
+    /// The most the medium could absorb and scatter at any point, per unit of
+    /// length
+    fn majorant(&self) -> f32 {
+        let extinction = self.absorption + self.scattering;
+        self.density * self.field.max() * extinction.x.max(extinction.y).max(extinction.z)
+    }
 }
 
-impl Hitable for Volume {
-    fn hit(&self, r: Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
-        if let Some(mut hit1) = self.boundary.hit(r, std::f32::MIN, std::f32::MAX) {
-            if let Some(mut hit2) = self.boundary.hit(r, hit1.t + 0.0001, std::f32::MAX) {
-                hit1.t = hit1.t.max(t_min);
-                hit2.t = hit2.t.min(t_max);
-                if hit1.t > hit2.t {
-                    return None;
-                }
-                hit1.t = hit1.t.max(0.0);
-                let dist_inside_boundary = (hit2.t - hit1.t) * r.dir.len();
-                let hit_dist = -random::<f32>().log10() / self.density;
-                if hit_dist < dist_inside_boundary {
-                    return Some(HitRecord {
-                        t: hit1.t + hit_dist / r.dir.len(),
-                        u: 0.0, // arbitrary
-                        v: 0.0, // arbitrary
-                        point: r.point_at_param(hit1.t),
-                        normal: Vec3::zero(), // arbitrary
-                        material: self.phase_function.as_ref(),
-                    });
-                }
+/// A stretch of a ray through a medium, and what the medium is made of at
+/// each distance along it. Everything is per unit of the ray's parameter
+/// rather than of length, which transforms can change, so stretches from
+/// anywhere in the scene can be tracked together.
+pub struct Segment<'a> {
+    enter: f32,
+    exit: f32,
+    majorant: f32,
+    /// Absorption and scattering at a distance along the ray
+    coefficients: Box<dyn Fn(f32) -> (Color, Color) + 'a>,
+    phase_function: &'a dyn Material,
+}
+
+impl<'a> Segment<'a> {
+    pub(crate) fn new(
+        (enter, exit): (f32, f32),
+        majorant: f32,
+        coefficients: impl Fn(f32) -> (Color, Color) + 'a,
+        phase_function: &'a dyn Material,
+    ) -> Self {
+        Segment {
+            enter,
+            exit,
+            majorant,
+            coefficients: Box::new(coefficients),
+            phase_function,
+        }
+    }
+}
+
+fn average(c: Color) -> f32 {
+    (c.x + c.y + c.z) / 3.0
+}
+
+/// What happened to a ray going through media
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Event<'a> {
+    Absorbed,
+    /// Where it scattered, how much of each channel is left, and the phase
+    /// function of the medium it scattered in
+    Scattered(f32, Color, &'a dyn Material),
+    /// How much of each channel made it through, which is always 1 for gray
+    /// media
+    Through(Color),
+}
+
+/// Spectral delta tracking through all the media along a ray at once, so
+/// they can overlap and what's left of each channel after one carries on
+/// into the next. Each step goes to the next point where something could
+/// happen if the media were as thick as their majorants everywhere, then
+/// picks between absorbing, scattering and nothing by the absorption and
+/// scattering that are really there. Between every place a medium starts or
+/// stops, the majorant is the sum of the ones the ray is in.
+pub(crate) fn track<'a>(segments: &[Segment<'a>]) -> Event<'a> {
+    let mut bounds: Vec<f32> = segments
+        .iter()
+        .flat_map(|s| vec![s.enter, s.exit])
+        .collect();
+    bounds.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    bounds.dedup();
+    let mut weight = Color::from(1);
+    for window in bounds.windows(2) {
+        let (start, end) = (window[0], window[1]);
+        let inside: Vec<&Segment> = segments
+            .iter()
+            .filter(|s| s.enter <= start && s.exit >= end)
+            .collect();
+        let majorant: f32 = inside.iter().map(|s| s.majorant).sum();
+        if majorant <= 0.0 {
+            continue;
+        }
+        let coefficients = |t| {
+            inside
+                .iter()
+                .fold((Color::zero(), Color::zero()), |(a, s), segment| {
+                    let (absorption, scattering) = (segment.coefficients)(t);
+                    (a + absorption, s + scattering)
+                })
+        };
+        let mut t = start;
+        loop {
+            t -= (1.0 - random::<f32>()).ln() / majorant;
+            if t >= end {
+                break;
             }
+            let (absorption, scattering) = coefficients(t);
+            let null = Color::from(majorant) - absorption - scattering;
+            let (pa, ps, pn) = (
+                average(weight * absorption),
+                average(weight * scattering),
+                average(weight * null),
+            );
+            let total = pa + ps + pn;
+            let pick = random::<f32>() * total;
+            if total <= 0.0 || pick < pa {
+                return Event::Absorbed;
+            } else if pick < pa + ps {
+                weight = weight * scattering * (total / (ps * majorant));
+                let (phase_function, share) = pick_scatterer(&inside, t, scattering);
+                return Event::Scattered(t, weight * share, phase_function);
+            }
+            weight = weight * null * (total / (pn * majorant));
         }
+    }
+    Event::Through(weight)
+}
+
+/// Which of the overlapping media a ray scattered in, picked by how much
+/// each scatters at `t`, along with what to weight it by to only count that
+/// one's part of the total `scattering`
+fn pick_scatterer<'a>(
+    inside: &[&Segment<'a>],
+    t: f32,
+    scattering: Color,
+) -> (&'a dyn Material, Color) {
+    if inside.len() == 1 {
+        return (inside[0].phase_function, Color::from(1));
+    }
+    let each: Vec<Color> = inside.iter().map(|s| (s.coefficients)(t).1).collect();
+    let mut pick = random::<f32>() * average(scattering);
+    let mut i = 0;
+    while i + 1 < each.len() && pick >= average(each[i]) {
+        pick -= average(each[i]);
+        i += 1;
+    }
+    let part = |a: f32, b: f32| if b > 0.0 { a / b } else { 0.0 };
+    let fraction = Color::new(
+        part(each[i].x, scattering.x),
+        part(each[i].y, scattering.y),
+        part(each[i].z, scattering.z),
+    );
+    let share = part(average(each[i]), average(scattering));
+    let weight = if share > 0.0 {
+        fraction / share
+    } else {
+        Color::zero()
+    };
+    (inside[i].phase_function, weight)
+}
+
+impl Hitable for Volume {
+    /// Media aren't surfaces, rays only stop in them through `media`
+    fn hit(&self, _r: Ray, _t_min: f32, _t_max: f32) -> Option<HitRecord> {
         None
     }
+
     fn get_bb(&self) -> AABB {
         self.boundary.get_bb()
     }
+
+    fn has_media(&self) -> bool {
+        true
+    }
+
+    fn media<'a>(&'a self, r: Ray, t_min: f32, t_max: f32, found: &mut Vec<Segment<'a>>) {
+        let majorant = self.majorant();
+        if majorant <= 0.0 {
+            return;
+        }
+        // the boundary can be entered and left any number of times, and the
+        // ray might start inside it
+        let len = r.dir.len();
+        for span in intervals(self.boundary.as_ref(), r, t_min, t_max) {
+            found.push(Segment::new(
+                span,
+                majorant * len,
+                move |t| {
+                    let density = self.density * self.field.at(r.point_at_param(t)) * len;
+                    (self.absorption * density, self.scattering * density)
+                },
+                self.phase_function.as_ref(),
+            ));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometry::prism::Prism;
+    use crate::geometry::sphere::Sphere;
+    use crate::geometry::torus::Torus;
+    use crate::material::isotropic::Isotropic;
+    use crate::texture::solid::Solid;
+
+    fn phase() -> Arc<dyn Material> {
+        Arc::new(Isotropic::new(Solid::new(Color::from(1))))
+    }
+
+    /// What happens to a ray going through just this volume
+    fn track_through(volume: &Volume, r: Ray) -> Event {
+        let mut segments = Vec::new();
+        volume.media(r, 0.001, std::f32::MAX, &mut segments);
+        track(&segments)
+    }
+
+    /// How much of a ray through the middle of a unit cube doesn't scatter
+    /// or get absorbed
+    fn transmittance(volume: &Volume) -> Color {
+        let r = Ray::new(Vec3::new(0.5, 0.51, -1.0), Vec3::new(0, 0, 1));
+        let samples = 20_000;
+        (0..samples).fold(Color::zero(), |sum, _| match track_through(volume, r) {
+            Event::Through(weight) => sum + weight,
+            _ => sum,
+        }) / samples as f32
+    }
+
+    fn cube() -> Box<dyn Hitable> {
+        Box::new(Prism::new(Vec3::zero(), Vec3::from(1), phase()))
+    }
+
+    #[test]
+    fn test_uniform() {
+        // Beer-Lambert, e^-density through a unit of it
+        let fog = Volume::new(1.5, cube(), phase());
+        let expected = (-1.5f32).exp();
+        assert!((transmittance(&fog).x - expected).abs() < 0.02);
+        let r = Ray::new(Vec3::new(0.5, 0.51, -1.0), Vec3::new(0, 0, 1));
+        for _ in 0..100 {
+            if let Event::Scattered(t, weight, _) = track_through(&fog, r) {
+                assert!(t >= 1.0 && t <= 2.0);
+                assert!((weight - Color::from(1)).len() < 1e-5);
+            }
+        }
+        // it's never hit like a surface
+        assert!(fog.hit(r, 0.001, std::f32::MAX).is_none());
+    }
+
+    #[test]
+    fn test_colored() {
+        // more red gets absorbed, so less of it makes it through
+        let smoke = Volume::new(1.0, cube(), phase())
+            .with_coefficients(Color::new(2.0, 0.5, 0.1), Color::from(0.2));
+        let through = transmittance(&smoke);
+        for (found, extinction) in
+            [(through.x, 2.2f32), (through.y, 0.7), (through.z, 0.3)].iter()
+        {
+            assert!((found - (-extinction).exp()).abs() < 0.03, "{:?}", through);
+        }
+    }
+
+    #[test]
+    fn test_boundaries() {
+        // through both sides of a ring, and from inside one side of it
+        let ring = Volume::new(
+            0.5,
+            Box::new(Torus::new(Vec3::zero(), 2.0, 0.5, phase())),
+            phase(),
+        );
+        for &(x, length) in &[(-5.0, 2.0), (-2.1, 1.6)] {
+            let r = Ray::new(Vec3::new(x, 0.01, 0.0), Vec3::new(1, 0, 0));
+            let samples = 20_000;
+            let mut through = 0;
+            for _ in 0..samples {
+                match track_through(&ring, r) {
+                    Event::Through(_) => through += 1,
+                    Event::Scattered(t, ..) => {
+                        let x = r.point_at_param(t).x.abs();
+                        assert!(x > 1.49 && x < 2.51)
+                    }
+                    Event::Absorbed => panic!("nothing absorbs"),
+                }
+            }
+            let expected = (-0.5 * length as f32).exp();
+            assert!((through as f32 / samples as f32 - expected).abs() < 0.02);
+        }
+    }
+
+    #[test]
+    fn test_field() {
+        // density ramping from 0 to 2 across the cube, like a texture with
+        // the height as its brightness
+        #[derive(Debug)]
+        struct Ramp;
+        impl Texture for Ramp {
+            fn value(&self, _u: f32, _v: f32, p: Vec3) -> Color {
+                Color::from(p.z * 2.0)
+            }
+        }
+        let ramp = Volume::new(1.0, cube(), phase())
+            .with_field(Density::Texture(Arc::new(Ramp), 2.0));
+        assert!((transmittance(&ramp).x - (-1.0f32).exp()).abs() < 0.02);
+
+        // a voxel grid filled at full density on one half
+        let grid = VoxelGrid::from_fn(
+            |_, _, z| if z < 2 { 255 } else { 0 },
+            [1, 1, 4],
+            Vec3::new(-1.0, -1.0, -0.5),
+            Vec3::new(3.0, 3.0, 0.5),
+            vec![],
+        );
+        assert!((grid.sample(Vec3::new(0.5, 0.5, 0.0)) - 1.0).abs() < 1e-5);
+        assert!((grid.sample(Vec3::new(0.5, 0.5, 0.5)) - 0.5).abs() < 1e-5);
+        let half =
+            Volume::new(2.0, cube(), phase()).with_field(Density::Voxels(Arc::new(grid)));
+        // the density goes 2, then linearly to 0 between 0.25 and 0.75
+        assert!((transmittance(&half).x - (-1.0f32).exp()).abs() < 0.02);
+        let sphere = Volume::new(
+            0.0,
+            Box::new(Sphere::new(Vec3::zero(), 1.0, phase())),
+            phase(),
+        );
+        let mut segments = Vec::new();
+        sphere.media(
+            Ray::new(Vec3::new(0, 0, -2), Vec3::new(0, 0, 1)),
+            0.0,
+            std::f32::MAX,
+            &mut segments,
+        );
+        assert!(segments.is_empty());
+    }
 }
@@ -0,0 +1,659 @@
+use super::aabb::AABB;
+use super::{HitRecord, Hitable};
+use crate::axis::Axis;
+use crate::material::{diffuse::Diffuse, Material};
+use crate::ray::Ray;
+use crate::scene::Color;
+use crate::texture::solid::Solid;
+use crate::vec3::Vec3;
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::io::{self, Error, ErrorKind};
+use std::sync::Arc;
+
+const AXES: [Axis; 3] = [Axis::X, Axis::Y, Axis::Z];
+
+/// Either every voxel, or only the filled ones for grids that are mostly
+/// empty
+#[derive(Debug, Clone, PartialEq)]
+pub enum Voxels {
+    /// x changes fastest, then y, then z
+    Dense(Vec<u8>),
+    Sparse(HashMap<[usize; 3], u8>),
+}
+
+impl Voxels {
+    /// Sparse when less than an eighth of the grid is filled
+    pub fn pack(dims: [usize; 3], filled: &[([usize; 3], u8)]) -> Self {
+        let count = dims[0] * dims[1] * dims[2];
+        if filled.len() < count / 8 {
+            Voxels::Sparse(filled.iter().cloned().collect())
+        } else {
+            let mut dense = vec![0; count];
+            for &([x, y, z], value) in filled {
+                dense[(z * dims[1] + y) * dims[0] + x] = value;
+            }
+            Voxels::Dense(dense)
+        }
+    }
+}
+
+/// A box of equally sized blocks, each either empty (0) or filled with one
+/// of up to 255 materials. Rays step from voxel to voxel with Amanatides and
+/// Woo's 3D-DDA, so even huge grids don't need a BVH.
+pub struct VoxelGrid {
+    voxels: Voxels,
+    dims: [usize; 3],
+    corner: Vec3,
+    /// The size of a single voxel
+    size: Vec3,
+    /// Voxels holding i use materials[i - 1]
+    materials: Vec<Arc<dyn Material>>,
+    /// The biggest value in any voxel
+    highest: u8,
+}
+
+impl std::fmt::Debug for VoxelGrid {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "voxel grid of {}x{}x{} from {:?} to {:?}",
+            self.dims[0],
+            self.dims[1],
+            self.dims[2],
+            self.corner,
+            self.get_bb().max
+        )
+    }
+}
+
+impl VoxelGrid {
+    /// `dims` voxels along each axis, starting at `corner` and each `size`
+    /// big. Grids only used as the density of a volume don't need any
+    /// materials, and rays go straight through them.
+    pub fn new(
+        voxels: Voxels,
+        dims: [usize; 3],
+        corner: Vec3,
+        size: Vec3,
+        materials: Vec<Arc<dyn Material>>,
+    ) -> Self {
+        assert!(dims.iter().all(|&d| d > 0), "a voxel grid can't be empty");
+        let highest = match &voxels {
+            Voxels::Dense(v) => {
+                assert_eq!(v.len(), dims[0] * dims[1] * dims[2]);
+                v.iter().cloned().max()
+            }
+            Voxels::Sparse(v) => {
+                assert!(v.keys().all(|p| (0..3).all(|a| p[a] < dims[a])));
+                v.values().cloned().max()
+            }
+        };
+        let highest = highest.unwrap_or(0);
+        assert!(
+            materials.is_empty() || highest as usize <= materials.len(),
+            "voxels use material {} but there are only {}",
+            highest,
+            materials.len()
+        );
+        VoxelGrid {
+            voxels,
+            dims,
+            corner,
+            size,
+            materials,
+            highest,
+        }
+    }
+
+    /// Fills the grid from a function of each voxel's x, y and z index
+    pub fn from_fn(
+        f: impl Fn(usize, usize, usize) -> u8,
+        dims: [usize; 3],
+        corner: Vec3,
+        size: Vec3,
+        materials: Vec<Arc<dyn Material>>,
+    ) -> Self {
+        let mut voxels = Vec::with_capacity(dims[0] * dims[1] * dims[2]);
+        for z in 0..dims[2] {
+            for y in 0..dims[1] {
+                for x in 0..dims[0] {
+                    voxels.push(f(x, y, z));
+                }
+            }
+        }
+        VoxelGrid::new(Voxels::Dense(voxels), dims, corner, size, materials)
+    }
+
+    /// Loads a file in the raw format `parse_raw` reads
+    pub fn from_raw(
+        filename: &str,
+        corner: Vec3,
+        size: Vec3,
+        materials: Vec<Arc<dyn Material>>,
+    ) -> io::Result<Self> {
+        let (dims, voxels) = parse_raw(&std::fs::read(filename)?)?;
+        let highest = voxels.iter().cloned().max().unwrap_or(0);
+        if !materials.is_empty() && highest as usize > materials.len() {
+            return Err(invalid(&format!(
+                "voxels use material {} but there are only {}",
+                highest,
+                materials.len()
+            )));
+        }
+        Ok(VoxelGrid::new(
+            Voxels::Dense(voxels),
+            dims,
+            corner,
+            size,
+            materials,
+        ))
+    }
+
+    /// Loads the first model of a MagicaVoxel file with a diffuse material
+    /// for each palette color. MagicaVoxel is z up, so it gets turned to be
+    /// y up like everything else.
+    pub fn from_vox(filename: &str, corner: Vec3, size: Vec3) -> io::Result<Self> {
+        let model = parse_vox(&std::fs::read(filename)?)?;
+        let materials = model.palette[1..]
+            .iter()
+            .map(|&c| Arc::new(Diffuse::new(Solid::new(c))) as Arc<dyn Material>)
+            .collect();
+        Ok(VoxelGrid::new(
+            Voxels::pack(model.dims, &model.voxels),
+            model.dims,
+            corner,
+            size,
+            materials,
+        ))
+    }
+
+    pub fn get(&self, [x, y, z]: [usize; 3]) -> u8 {
+        match &self.voxels {
+            Voxels::Dense(v) => v[(z * self.dims[1] + y) * self.dims[0] + x],
+            Voxels::Sparse(v) => v.get(&[x, y, z]).cloned().unwrap_or(0),
+        }
+    }
+
+    pub fn highest(&self) -> u8 {
+        self.highest
+    }
+
+    /// The values of the voxels around a point blended by how close their
+    /// centers are, from 0 to 1 for 0 to 255. It's 0 outside the grid.
+    pub fn sample(&self, p: Vec3) -> f32 {
+        let local = (p - self.corner) / self.size - Vec3::from(0.5);
+        let mut lower = [0; 3];
+        let mut fraction = [0.0; 3];
+        for (a, &axis) in AXES.iter().enumerate() {
+            let x = local.get_axis(axis);
+            if x < -1.0 || x >= self.dims[a] as f32 {
+                return 0.0;
+            }
+            lower[a] = x.floor() as isize;
+            fraction[a] = x - x.floor();
+        }
+        let value = |i: isize, j: isize, k: isize| {
+            let cell = [i, j, k];
+            if (0..3).all(|a| cell[a] >= 0 && (cell[a] as usize) < self.dims[a]) {
+                self.get([i as usize, j as usize, k as usize]) as f32 / 255.0
+            } else {
+                0.0
+            }
+        };
+        let mut total = 0.0;
+        for &(dx, dy, dz) in &[
+            (0, 0, 0),
+            (1, 0, 0),
+            (0, 1, 0),
+            (1, 1, 0),
+            (0, 0, 1),
+            (1, 0, 1),
+            (0, 1, 1),
+            (1, 1, 1),
+        ] {
+            let weight = |d: isize, f: f32| if d == 0 { 1.0 - f } else { f };
+            total += weight(dx, fraction[0])
+                * weight(dy, fraction[1])
+                * weight(dz, fraction[2])
+                * value(lower[0] + dx, lower[1] + dy, lower[2] + dz);
+        }
+        total
+    }
+
+    /// A hit on the face crossing `axis` at t, going from a voxel holding
+    /// `from` into one holding `to`. Entering a filled voxel hits its front
+    /// face, going from a filled one into nothing hits the back face.
+    fn record(&self, r: Ray, t: f32, axis: usize, from: u8, to: u8) -> HitRecord {
+        let point = r.point_at_param(t);
+        let d = r.dir.get_axis(AXES[axis]);
+        let (value, outward) = if to != 0 { (to, -d) } else { (from, d) };
+        let normal = Vec3::zero().set_axis(AXES[axis], outward.signum());
+        let local = (point - self.corner) / self.size;
+        let (a, b) = match axis {
+            0 => (Axis::Z, Axis::Y),
+            1 => (Axis::X, Axis::Z),
+            _ => (Axis::X, Axis::Y),
+        };
+        let fraction = |axis: Axis| {
+            let p = local.get_axis(axis);
+            p - p.floor()
+        };
+        HitRecord {
+            t,
+            u: fraction(a),
+            v: fraction(b),
+            point,
+            normal,
+            geometric_normal: normal,
+            front_face: to != 0,
+            tangent: Vec3::zero(),
+            color: Color::from(1),
+            material: self.materials[value as usize - 1].as_ref(),
+        }
+    }
+}
+
+impl Hitable for VoxelGrid {
+    fn hit(&self, r: Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+        if self.materials.is_empty() {
+            return None;
+        }
+        // clip the ray to the grid, remembering which side it came in from
+        let bb = self.get_bb();
+        let (mut start, mut end) = (t_min, t_max);
+        let mut entry = None;
+        for (a, &axis) in AXES.iter().enumerate() {
+            let (o, d) = (r.origin.get_axis(axis), r.dir.get_axis(axis));
+            let (lo, hi) = (bb.min.get_axis(axis), bb.max.get_axis(axis));
+            if d == 0.0 {
+                if o < lo || o > hi {
+                    return None;
+                }
+                continue;
+            }
+            let (t0, t1) = ((lo - o) / d, (hi - o) / d);
+            if t0.min(t1) > start {
+                start = t0.min(t1);
+                entry = Some(a);
+            }
+            end = end.min(t0.max(t1));
+        }
+        if start > end {
+            return None;
+        }
+
+        // the voxel it starts in, and the distance along the ray to the next
+        // voxel boundary on each axis and between boundaries
+        let origin = r.origin - self.corner;
+        let first = r.point_at_param(start) - self.corner;
+        let mut cell = [0; 3];
+        let mut next = [std::f32::INFINITY; 3];
+        let mut delta = [std::f32::INFINITY; 3];
+        for (a, &axis) in AXES.iter().enumerate() {
+            let (o, d) = (origin.get_axis(axis), r.dir.get_axis(axis));
+            let size = self.size.get_axis(axis);
+            let p = first.get_axis(axis);
+            cell[a] = ((p / size).floor().max(0.0) as usize).min(self.dims[a] - 1);
+            if d != 0.0 {
+                let boundary = if d > 0.0 { cell[a] + 1 } else { cell[a] } as f32;
+                next[a] = (boundary * size - o) / d;
+                delta[a] = size / d.abs();
+            }
+        }
+
+        // walk until what's in the voxels changes
+        let mut inside = if entry.is_some() { 0 } else { self.get(cell) };
+        let mut enter = start;
+        loop {
+            let value = self.get(cell);
+            if value != inside {
+                if let Some(axis) = entry {
+                    return Some(self.record(r, enter, axis, inside, value));
+                }
+            }
+            let a = (0..3)
+                .min_by(|&i, &j| next[i].partial_cmp(&next[j]).unwrap())
+                .unwrap();
+            let exit = next[a];
+            if exit >= t_max {
+                return None;
+            }
+            inside = value;
+            entry = Some(a);
+            enter = exit;
+            if r.dir.get_axis(AXES[a]) > 0.0 && cell[a] + 1 < self.dims[a] {
+                cell[a] += 1;
+            } else if r.dir.get_axis(AXES[a]) < 0.0 && cell[a] > 0 {
+                cell[a] -= 1;
+            } else {
+                // out the far side of the grid
+                return if value != 0 {
+                    Some(self.record(r, exit, a, value, 0))
+                } else {
+                    None
+                };
+            }
+            next[a] += delta[a];
+        }
+    }
+
+    fn get_bb(&self) -> AABB {
+        let count = Vec3::new(self.dims[0], self.dims[1], self.dims[2]);
+        AABB::new(self.corner, self.corner + self.size * count)
+    }
+}
+
+fn invalid(msg: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, format!("voxels: {}", msg))
+}
+
+fn u32_at(bytes: &[u8], i: usize) -> io::Result<u32> {
+    bytes
+        .get(i..i + 4)
+        .map(|b| u32::from_le_bytes(b.try_into().unwrap()))
+        .ok_or_else(|| invalid("unexpected end of file"))
+}
+
+/// The raw format is the size along x, y and z as little endian u32s, then a
+/// byte for every voxel with x changing fastest, then y, then z
+pub fn parse_raw(bytes: &[u8]) -> io::Result<([usize; 3], Vec<u8>)> {
+    let dims = [
+        u32_at(bytes, 0)? as usize,
+        u32_at(bytes, 4)? as usize,
+        u32_at(bytes, 8)? as usize,
+    ];
+    let count = dims
+        .iter()
+        .try_fold(1usize, |count, &d| count.checked_mul(d))
+        .filter(|&count| count > 0)
+        .ok_or_else(|| invalid("empty or too big grid"))?;
+    let voxels = bytes
+        .get(12..)
+        .and_then(|voxels| voxels.get(..count))
+        .ok_or_else(|| invalid("fewer voxels than the size says"))?;
+    Ok((dims, voxels.to_vec()))
+}
+
+/// One model from a .vox file, already turned to be y up
+#[derive(Debug, Clone, PartialEq)]
+pub struct VoxModel {
+    pub dims: [usize; 3],
+    pub voxels: Vec<([usize; 3], u8)>,
+    /// Indexed by voxel value, so the first entry is never used
+    pub palette: Vec<Color>,
+}
+
+/// Reads the size, voxels and palette of the first model in a MagicaVoxel
+/// file. Files without a palette get gray instead of MagicaVoxel's default
+/// one, and the scene graph and material extensions are skipped.
+pub fn parse_vox(bytes: &[u8]) -> io::Result<VoxModel> {
+    if bytes.len() < 20 || &bytes[..4] != b"VOX " || &bytes[8..12] != b"MAIN" {
+        return Err(invalid("missing VOX header"));
+    }
+    let mut size = None;
+    let mut voxels = None;
+    let mut palette = vec![Color::from(0.75); 256];
+    // the chunks inside MAIN, each an id, content and child sizes, then the
+    // content
+    let mut offset = 20 + u32_at(bytes, 12)? as usize;
+    while offset < bytes.len() {
+        let id = bytes
+            .get(offset..offset + 4)
+            .ok_or_else(|| invalid("unexpected end of file"))?;
+        let length = u32_at(bytes, offset + 4)? as usize;
+        let children = u32_at(bytes, offset + 8)? as usize;
+        let content = bytes
+            .get(offset + 12..offset + 12 + length)
+            .ok_or_else(|| invalid("unexpected end of file"))?;
+        match id {
+            b"SIZE" if size.is_none() => {
+                size = Some([
+                    u32_at(content, 0)? as usize,
+                    u32_at(content, 4)? as usize,
+                    u32_at(content, 8)? as usize,
+                ]);
+            }
+            b"XYZI" if voxels.is_none() => {
+                let count = u32_at(content, 0)? as usize;
+                let data = content
+                    .get(4..4 + count * 4)
+                    .ok_or_else(|| invalid("fewer voxels than the count says"))?;
+                voxels = Some(data.to_vec());
+            }
+            b"RGBA" => {
+                // color i of the file is for voxels holding i + 1
+                for (i, c) in content.chunks_exact(4).take(255).enumerate() {
+                    palette[i + 1] = Color::new(c[0], c[1], c[2]);
+                }
+            }
+            _ => (),
+        }
+        offset += 12 + length + children;
+    }
+
+    let [sx, sy, sz] = size.ok_or_else(|| invalid("no SIZE chunk"))?;
+    if sx == 0 || sy == 0 || sz == 0 {
+        return Err(invalid("empty model"));
+    }
+    let data = voxels.ok_or_else(|| invalid("no XYZI chunk"))?;
+    let mut filled = Vec::with_capacity(data.len() / 4);
+    for v in data.chunks(4) {
+        let (x, y, z) = (v[0] as usize, v[1] as usize, v[2] as usize);
+        if x >= sx || y >= sy || z >= sz {
+            return Err(invalid("voxel outside the model"));
+        }
+        if v[3] != 0 {
+            // z up to y up, turned around x so it doesn't get mirrored
+            filled.push(([x, z, sy - 1 - y], v[3]));
+        }
+    }
+    Ok(VoxModel {
+        dims: [sx, sz, sy],
+        voxels: filled,
+        palette,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bvh::BVHNode;
+    use crate::geometry::prism::Prism;
+    use crate::material::specular::Specular;
+    use rand::random;
+
+    fn materials(count: usize) -> Vec<Arc<dyn Material>> {
+        (0..count)
+            .map(|_| Arc::new(Specular::new(Color::from(1), 0.0)) as Arc<dyn Material>)
+            .collect()
+    }
+
+    #[test]
+    fn test_hit() {
+        let grid = VoxelGrid::from_fn(
+            |x, y, z| (x == 2 && y == 1 && z < 2) as u8,
+            [4, 3, 2],
+            Vec3::new(-1, 0, 0),
+            Vec3::new(0.5, 1.0, 1.0),
+            materials(1),
+        );
+        // the filled column spans x 0 to 0.5, y 1 to 2 and z 0 to 2
+        let r = Ray::new(Vec3::new(0.2, 1.3, 5.0), Vec3::new(0, 0, -1));
+        let hit = grid.hit(r, 0.0, std::f32::MAX).unwrap();
+        assert!((hit.t - 3.0).abs() < 1e-5);
+        assert_eq!(hit.normal, Vec3::new(0, 0, 1));
+        assert!(hit.front_face);
+        assert!((hit.u - 0.4).abs() < 1e-5 && (hit.v - 0.3).abs() < 1e-5);
+        // from inside it hits the back face on the way out
+        let r = Ray::new(Vec3::new(0.2, 1.3, 1.5), Vec3::new(0, 0, -1));
+        let hit = grid.hit(r, 0.0, std::f32::MAX).unwrap();
+        assert!((hit.t - 1.5).abs() < 1e-5);
+        assert_eq!(hit.normal, Vec3::new(0, 0, -1));
+        assert!(!hit.front_face);
+        // through the empty voxels next to it, and stopping short of it
+        let r = Ray::new(Vec3::new(0.7, 1.3, 5.0), Vec3::new(0, 0, -1));
+        assert!(grid.hit(r, 0.0, std::f32::MAX).is_none());
+        let r = Ray::new(Vec3::new(0.2, 1.3, 5.0), Vec3::new(0, 0, -1));
+        assert!(grid.hit(r, 0.0, 2.9).is_none());
+        // coming in from the side
+        let r = Ray::new(Vec3::new(-3.0, 1.5, 0.6), Vec3::new(1.0, 0.01, 0.0));
+        let hit = grid.hit(r, 0.0, std::f32::MAX).unwrap();
+        assert!((hit.point.x - 0.0).abs() < 1e-5);
+        assert_eq!(hit.normal, Vec3::new(-1, 0, 0));
+    }
+
+    #[test]
+    fn test_materials() {
+        let grid = VoxelGrid::from_fn(
+            |x, _, _| x as u8,
+            [3, 1, 1],
+            Vec3::zero(),
+            Vec3::from(1),
+            materials(2),
+        );
+        let r = Ray::new(Vec3::new(-1.0, 0.5, 0.5), Vec3::new(1, 0, 0));
+        // into 1, then 1 to 2, then out of 2
+        let hit = grid.hit(r, 0.0, std::f32::MAX).unwrap();
+        assert!((hit.t - 2.0).abs() < 1e-5 && hit.front_face);
+        let hit = grid.hit(r, hit.t + 1e-4, std::f32::MAX).unwrap();
+        assert!((hit.t - 3.0).abs() < 1e-5 && hit.front_face);
+        let hit = grid.hit(r, hit.t + 1e-4, std::f32::MAX).unwrap();
+        assert!((hit.t - 4.0).abs() < 1e-5 && !hit.front_face);
+        assert!(grid.hit(r, hit.t + 1e-4, std::f32::MAX).is_none());
+        // without materials there's nothing to hit
+        let density = VoxelGrid::from_fn(
+            |x, _, _| x as u8,
+            [3, 1, 1],
+            Vec3::zero(),
+            Vec3::from(1),
+            vec![],
+        );
+        assert!(density.hit(r, 0.0, std::f32::MAX).is_none());
+    }
+
+    #[test]
+    fn test_matches_boxes() {
+        // random voxels against a BVH of boxes, in dense and sparse grids
+        let dims = [6, 5, 4];
+        let corner = Vec3::new(-1.5, -1.0, -2.0);
+        let size = Vec3::new(0.5, 0.4, 1.0);
+        let (mut dense, mut filled) = (Vec::new(), Vec::new());
+        let mut boxes: Vec<Box<dyn Hitable>> = Vec::new();
+        for z in 0..dims[2] {
+            for y in 0..dims[1] {
+                for x in 0..dims[0] {
+                    let value = (random::<f32>() < 0.1) as u8;
+                    dense.push(value);
+                    if value != 0 {
+                        filled.push(([x, y, z], 1));
+                        let p0 = corner + size * Vec3::new(x, y, z);
+                        boxes.push(Box::new(Prism::new(
+                            p0,
+                            p0 + size,
+                            materials(1).remove(0),
+                        )));
+                    }
+                }
+            }
+        }
+        if boxes.is_empty() {
+            return;
+        }
+        let boxes = BVHNode::from(&mut boxes);
+        let sparse = Voxels::pack(dims, &filled);
+        assert!(filled.len() >= 15 || sparse != Voxels::Dense(dense.clone()));
+        for voxels in vec![Voxels::Dense(dense), sparse] {
+            let grid = VoxelGrid::new(voxels, dims, corner, size, materials(1));
+            for _ in 0..500 {
+                // from outside, since the boxes have faces between
+                // neighbors that the grid doesn't
+                let origin = (random::<Vec3>() - Vec3::from(0.5)).normalize() * 6.0;
+                let target = (random::<Vec3>() - Vec3::from(0.5)) * 2.0;
+                let r = Ray::new(origin, target - origin);
+                let expected = boxes.hit(r, 0.0, std::f32::MAX);
+                let found = grid.hit(r, 0.0, std::f32::MAX);
+                assert_eq!(expected.is_some(), found.is_some());
+                if let (Some(e), Some(f)) = (expected, found) {
+                    assert!((e.t - f.t).abs() < 1e-4);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_raw() {
+        let mut bytes = Vec::new();
+        for d in &[2u32, 1, 2] {
+            bytes.extend(&d.to_le_bytes());
+        }
+        bytes.extend(&[0, 1, 2, 0]);
+        let (dims, voxels) = parse_raw(&bytes).unwrap();
+        assert_eq!(dims, [2, 1, 2]);
+        assert_eq!(voxels, vec![0, 1, 2, 0]);
+        assert!(parse_raw(&bytes[..14]).is_err());
+        assert!(parse_raw(&bytes[..6]).is_err());
+        assert!(parse_raw(&[0; 16]).is_err());
+
+        let path = std::env::temp_dir().join("ray_voxels.raw");
+        std::fs::write(&path, &bytes).unwrap();
+        let load = |materials| {
+            VoxelGrid::from_raw(
+                path.to_str().unwrap(),
+                Vec3::zero(),
+                Vec3::from(1),
+                materials,
+            )
+        };
+        assert!(load(materials(2)).is_ok());
+        assert!(load(vec![]).is_ok());
+        assert!(load(materials(1)).is_err());
+        std::fs::write(&path, &bytes[..14]).unwrap();
+        assert!(load(materials(2)).is_err());
+        std::fs::remove_file(&path).unwrap();
+        assert!(load(materials(2)).is_err());
+    }
+
+    /// A chunk with its id, sizes and content
+    fn chunk(id: &[u8], content: &[u8], children: &[u8]) -> Vec<u8> {
+        let mut bytes = id.to_vec();
+        bytes.extend(&(content.len() as u32).to_le_bytes());
+        bytes.extend(&(children.len() as u32).to_le_bytes());
+        bytes.extend(content);
+        bytes.extend(children);
+        bytes
+    }
+
+    #[test]
+    fn test_vox() {
+        let mut size = Vec::new();
+        for d in &[2u32, 3, 4] {
+            size.extend(&d.to_le_bytes());
+        }
+        let mut xyzi = 2u32.to_le_bytes().to_vec();
+        xyzi.extend(&[1, 0, 3, 5, 0, 2, 0, 1]);
+        let mut rgba = vec![0; 1024];
+        rgba[16..20].copy_from_slice(&[255, 0, 51, 255]);
+        let children = [
+            chunk(b"SIZE", &size, &[]),
+            chunk(b"XYZI", &xyzi, &[]),
+            chunk(b"RGBA", &rgba, &[]),
+        ]
+        .concat();
+        let mut bytes = b"VOX ".to_vec();
+        bytes.extend(&150u32.to_le_bytes());
+        bytes.extend(chunk(b"MAIN", &[], &children));
+
+        let model = parse_vox(&bytes).unwrap();
+        // z up becomes y up
+        assert_eq!(model.dims, [2, 4, 3]);
+        assert_eq!(model.voxels, vec![([1, 3, 2], 5), ([0, 0, 0], 1)]);
+        assert_eq!(model.palette[5], Color::new(1.0, 0.0, 0.2));
+        assert_eq!(model.palette[1], Color::zero());
+        assert!(parse_vox(&bytes[..40]).is_err());
+        assert!(parse_vox(b"NOPE").is_err());
+        assert!(
+            VoxelGrid::from_vox("no such file.vox", Vec3::zero(), Vec3::from(1)).is_err()
+        );
+    }
+}
@@ -0,0 +1,405 @@
+use crate::bvh::BVHNode;
+use crate::camera::Camera;
+use crate::geometry::{
+    mesh::{Mesh, MeshData},
+    sphere::Sphere,
+    triangle_mesh::TriangleMesh,
+    Hitable,
+};
+use crate::material::{
+    dielectric::Dielectric, diffuse::Diffuse, light::Light, specular::Specular, Material,
+};
+use crate::matrix::Matrix4;
+use crate::scene::Color;
+use crate::scene_graph::SceneNode;
+use crate::texture::{solid::Solid, tinted::Tinted};
+use crate::vec3::Vec3;
+use ::gltf::camera::Projection;
+use ::gltf::khr_lights_punctual::Kind;
+use ::gltf::material::AlphaMode;
+use ::gltf::mesh::Mode;
+use ::gltf::{buffer, image::Source, Gltf};
+use image::RgbImage;
+use std::io::{self, Error, ErrorKind};
+use std::path::Path;
+use std::sync::Arc;
+
+/// Punctual lights become emissive spheres this big since the integrator
+/// only finds lights by hitting them
+const LIGHT_RADIUS: f32 = 0.05;
+
+/// A perspective camera from the file, already in world space
+#[derive(Debug, Clone)]
+pub struct GltfCamera {
+    pub name: String,
+    pub position: Vec3,
+    pub look_at: Vec3,
+    pub up_dir: Vec3,
+    /// Vertical field of view in degrees
+    pub fov: f32,
+    /// Most exporters leave this to the viewport
+    pub aspect: Option<f32>,
+}
+
+impl GltfCamera {
+    /// Uses `aspect` if the file didn't specify one
+    pub fn camera(&self, aspect: f32) -> Camera {
+        Camera::new(
+            self.position,
+            self.look_at,
+            self.up_dir,
+            self.fov,
+            self.aspect.unwrap_or(aspect),
+            0.0,
+        )
+    }
+}
+
+/// The default scene of a glTF file. Nodes keep their names and local
+/// transforms, and nodes sharing a mesh share one `Mesh` so they get
+/// flattened into instances.
+#[derive(Debug)]
+pub struct GltfScene {
+    pub root: SceneNode,
+    pub cameras: Vec<GltfCamera>,
+}
+
+/// Loads a `.gltf` with its external or embedded buffers, or a `.glb`
+pub fn load(filename: &str) -> io::Result<GltfScene> {
+    let path = Path::new(filename);
+    parse(
+        &std::fs::read(path)?,
+        path.parent().unwrap_or_else(|| Path::new("")),
+    )
+}
+
+fn invalid(msg: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, format!("gltf: {}", msg))
+}
+
+/// External files are looked up relative to `dir`
+pub fn parse(bytes: &[u8], dir: &Path) -> io::Result<GltfScene> {
+    let gltf = Gltf::from_slice(bytes).map_err(|e| invalid(&e.to_string()))?;
+    let buffers = gltf
+        .buffers()
+        .map(|b| match b.source() {
+            buffer::Source::Bin => gltf
+                .blob
+                .clone()
+                .ok_or_else(|| invalid("missing binary chunk")),
+            buffer::Source::Uri(uri) => read_uri(uri, dir),
+        })
+        .collect::<io::Result<Vec<_>>>()?;
+    // a model missing its textures would look wrong, so like the obj
+    // importer that's an error
+    let images = gltf
+        .images()
+        .map(|image| {
+            let bytes = match image.source() {
+                Source::View { view, .. } => {
+                    let start = view.offset();
+                    buffers[view.buffer().index()]
+                        .get(start..start + view.length())
+                        .map(<[u8]>::to_vec)
+                        .ok_or_else(|| invalid("image view out of range"))
+                }
+                Source::Uri { uri, .. } => read_uri(uri, dir),
+            };
+            let img = image::load_from_memory(&bytes?).map_err(|e| {
+                invalid(&format!("couldn't load image {}: {}", image.index(), e))
+            })?;
+            Ok(img.to_rgb())
+        })
+        .collect::<io::Result<Vec<_>>>()?;
+    let materials: Vec<Arc<dyn Material>> =
+        gltf.materials().map(|m| material(&m, &images)).collect();
+    // the spec's default material is a rough metal, which looks like a mistake
+    // on untextured models so use a plain grey instead
+    let fallback: Arc<dyn Material> =
+        Arc::new(Diffuse::new(Solid::new(Color::from(0.8))));
+    let meshes = gltf
+        .meshes()
+        .map(|m| mesh(&m, &buffers, &materials, &fallback))
+        .collect::<io::Result<Vec<_>>>()?;
+
+    let scene = gltf
+        .default_scene()
+        .or_else(|| gltf.scenes().next())
+        .ok_or_else(|| invalid("no scenes"))?;
+    let mut cameras = Vec::new();
+    let mut root = SceneNode::new(scene.name().unwrap_or("scene"));
+    for n in scene.nodes() {
+        root.children
+            .push(node(&n, Matrix4::identity(), &meshes, &mut cameras));
+    }
+    Ok(GltfScene { root, cameras })
+}
+
+fn read_uri(uri: &str, dir: &Path) -> io::Result<Vec<u8>> {
+    if uri.starts_with("data:") {
+        let data = uri
+            .splitn(2, ";base64,")
+            .nth(1)
+            .ok_or_else(|| invalid("only base64 data uris are supported"))?;
+        base64::decode(data).map_err(|_| invalid("bad base64 data"))
+    } else {
+        std::fs::read(dir.join(uri))
+    }
+}
+
+fn vec3(v: [f32; 3]) -> Vec3 {
+    Vec3::new(v[0], v[1], v[2])
+}
+
+/// Maps metallic-roughness onto whichever material is closest. Like the obj
+/// importer, a surface is either emissive, glass, metal or diffuse, and the
+/// metallic-roughness, normal and occlusion textures are ignored.
+fn material(m: &::gltf::Material, images: &[RgbImage]) -> Arc<dyn Material> {
+    let pbr = m.pbr_metallic_roughness();
+    let image = |info: Option<::gltf::texture::Info>| {
+        info.map(|i| images[i.texture().source().index()].clone())
+    };
+    let [r, g, b, alpha] = pbr.base_color_factor();
+    let base = Color::new(r, g, b);
+    let emission = vec3(m.emissive_factor());
+    if emission.x.max(emission.y).max(emission.z) > 0.0 {
+        return match image(m.emissive_texture()) {
+            Some(img) => Arc::new(Light::new(Tinted::new(img, emission))),
+            None => Arc::new(Light::new(Solid::new(emission))),
+        };
+    }
+    if m.alpha_mode() == AlphaMode::Blend && alpha < 1.0 {
+        return Arc::new(Dielectric::new(base, 1.5));
+    }
+    if pbr.metallic_factor() >= 0.5 {
+        return Arc::new(Specular::new(base, pbr.roughness_factor()));
+    }
+    match image(pbr.base_color_texture()) {
+        Some(img) => Arc::new(Diffuse::new(Tinted::new(img, base))),
+        None => Arc::new(Diffuse::new(Solid::new(base))),
+    }
+}
+
+/// All the triangle primitives of a mesh, None if there aren't any
+fn mesh(
+    mesh: &::gltf::Mesh,
+    buffers: &[Vec<u8>],
+    materials: &[Arc<dyn Material>],
+    fallback: &Arc<dyn Material>,
+) -> io::Result<Option<Arc<dyn Hitable>>> {
+    let mut parts: Vec<Box<dyn Hitable>> = Vec::new();
+    for primitive in mesh.primitives() {
+        if primitive.mode() != Mode::Triangles {
+            eprintln!(
+                "skipping {:?} primitive, only triangles are supported",
+                primitive.mode()
+            );
+            continue;
+        }
+        let reader = primitive.reader(|b| buffers.get(b.index()).map(Vec::as_slice));
+        let positions: Vec<Vec3> = match reader.read_positions() {
+            Some(p) => p.map(vec3).collect(),
+            None => continue,
+        };
+        let count = positions.len() as u32;
+        let data = MeshData {
+            positions,
+            normals: reader
+                .read_normals()
+                .map_or_else(Vec::new, |n| n.map(vec3).collect()),
+            // glTF puts the texture origin at the top left
+            texcoords: reader.read_tex_coords(0).map_or_else(Vec::new, |t| {
+                t.into_f32()
+                    .map(|[u, v]| Vec3::new(u, 1.0 - v, 0.0))
+                    .collect()
+            }),
+            colors: reader
+                .read_colors(0)
+                .map_or_else(Vec::new, |c| c.into_rgb_f32().map(vec3).collect()),
+            indices: reader
+                .read_indices()
+                .map_or_else(|| (0..count).collect(), |i| i.into_u32().collect()),
+            smoothing_groups: Vec::new(),
+        };
+        if data.indices.iter().any(|&i| i >= count) {
+            return Err(invalid("index out of range"));
+        }
+        if data.indices.is_empty() {
+            continue;
+        }
+        let material = primitive
+            .material()
+            .index()
+            .map_or_else(|| fallback.clone(), |i| materials[i].clone());
+        // doubleSided is left off by most exporters even on open surfaces,
+        // and culling would stop rays inside glass from ever getting out, so
+        // everything is two sided
+        parts.push(Box::new(TriangleMesh::new(
+            &data,
+            Matrix4::identity(),
+            material,
+        )));
+    }
+    if parts.is_empty() {
+        return Ok(None);
+    }
+    Ok(Some(Arc::new(Mesh {
+        faces: BVHNode::from(&mut parts),
+    })))
+}
+
+/// Intensity is in candela, so the sphere's radiance is spread over its
+/// projected area. Spot lights shine in every direction.
+fn light(light: &::gltf::khr_lights_punctual::Light) -> Option<Arc<dyn Hitable>> {
+    if let Kind::Directional = light.kind() {
+        eprintln!(
+            "skipping directional light, only lights with a position are supported"
+        );
+        return None;
+    }
+    let area = std::f32::consts::PI * LIGHT_RADIUS * LIGHT_RADIUS;
+    let radiance = vec3(light.color()) * (light.intensity() / area);
+    Some(Arc::new(Sphere::new(
+        Vec3::zero(),
+        LIGHT_RADIUS,
+        Arc::new(Light::new(Solid::new(radiance))),
+    )))
+}
+
+fn node(
+    n: &::gltf::Node,
+    parent: Matrix4,
+    meshes: &[Option<Arc<dyn Hitable>>],
+    cameras: &mut Vec<GltfCamera>,
+) -> SceneNode {
+    // glTF matrices are column major
+    let local = Matrix4::new(n.transform().matrix()).transpose();
+    let world = parent * local;
+    let name = n
+        .name()
+        .map_or_else(|| format!("node{}", n.index()), str::to_string);
+    let mut result = SceneNode::new(&name).transform(local);
+    result.object = n.mesh().and_then(|m| meshes[m.index()].clone());
+    if let Some(camera) = n.camera() {
+        // cameras look down their -z axis with +y up
+        match camera.projection() {
+            Projection::Perspective(p) => cameras.push(GltfCamera {
+                name: camera.name().unwrap_or(&name).to_string(),
+                position: world.transform_point(Vec3::zero()),
+                look_at: world.transform_point(Vec3::new(0, 0, -1)),
+                up_dir: world.transform_vector(Vec3::new(0, 1, 0)),
+                fov: p.yfov().to_degrees(),
+                aspect: p.aspect_ratio(),
+            }),
+            Projection::Orthographic(_) => {
+                eprintln!("skipping orthographic camera {}", name)
+            }
+        }
+    }
+    if let Some(sphere) = n.light().as_ref().and_then(light) {
+        result
+            .children
+            .push(SceneNode::with_object(&format!("{}.light", name), sphere));
+    }
+    for child in n.children() {
+        result.children.push(node(&child, world, meshes, cameras));
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ray::Ray;
+
+    /// One triangle pushed 5 units down -z, a camera at the origin looking at
+    /// it and a point light
+    fn document() -> String {
+        let mut buffer = Vec::new();
+        for v in &[0.0f32, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0] {
+            buffer.extend_from_slice(&v.to_le_bytes());
+        }
+        format!(
+            r#"{{
+            "asset": {{"version": "2.0"}},
+            "extensionsUsed": ["KHR_lights_punctual"],
+            "extensions": {{"KHR_lights_punctual": {{
+                "lights": [{{"type": "point", "color": [1, 1, 1], "intensity": 2}}]
+            }}}},
+            "scene": 0,
+            "scenes": [{{"nodes": [0, 1]}}],
+            "nodes": [
+                {{"name": "tri", "mesh": 0, "translation": [0, 0, -5]}},
+                {{"name": "cam", "camera": 0, "children": [2]}},
+                {{"name": "lamp", "translation": [0, 2, 0],
+                  "extensions": {{"KHR_lights_punctual": {{"light": 0}}}}}}
+            ],
+            "cameras": [{{"type": "perspective",
+                "perspective": {{"yfov": 0.5, "aspectRatio": 2.0, "znear": 0.1}}}}],
+            "meshes": [{{"primitives": [{{"attributes": {{"POSITION": 0}}, "material": 0}}]}}],
+            "materials": [{{"pbrMetallicRoughness": {{
+                "baseColorFactor": [1, 0, 0, 1], "metallicFactor": 0}}}}],
+            "accessors": [{{"bufferView": 0, "componentType": 5126, "count": 3,
+                "type": "VEC3", "min": [0, 0, 0], "max": [1, 1, 0]}}],
+            "bufferViews": [{{"buffer": 0, "byteLength": 36}}],
+            "buffers": [{{"byteLength": 36,
+                "uri": "data:application/octet-stream;base64,{}"}}]
+            }}"#,
+            base64::encode(&buffer)
+        )
+    }
+
+    #[test]
+    fn test_scene() {
+        let scene = parse(document().as_bytes(), Path::new("")).unwrap();
+        let world = scene.root.world_transform("tri").unwrap();
+        assert_eq!(world.transform_point(Vec3::zero()), Vec3::new(0, 0, -5));
+        assert!(scene.root.find("lamp.light").is_some());
+        let objects = scene.root.flatten().unwrap();
+        let r = Ray::new(Vec3::new(0.2, 0.2, 0), Vec3::new(0, 0, -1));
+        assert!((objects.hit(r, 0.001, std::f32::MAX).unwrap().t - 5.0).abs() < 1e-4);
+        // the light is a sphere 2 units up
+        let r = Ray::new(Vec3::new(0, 0, 0), Vec3::new(0, 1, 0));
+        let hit = objects.hit(r, 0.001, std::f32::MAX).unwrap();
+        assert!((hit.t - (2.0 - LIGHT_RADIUS)).abs() < 1e-4);
+    }
+
+    #[test]
+    fn test_only_cameras() {
+        let scene = parse(
+            br#"{"asset": {"version": "2.0"}, "scenes": [{"nodes": [0]}],
+                "nodes": [{"name": "cam", "camera": 0}],
+                "cameras": [{"type": "perspective", "perspective": {"yfov": 0.5, "znear": 0.1}}]}"#,
+            Path::new(""),
+        )
+        .unwrap();
+        assert_eq!(scene.cameras.len(), 1);
+        assert!(scene.root.flatten().is_none());
+    }
+
+    #[test]
+    fn test_camera() {
+        let scene = parse(document().as_bytes(), Path::new("")).unwrap();
+        let camera = &scene.cameras[0];
+        assert_eq!(camera.name, "cam");
+        assert_eq!(camera.look_at, Vec3::new(0, 0, -1));
+        assert_eq!(camera.up_dir, Vec3::new(0, 1, 0));
+        assert!((camera.fov - 0.5f32.to_degrees()).abs() < 1e-4);
+        assert_eq!(camera.aspect, Some(2.0));
+    }
+
+    #[test]
+    fn test_invalid() {
+        assert!(parse(b"{}", Path::new("")).is_err());
+        assert!(parse(b"not json", Path::new("")).is_err());
+        // images that can't be read are errors too
+        let broken = br#"{"asset": {"version": "2.0"}, "scenes": [{"nodes": []}],
+            "images": [{"uri": "data:image/png;base64,bm90IGFuIGltYWdl"}]}"#;
+        let error = parse(broken, Path::new("")).unwrap_err();
+        assert!(error.to_string().contains("couldn't load image 0"));
+        let missing = br#"{"asset": {"version": "2.0"}, "scenes": [{"nodes": []}],
+            "images": [{"uri": "no such image.png"}]}"#;
+        let error = parse(missing, Path::new("")).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::NotFound);
+    }
+}
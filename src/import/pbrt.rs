@@ -0,0 +1,887 @@
+use crate::background::Background;
+use crate::bvh::BVHNode;
+use crate::camera::Camera;
+use crate::geometry::{
+    instance::Instance,
+    mesh::{Mesh, MeshData},
+    ply,
+    sphere::Sphere,
+    transform::Transform,
+    Hitable,
+};
+use crate::material::{
+    dielectric::Dielectric, diffuse::Diffuse, light::Light, specular::Specular, Material,
+};
+use crate::matrix::Matrix4;
+use crate::ray::Ray;
+use crate::scene::{Color, Scene};
+use crate::texture::{image::load_hdr, solid::Solid, Texture};
+use crate::vec3::Vec3;
+use image::{ImageBuffer, Rgb};
+use std::collections::HashMap;
+use std::f32::consts::PI;
+use std::io::{self, Error, ErrorKind};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// pbrt is left handed, so everything gets mirrored across x on the way into
+/// this crate's right handed space to keep renders from coming out flipped
+fn to_right_handed() -> Matrix4 {
+    Matrix4::scale(Vec3::new(-1, 1, 1))
+}
+
+fn invalid(msg: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, format!("pbrt: {}", msg))
+}
+
+/// Lighting from an infinite light source, used as the scene's background
+pub enum Environment {
+    Uniform(Color),
+    Map {
+        image: ImageBuffer<Rgb<f32>, Vec<f32>>,
+        /// Takes render space directions to the light's space, where +z is up
+        to_light: Matrix4,
+        scale: Color,
+    },
+}
+
+impl Background for Environment {
+    fn get_color(&self, r: Ray) -> Color {
+        match self {
+            Environment::Uniform(color) => *color,
+            Environment::Map {
+                image,
+                to_light,
+                scale,
+            } => {
+                // pbrt's equirectangular layout has +z at the top row
+                let d = to_light.transform_vector(r.dir).normalize();
+                let phi = d.y.atan2(d.x);
+                let phi = if phi < 0.0 { phi + 2.0 * PI } else { phi };
+                let theta = d.z.max(-1.0).min(1.0).acos();
+                let u = (phi / (2.0 * PI)).min(0.9999);
+                *scale * image.value(u, 1.0 - theta / PI, Vec3::zero())
+            }
+        }
+    }
+}
+
+/// Loads a pbrt-v3 scene. Only a subset is understood: perspective cameras,
+/// sphere, trianglemesh and plymesh shapes, matte, plastic, metal, glass and
+/// mirror materials with constant parameters, and diffuse area lights plus an
+/// infinite light that becomes the background. Anything else is skipped with
+/// a warning.
+pub fn load(filename: &str) -> io::Result<Scene<Environment>> {
+    let path = Path::new(filename);
+    parse(
+        &std::fs::read_to_string(path)?,
+        path.parent().unwrap_or_else(|| Path::new("")),
+    )
+}
+
+/// Files referenced by the scene are looked up relative to `dir`
+pub fn parse(text: &str, dir: &Path) -> io::Result<Scene<Environment>> {
+    let mut state = State::new(dir);
+    state.run(&tokenize(text)?)?;
+    state.finish()
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Directive(String),
+    Str(String),
+    Num(f64),
+    Open,
+    Close,
+}
+
+fn tokenize(text: &str) -> io::Result<Vec<Token>> {
+    let mut tokens = Vec::new();
+    let mut chars = text.chars().peekable();
+    let mut depth = 0;
+    while let Some(&c) = chars.peek() {
+        match c {
+            '#' => {
+                while chars.peek().map_or(false, |&c| c != '\n') {
+                    chars.next();
+                }
+            }
+            '[' | ']' => {
+                chars.next();
+                if c == '[' {
+                    depth += 1;
+                    tokens.push(Token::Open);
+                } else {
+                    depth -= 1;
+                    tokens.push(Token::Close);
+                }
+            }
+            '"' => {
+                chars.next();
+                let s: String = chars.by_ref().take_while(|&c| c != '"').collect();
+                tokens.push(Token::Str(s));
+            }
+            c if c.is_whitespace() => {
+                chars.next();
+            }
+            _ => {
+                let mut word = String::new();
+                while let Some(&c) = chars.peek() {
+                    if c.is_whitespace() || c == '[' || c == ']' || c == '"' || c == '#' {
+                        break;
+                    }
+                    word.push(c);
+                    chars.next();
+                }
+                tokens.push(match word.parse() {
+                    Ok(n) => Token::Num(n),
+                    // bare words inside brackets are bools
+                    Err(_) if depth > 0 => Token::Str(word),
+                    Err(_) => Token::Directive(word),
+                });
+            }
+        }
+    }
+    if depth != 0 {
+        return Err(invalid("unbalanced brackets"));
+    }
+    Ok(tokens)
+}
+
+/// A directive's arguments, with bracketed lists collapsed
+#[derive(Debug, Clone)]
+enum Arg {
+    Str(String),
+    Nums(Vec<f64>),
+    Strs(Vec<String>),
+}
+
+fn group(tokens: &[Token]) -> io::Result<Vec<Arg>> {
+    let mut args = Vec::new();
+    let mut iter = tokens.iter();
+    while let Some(token) = iter.next() {
+        args.push(match token {
+            Token::Str(s) => Arg::Str(s.clone()),
+            Token::Num(n) => Arg::Nums(vec![*n]),
+            Token::Open => {
+                let (mut nums, mut strs) = (Vec::new(), Vec::new());
+                for token in iter.by_ref().take_while(|&t| *t != Token::Close) {
+                    match token {
+                        Token::Num(n) => nums.push(*n),
+                        Token::Str(s) => strs.push(s.clone()),
+                        _ => return Err(invalid("nested brackets")),
+                    }
+                }
+                if strs.is_empty() {
+                    Arg::Nums(nums)
+                } else {
+                    Arg::Strs(strs)
+                }
+            }
+            _ => return Err(invalid("unexpected token")),
+        });
+    }
+    Ok(args)
+}
+
+/// Leading numbers, like the arguments of `Translate`
+fn numbers(args: &[Arg]) -> Vec<f32> {
+    args.iter()
+        .take_while(|a| match a {
+            Arg::Nums(_) => true,
+            _ => false,
+        })
+        .flat_map(|a| match a {
+            Arg::Nums(n) => n.iter().map(|&n| n as f32).collect(),
+            _ => Vec::new(),
+        })
+        .collect()
+}
+
+/// The leading string, like the shape name in `Shape "sphere"`
+fn name(args: &[Arg]) -> io::Result<&str> {
+    match args.first() {
+        Some(Arg::Str(s)) => Ok(s),
+        _ => Err(invalid("missing name")),
+    }
+}
+
+/// Typed parameter lists like `"float radius" [1]`. The declared types are
+/// ignored since the names are enough to tell them apart.
+#[derive(Debug, Default)]
+struct Params {
+    nums: HashMap<String, Vec<f64>>,
+    strs: HashMap<String, Vec<String>>,
+}
+
+impl Params {
+    fn parse(args: &[Arg]) -> io::Result<Self> {
+        let mut params = Params::default();
+        for pair in args.chunks(2) {
+            let key = match pair {
+                [Arg::Str(key), _] => key,
+                _ => return Err(invalid("expected a parameter")),
+            };
+            let name = key.split_whitespace().last().unwrap_or("").to_string();
+            match &pair[1] {
+                Arg::Nums(n) => {
+                    params.nums.insert(name, n.clone());
+                }
+                Arg::Strs(s) => {
+                    params.strs.insert(name, s.clone());
+                }
+                Arg::Str(s) => {
+                    params.strs.insert(name, vec![s.clone()]);
+                }
+            }
+        }
+        Ok(params)
+    }
+
+    fn float(&self, name: &str, default: f32) -> f32 {
+        self.nums
+            .get(name)
+            .and_then(|n| n.first())
+            .map_or(default, |&n| n as f32)
+    }
+
+    fn floats(&self, name: &str) -> Vec<f32> {
+        self.nums
+            .get(name)
+            .map_or_else(Vec::new, |n| n.iter().map(|&n| n as f32).collect())
+    }
+
+    fn vecs(&self, name: &str) -> Vec<Vec3> {
+        self.floats(name)
+            .chunks(3)
+            .filter(|c| c.len() == 3)
+            .map(|c| Vec3::new(c[0], c[1], c[2]))
+            .collect()
+    }
+
+    /// rgb values or a single gray value. Spectrum, blackbody and texture
+    /// parameters aren't supported and give the default.
+    fn color(&self, name: &str, default: Color) -> Color {
+        if self.strs.contains_key(name) {
+            eprintln!("pbrt: texture parameter {} isn't supported", name);
+        }
+        match self.nums.get(name).map(Vec::as_slice) {
+            Some([r, g, b]) => Color::new(*r as f32, *g as f32, *b as f32),
+            Some([gray]) => Color::from(*gray as f32),
+            _ => default,
+        }
+    }
+
+    fn string(&self, name: &str) -> Option<&str> {
+        self.strs
+            .get(name)
+            .and_then(|s| s.first())
+            .map(String::as_str)
+    }
+}
+
+fn material(kind: &str, params: &Params) -> Arc<dyn Material> {
+    let brightest = |c: Color| c.x.max(c.y).max(c.z);
+    match kind {
+        "matte" => Arc::new(Diffuse::new(Solid::new(
+            params.color("Kd", Color::from(0.5)),
+        ))),
+        // there's no layered material so plastic is whichever of its
+        // components is stronger
+        "plastic" => {
+            let kd = params.color("Kd", Color::from(0.25));
+            let ks = params.color("Ks", Color::from(0.25));
+            if brightest(ks) > brightest(kd) {
+                Arc::new(Specular::new(ks, params.float("roughness", 0.1)))
+            } else {
+                Arc::new(Diffuse::new(Solid::new(kd)))
+            }
+        }
+        "metal" => {
+            // reflectance at normal incidence from the complex index of
+            // refraction, which defaults to copper
+            let eta = params.color("eta", Color::new(0.2, 0.92, 1.1));
+            let k = params.color("k", Color::new(3.91, 2.45, 2.14));
+            let one = Color::from(1);
+            let f0 =
+                ((eta - one) * (eta - one) + k * k) / ((eta + one) * (eta + one) + k * k);
+            let roughness = params.float("roughness", params.float("uroughness", 0.01));
+            Arc::new(Specular::new(f0, roughness))
+        }
+        "glass" => {
+            let index = params.float("index", params.float("eta", 1.5));
+            Arc::new(Dielectric::new(params.color("Kt", Color::from(1)), index))
+        }
+        "mirror" => Arc::new(Specular::new(params.color("Kr", Color::from(0.9)), 0.0)),
+        _ => {
+            eprintln!("pbrt: unsupported material {}, using matte", kind);
+            material("matte", params)
+        }
+    }
+}
+
+#[derive(Clone)]
+struct Attributes {
+    transform: Matrix4,
+    material: Arc<dyn Material>,
+    area_light: Option<Arc<dyn Material>>,
+}
+
+struct State {
+    dir: PathBuf,
+    attributes: Attributes,
+    attribute_stack: Vec<Attributes>,
+    transform_stack: Vec<Matrix4>,
+    coordinate_systems: HashMap<String, Matrix4>,
+    named_materials: HashMap<String, Arc<dyn Material>>,
+    /// Shapes in an `ObjectBegin` block are collected separately to be
+    /// instanced later
+    object: Option<(String, Vec<Box<dyn Hitable>>)>,
+    prototypes: HashMap<String, Arc<dyn Hitable>>,
+    objects: Vec<Box<dyn Hitable>>,
+    /// Camera to world transform and the camera's parameters
+    camera: (Matrix4, Params),
+    width: usize,
+    height: usize,
+    samples: u16,
+    bounces: u16,
+    background: Environment,
+}
+
+impl State {
+    fn new(dir: &Path) -> Self {
+        State {
+            dir: dir.to_path_buf(),
+            attributes: Attributes {
+                transform: Matrix4::identity(),
+                material: material("matte", &Params::default()),
+                area_light: None,
+            },
+            attribute_stack: Vec::new(),
+            transform_stack: Vec::new(),
+            coordinate_systems: HashMap::new(),
+            named_materials: HashMap::new(),
+            object: None,
+            prototypes: HashMap::new(),
+            objects: Vec::new(),
+            camera: (Matrix4::identity(), Params::default()),
+            width: 1280,
+            height: 720,
+            samples: 16,
+            bounces: 5,
+            background: Environment::Uniform(Color::zero()),
+        }
+    }
+
+    fn run(&mut self, tokens: &[Token]) -> io::Result<()> {
+        let mut i = 0;
+        while i < tokens.len() {
+            let directive = match &tokens[i] {
+                Token::Directive(d) => d.as_str(),
+                _ => return Err(invalid("expected a directive")),
+            };
+            let end = tokens[i + 1..]
+                .iter()
+                .position(|t| match t {
+                    Token::Directive(_) => true,
+                    _ => false,
+                })
+                .map_or(tokens.len(), |j| i + 1 + j);
+            self.directive(directive, &group(&tokens[i + 1..end])?)?;
+            i = end;
+        }
+        Ok(())
+    }
+
+    fn concat(&mut self, m: Matrix4) {
+        self.attributes.transform = self.attributes.transform * m;
+    }
+
+    /// Object to render space for the current shape
+    fn shape_transform(&self) -> Matrix4 {
+        if self.object.is_some() {
+            // instances get mirrored when they're placed instead
+            self.attributes.transform
+        } else {
+            to_right_handed() * self.attributes.transform
+        }
+    }
+
+    fn directive(&mut self, directive: &str, args: &[Arg]) -> io::Result<()> {
+        let n = numbers(args);
+        let expect = |count: usize| {
+            if n.len() == count {
+                Ok(())
+            } else {
+                Err(invalid(&format!("{} needs {} numbers", directive, count)))
+            }
+        };
+        match directive {
+            "Identity" => self.attributes.transform = Matrix4::identity(),
+            "Translate" => {
+                expect(3)?;
+                self.concat(Matrix4::translate(Vec3::new(n[0], n[1], n[2])));
+            }
+            "Scale" => {
+                expect(3)?;
+                self.concat(Matrix4::scale(Vec3::new(n[0], n[1], n[2])));
+            }
+            "Rotate" => {
+                expect(4)?;
+                self.concat(Matrix4::rotate(Vec3::new(n[1], n[2], n[3]), n[0]));
+            }
+            "LookAt" => {
+                expect(9)?;
+                let look_at = Matrix4::look_at(
+                    Vec3::new(n[0], n[1], n[2]),
+                    Vec3::new(n[3], n[4], n[5]),
+                    Vec3::new(n[6], n[7], n[8]),
+                );
+                self.concat(
+                    look_at
+                        .inverse()
+                        .ok_or_else(|| invalid("degenerate LookAt"))?,
+                );
+            }
+            "Transform" | "ConcatTransform" => {
+                expect(16)?;
+                // the matrix is written column by column
+                let mut m = [[0.0; 4]; 4];
+                for (i, v) in n.iter().enumerate() {
+                    m[i % 4][i / 4] = *v;
+                }
+                if directive == "Transform" {
+                    self.attributes.transform = Matrix4::identity();
+                }
+                self.concat(Matrix4::new(m));
+            }
+            "CoordinateSystem" => {
+                self.coordinate_systems
+                    .insert(name(args)?.to_string(), self.attributes.transform);
+            }
+            "CoordSysTransform" => match self.coordinate_systems.get(name(args)?) {
+                Some(&m) => self.attributes.transform = m,
+                None => eprintln!("pbrt: unknown coordinate system {}", name(args)?),
+            },
+            "TransformBegin" => self.transform_stack.push(self.attributes.transform),
+            "TransformEnd" => {
+                self.attributes.transform = self
+                    .transform_stack
+                    .pop()
+                    .ok_or_else(|| invalid("unmatched TransformEnd"))?
+            }
+            "AttributeBegin" => self.attribute_stack.push(self.attributes.clone()),
+            "AttributeEnd" => {
+                self.attributes = self
+                    .attribute_stack
+                    .pop()
+                    .ok_or_else(|| invalid("unmatched AttributeEnd"))?
+            }
+            "Camera" => {
+                if name(args)? != "perspective" {
+                    eprintln!(
+                        "pbrt: unsupported camera {}, using perspective",
+                        name(args)?
+                    );
+                }
+                let to_world = self
+                    .attributes
+                    .transform
+                    .inverse()
+                    .ok_or_else(|| invalid("camera transform isn't invertible"))?;
+                self.coordinate_systems
+                    .insert("camera".to_string(), to_world);
+                self.camera = (to_world, Params::parse(&args[1..])?);
+            }
+            "Film" => {
+                let params = Params::parse(&args[1..])?;
+                self.width = params.float("xresolution", 1280.0) as usize;
+                self.height = params.float("yresolution", 720.0) as usize;
+            }
+            "Sampler" => {
+                let params = Params::parse(&args[1..])?;
+                self.samples = params.float("pixelsamples", 16.0) as u16;
+            }
+            "Integrator" => {
+                let params = Params::parse(&args[1..])?;
+                self.bounces = params.float("maxdepth", 5.0) as u16;
+            }
+            "WorldBegin" => {
+                self.attributes.transform = Matrix4::identity();
+                self.coordinate_systems
+                    .insert("world".to_string(), Matrix4::identity());
+            }
+            "Material" => {
+                self.attributes.material =
+                    material(name(args)?, &Params::parse(&args[1..])?)
+            }
+            "MakeNamedMaterial" => {
+                let params = Params::parse(&args[1..])?;
+                let kind = params.string("type").unwrap_or("matte").to_string();
+                self.named_materials
+                    .insert(name(args)?.to_string(), material(&kind, &params));
+            }
+            "NamedMaterial" => match self.named_materials.get(name(args)?) {
+                Some(m) => self.attributes.material = m.clone(),
+                None => eprintln!("pbrt: unknown material {}", name(args)?),
+            },
+            "AreaLightSource" => {
+                let params = Params::parse(&args[1..])?;
+                let emission = params.color("L", Color::from(1))
+                    * params.color("scale", Color::from(1));
+                self.attributes.area_light =
+                    Some(Arc::new(Light::new(Solid::new(emission))));
+            }
+            "LightSource" => self.light(name(args)?, &Params::parse(&args[1..])?)?,
+            "Shape" => {
+                if let Some(shape) =
+                    self.shape(name(args)?, &Params::parse(&args[1..])?)?
+                {
+                    match &mut self.object {
+                        Some((_, shapes)) => shapes.push(shape),
+                        None => self.objects.push(shape),
+                    }
+                }
+            }
+            "ObjectBegin" => {
+                self.attribute_stack.push(self.attributes.clone());
+                self.object = Some((name(args)?.to_string(), Vec::new()));
+            }
+            "ObjectEnd" => {
+                if let Some((name, mut shapes)) = self.object.take() {
+                    if !shapes.is_empty() {
+                        let prototype: Arc<dyn Hitable> =
+                            Arc::new(BVHNode::from(&mut shapes));
+                        self.prototypes.insert(name, prototype);
+                    }
+                }
+                self.attributes = self
+                    .attribute_stack
+                    .pop()
+                    .ok_or_else(|| invalid("unmatched ObjectEnd"))?;
+            }
+            "ObjectInstance" => match self.prototypes.get(name(args)?) {
+                Some(prototype) => {
+                    // instances flattened to nothing can't be seen
+                    let transform = to_right_handed() * self.attributes.transform;
+                    if let Some(instance) = Instance::new(prototype.clone(), transform) {
+                        self.objects.push(Box::new(instance));
+                    }
+                }
+                None => eprintln!("pbrt: unknown object {}", name(args)?),
+            },
+            "Include" => {
+                let path = self.dir.join(name(args)?);
+                self.run(&tokenize(&std::fs::read_to_string(path)?)?)?;
+            }
+            // these don't change what the scene looks like
+            "WorldEnd" | "PixelFilter" | "Accelerator" | "ReverseOrientation" => {}
+            _ => eprintln!("pbrt: skipping unsupported directive {}", directive),
+        }
+        Ok(())
+    }
+
+    fn light(&mut self, kind: &str, params: &Params) -> io::Result<()> {
+        if kind != "infinite" {
+            eprintln!(
+                "pbrt: skipping {} light, only area and infinite lights are supported",
+                kind
+            );
+            return Ok(());
+        }
+        let color =
+            params.color("L", Color::from(1)) * params.color("scale", Color::from(1));
+        self.background = match params.string("mapname") {
+            None => Environment::Uniform(color),
+            Some(map) => {
+                let path = self.dir.join(map);
+                let image = if map.ends_with(".hdr") {
+                    load_hdr(&path.to_string_lossy())?
+                } else {
+                    let img = image::open(&path)
+                        .map_err(|e| invalid(&e.to_string()))?
+                        .to_rgb();
+                    let pixels = img
+                        .pixels()
+                        .flat_map(|p| p.data.to_vec())
+                        .map(|c| f32::from(c) / 255.0);
+                    ImageBuffer::from_vec(img.width(), img.height(), pixels.collect())
+                        .unwrap()
+                };
+                let to_world = to_right_handed() * self.attributes.transform;
+                Environment::Map {
+                    image,
+                    to_light: to_world
+                        .inverse()
+                        .ok_or_else(|| invalid("light transform isn't invertible"))?,
+                    scale: color,
+                }
+            }
+        };
+        Ok(())
+    }
+
+    fn shape(&self, kind: &str, params: &Params) -> io::Result<Option<Box<dyn Hitable>>> {
+        let material = self
+            .attributes
+            .area_light
+            .clone()
+            .unwrap_or_else(|| self.attributes.material.clone());
+        let transform = self.shape_transform();
+        let mesh = |data: &MeshData| -> io::Result<Option<Box<dyn Hitable>>> {
+            if data
+                .indices
+                .iter()
+                .any(|&i| i as usize >= data.positions.len())
+            {
+                return Err(invalid("index out of range"));
+            }
+            if data.indices.is_empty() {
+                return Ok(None);
+            }
+            Ok(Some(Box::new(Mesh::from_data(
+                data,
+                transform,
+                material.clone(),
+            ))))
+        };
+        match kind {
+            "sphere" => {
+                let sphere = Sphere::new(
+                    Vec3::zero(),
+                    params.float("radius", 1.0),
+                    material.clone(),
+                );
+                // a sphere flattened to nothing can't be seen
+                Ok(Transform::new(sphere, transform)
+                    .map(|sphere| Box::new(sphere) as Box<dyn Hitable>))
+            }
+            "trianglemesh" => {
+                let texcoords = if params.nums.contains_key("uv") {
+                    "uv"
+                } else {
+                    "st"
+                };
+                let texcoords = params.floats(texcoords);
+                if texcoords.len() % 2 != 0 {
+                    return Err(invalid("texture coordinates don't come in pairs"));
+                }
+                mesh(&MeshData {
+                    positions: params.vecs("P"),
+                    normals: params.vecs("N"),
+                    texcoords: texcoords
+                        .chunks(2)
+                        .map(|t| Vec3::new(t[0], t[1], 0.0))
+                        .collect(),
+                    colors: Vec::new(),
+                    indices: params.floats("indices").iter().map(|&i| i as u32).collect(),
+                    smoothing_groups: Vec::new(),
+                })
+            }
+            "plymesh" => {
+                let filename = params
+                    .string("filename")
+                    .ok_or_else(|| invalid("plymesh without a filename"))?;
+                mesh(&ply::load(&self.dir.join(filename).to_string_lossy())?)
+            }
+            _ => {
+                eprintln!("pbrt: skipping unsupported shape {}", kind);
+                Ok(None)
+            }
+        }
+    }
+
+    fn finish(mut self) -> io::Result<Scene<Environment>> {
+        if self.objects.is_empty() {
+            return Err(invalid("scene has no shapes"));
+        }
+        let (to_world, params) = &self.camera;
+        let to_world = to_right_handed() * *to_world;
+        let aspect = self.width as f32 / self.height as f32;
+        // pbrt's fov is for the shorter side of the image
+        let fov = params.float("fov", 90.0);
+        let fov = if aspect < 1.0 {
+            2.0 * ((fov.to_radians() / 2.0).tan() / aspect)
+                .atan()
+                .to_degrees()
+        } else {
+            fov
+        };
+        let lens_radius = params.float("lensradius", 0.0);
+        // rays are as long as the focus distance, and pbrt's default of 1e6
+        // would push every hit below the intersection epsilon
+        let focus_dist = if lens_radius > 0.0 {
+            params.float("focaldistance", 1e6)
+        } else {
+            1.0
+        };
+        let camera = Camera::with_focus(
+            to_world.transform_point(Vec3::zero()),
+            to_world.transform_point(Vec3::new(0, 0, 1)),
+            to_world.transform_vector(Vec3::new(0, 1, 0)),
+            fov,
+            aspect,
+            lens_radius * 2.0,
+            focus_dist,
+        );
+        Ok(Scene {
+            width: self.width,
+            height: self.height,
+            objects: BVHNode::from(&mut self.objects),
+            camera,
+            samples: self.samples,
+            bounces: self.bounces,
+            show_bg: true,
+            atmosphere: None,
+            lights: Vec::new(),
+            background: self.background,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SCENE: &str = r#"
+        # a glass ball to the right of the view and a light overhead
+        LookAt 0 0 -5  0 0 0  0 1 0
+        Camera "perspective" "float fov" [45]
+        Film "image" "integer xresolution" [200] "integer yresolution" [100]
+        Sampler "halton" "integer pixelsamples" 8
+        Integrator "path" "integer maxdepth" [7]
+        WorldBegin
+        LightSource "infinite" "rgb L" [.5 .5 .5]
+        AttributeBegin
+            Material "glass"
+            Translate 1 0 0
+            Shape "sphere" "float radius" 0.5
+        AttributeEnd
+        AttributeBegin
+            AreaLightSource "diffuse" "rgb L" [4 4 4]
+            Shape "trianglemesh" "integer indices" [0 1 2]
+                "point P" [-1 2 -1  1 2 -1  0 2 1]
+        AttributeEnd
+        WorldEnd
+    "#;
+
+    #[test]
+    fn test_tokenize() {
+        let tokens = tokenize("Shape \"sphere\" # comment\n \"bool b\" [true]").unwrap();
+        assert_eq!(
+            tokens,
+            vec![
+                Token::Directive("Shape".to_string()),
+                Token::Str("sphere".to_string()),
+                Token::Str("bool b".to_string()),
+                Token::Open,
+                Token::Str("true".to_string()),
+                Token::Close,
+            ]
+        );
+        assert!(tokenize("Translate [1 2").is_err());
+    }
+
+    #[test]
+    fn test_settings() {
+        let scene = parse(SCENE, Path::new("")).unwrap();
+        assert_eq!((scene.width, scene.height), (200, 100));
+        assert_eq!((scene.samples, scene.bounces), (8, 7));
+        let r = Ray::new(Vec3::zero(), Vec3::new(0, 0, 1));
+        assert_eq!(scene.background.get_color(r), Color::from(0.5));
+    }
+
+    #[test]
+    fn test_handedness() {
+        let scene = parse(SCENE, Path::new("")).unwrap();
+        // the ball is 1 unit off center at 5 units away, and pbrt puts it on
+        // the right side of the image
+        let offset = 1.0 / (5.0 * (22.5f32.to_radians().tan() * 2.0));
+        let hit = |h: f32| {
+            let r = scene.camera.get_ray(0.5 + offset / 2.0 * h, 0.5);
+            scene.objects.hit(r, 0.001, std::f32::MAX).is_some()
+        };
+        assert!(hit(1.0));
+        assert!(!hit(-1.0));
+    }
+
+    #[test]
+    fn test_area_light() {
+        let scene = parse(SCENE, Path::new("")).unwrap();
+        let r = Ray::new(Vec3::zero(), Vec3::new(0, 1, 0));
+        let hit = scene.objects.hit(r, 0.001, std::f32::MAX).unwrap();
+        assert!((hit.t - 2.0).abs() < 1e-4);
+        assert_eq!(hit.material.emit(0.0, 0.0, hit.point), Color::from(4));
+    }
+
+    #[test]
+    fn test_instances() {
+        let scene = parse(
+            r#"WorldBegin
+            MakeNamedMaterial "steel" "string type" "metal"
+            ObjectBegin "ball"
+                NamedMaterial "steel"
+                Shape "sphere"
+            ObjectEnd
+            AttributeBegin Translate 0 0 10 ObjectInstance "ball" AttributeEnd
+            AttributeBegin Translate 0 0 20 ObjectInstance "ball" AttributeEnd"#,
+            Path::new(""),
+        )
+        .unwrap();
+        let r = Ray::new(Vec3::new(0, 0, 15), Vec3::new(0, 0, 1));
+        assert!(
+            (scene.objects.hit(r, 0.001, std::f32::MAX).unwrap().t - 4.0).abs() < 1e-4
+        );
+        let r = Ray::new(Vec3::new(0, 0, 15), Vec3::new(0, 0, -1));
+        assert!(
+            (scene.objects.hit(r, 0.001, std::f32::MAX).unwrap().t - 4.0).abs() < 1e-4
+        );
+    }
+
+    #[test]
+    fn test_flattened() {
+        // scaled to nothing, only the last ball is left
+        let scene = parse(
+            r#"WorldBegin
+            ObjectBegin "ball" Shape "sphere" ObjectEnd
+            AttributeBegin Scale 1 1 0 Shape "sphere" AttributeEnd
+            AttributeBegin Scale 0 0 0 ObjectInstance "ball" AttributeEnd
+            AttributeBegin Translate 0 0 10 Shape "sphere" AttributeEnd"#,
+            Path::new(""),
+        )
+        .unwrap();
+        let r = Ray::new(Vec3::new(0, 0, -5), Vec3::new(0, 0, 1));
+        assert!(
+            (scene.objects.hit(r, 0.001, std::f32::MAX).unwrap().t - 14.0).abs() < 1e-4
+        );
+    }
+
+    #[test]
+    fn test_bad_params() {
+        // an empty list falls back to the default
+        let scene = parse(
+            r#"WorldBegin Shape "sphere" "float radius" []"#,
+            Path::new(""),
+        )
+        .unwrap();
+        let r = Ray::new(Vec3::new(0, 0, -5), Vec3::new(0, 0, 1));
+        assert!(
+            (scene.objects.hit(r, 0.001, std::f32::MAX).unwrap().t - 4.0).abs() < 1e-4
+        );
+        let odd = parse(
+            r#"WorldBegin Shape "trianglemesh" "integer indices" [0 1 2]
+                "point P" [0 0 0  1 0 0  0 1 0] "float uv" [0 0 1 0 1]"#,
+            Path::new(""),
+        );
+        assert_eq!(odd.err().unwrap().kind(), ErrorKind::InvalidData);
+        let dir = std::env::temp_dir().join("ray_pbrt_hdr");
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("broken.hdr"), "#?RADIANCE\nnot really\n").unwrap();
+        let broken = parse(
+            r#"WorldBegin LightSource "infinite" "string mapname" "broken.hdr"
+                Shape "sphere""#,
+            &dir,
+        );
+        assert_eq!(broken.err().unwrap().kind(), ErrorKind::InvalidData);
+    }
+}
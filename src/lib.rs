@@ -1,31 +1,48 @@
+pub mod animation;
+pub mod atmosphere;
 pub mod axis;
 pub mod background;
 pub mod bvh;
 pub mod camera;
 pub mod geometry;
+pub mod import;
 pub mod material;
+pub mod matrix;
+pub mod quaternion;
 pub mod ray;
 pub mod scene;
+pub mod scene_graph;
 pub mod texture;
 pub mod vec3;
 
 #[macro_export]
 macro_rules! use_all {
     () => {
+        use ray::animation::*;
+        use ray::atmosphere::Atmosphere;
         use ray::axis::Axis;
         use ray::background::Gradient;
         use ray::bvh::BVHNode;
-        use ray::camera::Camera;
+        use ray::camera::{Camera, Eyes, Lens, OdsCamera, StereoCamera, StereoLayout};
         use ray::geometry::{
-            mesh::Mesh, prism::Prism, rect::Rect, sphere::Sphere, transform::*, Hitable,
+            capsule::Capsule, cone::Cone, csg::*, curve::*, cylinder::Cylinder,
+            disk::Disk, displacement::Displacement, heightfield::Heightfield,
+            instance::Instance, mesh::Mesh, plane::Plane, prism::Prism, quad::Quad,
+            rect::Rect, sdf::Sdf, sphere::Sphere, strands::*, subdivision::*,
+            torus::Torus, transform::*, triangle_mesh::TriangleMesh, volume::*, voxel::*,
+            Hitable,
         };
         use ray::material::{
-            dielectric::Dielectric, diffuse::Diffuse, isotropic::Isotropic, light::Light,
-            specular::Specular,
+            dielectric::Dielectric, diffuse::Diffuse, hair::Hair, isotropic::Isotropic,
+            light::Light, specular::Specular,
         };
+        use ray::matrix::Matrix4;
+        use ray::quaternion::Quaternion;
         use ray::scene::*;
+        use ray::scene_graph::SceneNode;
         use ray::texture::{
             checker::*, gradient::SimpleGradient, image::*, perlin::*, solid::Solid,
+            tinted::Tinted,
         };
         use ray::vec3::Vec3;
         use std::sync::Arc;
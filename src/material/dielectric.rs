@@ -77,4 +77,7 @@ impl Material for Dielectric {
             ),
         ))
     }
+    fn two_sided(&self) -> bool {
+        false
+    }
 }
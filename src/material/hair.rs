@@ -0,0 +1,466 @@
+use super::Material;
+use crate::geometry::tangents;
+use crate::ray::Ray;
+use crate::scene::Color;
+use crate::vec3::Vec3;
+use rand::random;
+use std::f32::consts::PI;
+
+#[macro_export]
+macro_rules! hair {
+    ($color:expr) => {
+        Arc::new(Hair::new($color.into()))
+    };
+}
+
+/// Lobes past the first 3 are lumped into one
+const P_MAX: usize = 3;
+
+/// Scattering from a rough dielectric cylinder with a pigmented interior, as
+/// in "A Practical and Controllable Hair and Fur Model for Production Path
+/// Tracing" by Chiang et al, which builds on d'Eon's energy conserving
+/// version of Marschner's model. Light reflects off the surface (R), goes
+/// straight through (TT), or reflects inside once (TRT) or more, each lobe
+/// spread around the cone the fiber's direction makes with the ray.
+///
+/// It needs curves with the cylinder shape, which supply the direction the
+/// fiber runs in and a normal that says where across it the ray hit.
+#[derive(Debug, Clone)]
+pub struct Hair {
+    /// How much each channel is absorbed per unit of fiber crossed
+    sigma_a: Color,
+    eta: f32,
+    /// Variance of the longitudinal spread for each lobe
+    v: [f32; P_MAX + 1],
+    /// Azimuthal logistic scale
+    s: f32,
+    /// Sines and cosines of the scales' tilt, doubled and quadrupled
+    sin_2k_alpha: [f32; 3],
+    cos_2k_alpha: [f32; 3],
+}
+
+impl Hair {
+    /// Hair that looks about `color` after many bounces. Colors go through
+    /// the absorption that would produce them, so very saturated colors get
+    /// dark.
+    pub fn new(color: Color) -> Self {
+        Hair::with_absorption(Hair::absorption_for(color, 0.3), 0.3, 0.3, 2.0)
+    }
+
+    /// Natural hair from its concentrations of black-brown eumelanin and
+    /// red-yellow pheomelanin. Around 0.05 of eumelanin is blonde, 1.3 brown
+    /// and 8 black.
+    pub fn from_melanin(eumelanin: f32, pheomelanin: f32) -> Self {
+        let absorption = Color::new(0.419, 0.697, 1.37) * eumelanin
+            + Color::new(0.187, 0.4, 1.05) * pheomelanin;
+        Hair::with_absorption(absorption, 0.3, 0.3, 2.0)
+    }
+
+    /// `beta_m` and `beta_n` from 0 to 1 are the longitudinal and azimuthal
+    /// roughness, `alpha` the tilt of the scales on the surface in degrees
+    pub fn with_absorption(sigma_a: Color, beta_m: f32, beta_n: f32, alpha: f32) -> Self {
+        let v0 =
+            (0.726 * beta_m + 0.812 * beta_m.powi(2) + 3.7 * beta_m.powi(20)).powi(2);
+        let s = 0.626_657
+            * (0.265 * beta_n + 1.194 * beta_n.powi(2) + 5.372 * beta_n.powi(22));
+        let mut sin_2k_alpha = [alpha.to_radians().sin(), 0.0, 0.0];
+        let mut cos_2k_alpha = [safe_sqrt(1.0 - sin_2k_alpha[0].powi(2)), 0.0, 0.0];
+        for i in 1..3 {
+            sin_2k_alpha[i] = 2.0 * cos_2k_alpha[i - 1] * sin_2k_alpha[i - 1];
+            cos_2k_alpha[i] = cos_2k_alpha[i - 1].powi(2) - sin_2k_alpha[i - 1].powi(2);
+        }
+        Hair {
+            sigma_a,
+            eta: 1.55,
+            v: [v0, 0.25 * v0, 4.0 * v0, 4.0 * v0],
+            s,
+            sin_2k_alpha,
+            cos_2k_alpha,
+        }
+    }
+
+    /// The absorption that gives roughly `color` after multiple scattering,
+    /// fit by Chiang et al for the azimuthal roughness
+    pub fn absorption_for(color: Color, beta_n: f32) -> Color {
+        let denom = 5.969 - 0.215 * beta_n + 2.532 * beta_n.powi(2)
+            - 10.73 * beta_n.powi(3)
+            + 5.574 * beta_n.powi(4)
+            + 0.245 * beta_n.powi(5);
+        let channel = |c: f32| (c.max(1e-4).ln() / denom).powi(2);
+        Color::new(channel(color.x), channel(color.y), channel(color.z))
+    }
+
+    /// The longitudinal angle of the outgoing direction tilted by the scales
+    /// for lobe p
+    fn tilt(&self, p: usize, sin_theta_o: f32, cos_theta_o: f32) -> (f32, f32) {
+        let (sin, cos) = match p {
+            0 => (-self.sin_2k_alpha[1], self.cos_2k_alpha[1]),
+            1 => (self.sin_2k_alpha[0], self.cos_2k_alpha[0]),
+            2 => (self.sin_2k_alpha[2], self.cos_2k_alpha[2]),
+            _ => return (sin_theta_o, cos_theta_o),
+        };
+        (
+            sin_theta_o * cos + cos_theta_o * sin,
+            (cos_theta_o * cos - sin_theta_o * sin).abs(),
+        )
+    }
+
+    /// How much light each lobe carries, and where inside the fiber the
+    /// refracted ray goes
+    fn attenuation(
+        &self,
+        sin_theta_o: f32,
+        cos_theta_o: f32,
+        h: f32,
+    ) -> ([Color; P_MAX + 1], f32) {
+        let sin_theta_t = sin_theta_o / self.eta;
+        let cos_theta_t = safe_sqrt(1.0 - sin_theta_t.powi(2));
+        let etap = (self.eta * self.eta - sin_theta_o.powi(2)).sqrt() / cos_theta_o;
+        let sin_gamma_t = h / etap;
+        let cos_gamma_t = safe_sqrt(1.0 - sin_gamma_t.powi(2));
+        let path = 2.0 * cos_gamma_t / cos_theta_t;
+        let transmittance = Color::new(
+            (-self.sigma_a.x * path).exp(),
+            (-self.sigma_a.y * path).exp(),
+            (-self.sigma_a.z * path).exp(),
+        );
+        let f = fresnel(cos_theta_o * safe_sqrt(1.0 - h * h), self.eta);
+        let mut ap = [Color::zero(); P_MAX + 1];
+        ap[0] = Color::from(f);
+        ap[1] = transmittance * (1.0 - f).powi(2);
+        for p in 2..P_MAX {
+            ap[p] = ap[p - 1] * transmittance * f;
+        }
+        ap[P_MAX] =
+            ap[P_MAX - 1] * transmittance * f / (Color::from(1) - transmittance * f);
+        (ap, safe_asin(sin_gamma_t))
+    }
+
+    /// The BSDF times the cosine, and the pdf of `sample` picking `wi`. Both
+    /// directions are in the fiber's frame with x along it.
+    fn eval(&self, wo: Vec3, wi: Vec3, h: f32) -> (Color, f32) {
+        let (sin_theta_o, cos_theta_o) = (wo.x, safe_sqrt(1.0 - wo.x * wo.x));
+        let (sin_theta_i, cos_theta_i) = (wi.x, safe_sqrt(1.0 - wi.x * wi.x));
+        let phi = wi.z.atan2(wi.y) - wo.z.atan2(wo.y);
+        let gamma_o = safe_asin(h);
+        let (ap, gamma_t) = self.attenuation(sin_theta_o, cos_theta_o, h);
+        let weights = lobe_weights(&ap);
+        let mut f = Color::zero();
+        let mut pdf = 0.0;
+        for p in 0..P_MAX {
+            let (sin_op, cos_op) = self.tilt(p, sin_theta_o, cos_theta_o);
+            let m = mp(cos_theta_i, cos_op, sin_theta_i, sin_op, self.v[p]);
+            let n = np(phi, p, self.s, gamma_o, gamma_t);
+            f += ap[p] * (m * n);
+            pdf += weights[p] * m * n;
+        }
+        let m = mp(
+            cos_theta_i,
+            cos_theta_o,
+            sin_theta_i,
+            sin_theta_o,
+            self.v[P_MAX],
+        );
+        f += ap[P_MAX] * (m / (2.0 * PI));
+        pdf += weights[P_MAX] * m / (2.0 * PI);
+        (f, pdf)
+    }
+
+    /// Picks a lobe by how much light it carries, then a direction from its
+    /// longitudinal and azimuthal distributions
+    fn sample(&self, wo: Vec3, h: f32) -> Vec3 {
+        let (sin_theta_o, cos_theta_o) = (wo.x, safe_sqrt(1.0 - wo.x * wo.x));
+        let (ap, gamma_t) = self.attenuation(sin_theta_o, cos_theta_o, h);
+        let weights = lobe_weights(&ap);
+        let mut pick = random::<f32>();
+        let mut p = 0;
+        while p < P_MAX && pick >= weights[p] {
+            pick -= weights[p];
+            p += 1;
+        }
+        let (sin_op, cos_op) = self.tilt(p, sin_theta_o, cos_theta_o);
+        let u = random::<f32>().max(1e-5);
+        let v = self.v[p];
+        let cos_theta = 1.0 + v * (u + (1.0 - u) * (-2.0 / v).exp()).ln();
+        let sin_theta = safe_sqrt(1.0 - cos_theta * cos_theta);
+        let cos_phi = (2.0 * PI * random::<f32>()).cos();
+        let sin_theta_i = -cos_theta * sin_op + sin_theta * cos_phi * cos_op;
+        let cos_theta_i = safe_sqrt(1.0 - sin_theta_i * sin_theta_i);
+        let dphi = if p < P_MAX {
+            phi(p, safe_asin(h), gamma_t) + sample_trimmed_logistic(random(), self.s)
+        } else {
+            2.0 * PI * random::<f32>()
+        };
+        let phi_i = wo.z.atan2(wo.y) + dphi;
+        Vec3::new(
+            sin_theta_i,
+            cos_theta_i * phi_i.cos(),
+            cos_theta_i * phi_i.sin(),
+        )
+    }
+}
+
+impl Material for Hair {
+    /// Without the fiber's direction it guesses one across the normal
+    fn scatter(
+        &self,
+        r: Ray,
+        normal: Vec3,
+        point: Vec3,
+        u: f32,
+        v: f32,
+    ) -> Option<(Color, Ray)> {
+        self.scatter_along(r, normal, Vec3::zero(), point, u, v)
+    }
+
+    fn scatter_along(
+        &self,
+        r: Ray,
+        normal: Vec3,
+        tangent: Vec3,
+        point: Vec3,
+        _u: f32,
+        _v: f32,
+    ) -> Option<(Color, Ray)> {
+        // x along the fiber, z out of the normal. Only curves have a tangent
+        // so anything else gets one guessed across the normal.
+        let x = tangent - normal * tangent.dot(&normal);
+        let x = if x.len() > 0.0 {
+            x.normalize()
+        } else {
+            tangents(normal).0
+        };
+        let y = normal.cross(&x);
+        let to_local = |d: Vec3| Vec3::new(d.dot(&x), d.dot(&y), d.dot(&normal));
+        let wo = to_local(-r.dir.normalize());
+        // how far across the fiber it hit, from the normal's lean away from
+        // the ray around the fiber
+        let across = (wo.y * wo.y + wo.z * wo.z).sqrt();
+        let h = if across > 0.0 { -wo.y / across } else { 0.0 };
+        let wi = self.sample(wo, h);
+        let (f, pdf) = self.eval(wo, wi, h);
+        if pdf.is_nan() || pdf <= 0.0 {
+            return None;
+        }
+        let dir = x * wi.x + y * wi.y + normal * wi.z;
+        Some((f / pdf, Ray::new(point, dir)))
+    }
+}
+
+fn safe_sqrt(x: f32) -> f32 {
+    x.max(0.0).sqrt()
+}
+
+fn safe_asin(x: f32) -> f32 {
+    x.max(-1.0).min(1.0).asin()
+}
+
+/// Fresnel reflectance of a dielectric from outside
+fn fresnel(cos_theta_i: f32, eta: f32) -> f32 {
+    let cos_i = cos_theta_i.max(-1.0).min(1.0).abs();
+    let sin_t = safe_sqrt(1.0 - cos_i * cos_i) / eta;
+    if sin_t >= 1.0 {
+        return 1.0;
+    }
+    let cos_t = safe_sqrt(1.0 - sin_t * sin_t);
+    let parallel = (eta * cos_i - cos_t) / (eta * cos_i + cos_t);
+    let perpendicular = (cos_i - eta * cos_t) / (cos_i + eta * cos_t);
+    (parallel * parallel + perpendicular * perpendicular) / 2.0
+}
+
+/// Lobes are sampled in proportion to their average attenuation
+fn lobe_weights(ap: &[Color; P_MAX + 1]) -> [f32; P_MAX + 1] {
+    let luminance = |c: Color| (c.x + c.y + c.z) / 3.0;
+    let total: f32 = ap.iter().map(|&c| luminance(c)).sum();
+    let mut weights = [0.0; P_MAX + 1];
+    for (w, &c) in weights.iter_mut().zip(ap) {
+        *w = if total > 0.0 {
+            luminance(c) / total
+        } else {
+            0.0
+        };
+    }
+    weights
+}
+
+/// Modified Bessel function of the first kind
+fn i0(x: f32) -> f32 {
+    let mut value = 0.0;
+    let mut x2i = 1.0;
+    let mut factorial = 1.0f32;
+    let mut four_i = 1.0;
+    for i in 0..10 {
+        if i > 1 {
+            factorial *= i as f32;
+        }
+        value += x2i / (four_i * factorial * factorial);
+        x2i *= x * x;
+        four_i *= 4.0;
+    }
+    value
+}
+
+fn log_i0(x: f32) -> f32 {
+    if x > 12.0 {
+        x + 0.5 * (-(2.0 * PI).ln() + (1.0 / x).ln() + 1.0 / (8.0 * x))
+    } else {
+        i0(x).ln()
+    }
+}
+
+/// Longitudinal scattering
+fn mp(
+    cos_theta_i: f32,
+    cos_theta_o: f32,
+    sin_theta_i: f32,
+    sin_theta_o: f32,
+    v: f32,
+) -> f32 {
+    let a = cos_theta_i * cos_theta_o / v;
+    let b = sin_theta_i * sin_theta_o / v;
+    if v <= 0.1 {
+        // the direct form overflows for narrow lobes
+        (log_i0(a) - b - 1.0 / v + std::f32::consts::LN_2 + (1.0 / (2.0 * v)).ln()).exp()
+    } else {
+        ((-b).exp() * i0(a)) / ((1.0 / v).sinh() * 2.0 * v)
+    }
+}
+
+/// The azimuthal angle lobe p leaves at, ignoring roughness
+fn phi(p: usize, gamma_o: f32, gamma_t: f32) -> f32 {
+    2.0 * p as f32 * gamma_t - 2.0 * gamma_o + p as f32 * PI
+}
+
+fn logistic(x: f32, s: f32) -> f32 {
+    let x = x.abs();
+    (-x / s).exp() / (s * (1.0 + (-x / s).exp()).powi(2))
+}
+
+fn logistic_cdf(x: f32, s: f32) -> f32 {
+    1.0 / (1.0 + (-x / s).exp())
+}
+
+/// Azimuthal scattering, a logistic distribution cut off at -pi and pi
+fn np(phi_diff: f32, p: usize, s: f32, gamma_o: f32, gamma_t: f32) -> f32 {
+    let mut dphi = phi_diff - phi(p, gamma_o, gamma_t);
+    while dphi > PI {
+        dphi -= 2.0 * PI;
+    }
+    while dphi < -PI {
+        dphi += 2.0 * PI;
+    }
+    logistic(dphi, s) / (logistic_cdf(PI, s) - logistic_cdf(-PI, s))
+}
+
+fn sample_trimmed_logistic(u: f32, s: f32) -> f32 {
+    let k = logistic_cdf(PI, s) - logistic_cdf(-PI, s);
+    let x = -s * (1.0 / (u * k + logistic_cdf(-PI, s)) - 1.0).ln();
+    x.max(-PI).min(PI)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A uniformly random direction in the `i`th of `n` by `n` equal areas
+    /// of the sphere, which keeps integrals of sharp lobes from being noisy
+    fn stratified_sample(i: usize, n: usize) -> Vec3 {
+        let z = 1.0 - 2.0 * ((i / n) as f32 + random::<f32>()) / n as f32;
+        let r = safe_sqrt(1.0 - z * z);
+        let phi = 2.0 * PI * ((i % n) as f32 + random::<f32>()) / n as f32;
+        Vec3::new(r * phi.cos(), r * phi.sin(), z)
+    }
+
+    #[test]
+    fn test_energy() {
+        // with no absorption nothing is lost, so the reflectance integrates
+        // to about 1 in every direction
+        let white = Hair::with_absorption(Color::zero(), 0.3, 0.3, 2.0);
+        let n = 224;
+        for &(wo, h) in &[
+            (Vec3::new(0.0, 0.0, 1.0), 0.0),
+            (Vec3::new(0.5, -0.3, 0.8).normalize(), 0.4),
+            (Vec3::new(-0.7, 0.5, 0.5).normalize(), -0.8),
+        ] {
+            let sum = (0..n * n).fold(Color::zero(), |sum, i| {
+                let wi = stratified_sample(i, n);
+                sum + white.eval(wo, wi, h).0 * (4.0 * PI)
+            }) / (n * n) as f32;
+            assert!(sum.x > 0.9 && sum.x < 1.05, "{:?}", sum);
+        }
+    }
+
+    #[test]
+    fn test_pdf() {
+        // the pdf integrates to 1, and matches what sampling picks
+        let hair = Hair::from_melanin(1.3, 0.0);
+        let wo = Vec3::new(0.3, -0.2, 0.9).normalize();
+        let n = 224;
+        let samples = n * n;
+        let total = (0..samples)
+            .map(|i| hair.eval(wo, stratified_sample(i, n), 0.3).1 * 4.0 * PI)
+            .sum::<f32>()
+            / samples as f32;
+        assert!((total - 1.0).abs() < 0.05, "{}", total);
+        // the fraction of samples going in the forward hemisphere of x
+        let forward = (0..samples)
+            .filter(|_| hair.sample(wo, 0.3).x < 0.0)
+            .count() as f32
+            / samples as f32;
+        let expected = (0..samples)
+            .map(|i| {
+                let wi = stratified_sample(i, n);
+                if wi.x < 0.0 {
+                    hair.eval(wo, wi, 0.3).1 * 4.0 * PI
+                } else {
+                    0.0
+                }
+            })
+            .sum::<f32>()
+            / samples as f32;
+        assert!(
+            (forward - expected).abs() < 0.05,
+            "{} {}",
+            forward,
+            expected
+        );
+    }
+
+    #[test]
+    fn test_no_tangent() {
+        // surfaces other than curves don't have a fiber direction, it still
+        // has to scatter off them
+        let hair = Hair::from_melanin(1.3, 0.0);
+        let r = Ray::new(Vec3::new(0, 0, 1), Vec3::new(0, 0, -1));
+        let normal = Vec3::new(0, 0, 1);
+        assert!((0..100).any(|_| {
+            hair.scatter_along(r, normal, Vec3::zero(), Vec3::zero(), 0.0, 0.0)
+                .is_some()
+        }));
+    }
+
+    #[test]
+    fn test_color() {
+        let red = Hair::new(Color::new(0.8, 0.2, 0.1));
+        assert!(red.sigma_a.x < red.sigma_a.y && red.sigma_a.y < red.sigma_a.z);
+        let dark = Hair::from_melanin(8.0, 0.0);
+        let blonde = Hair::from_melanin(0.05, 0.0);
+        assert!(dark.sigma_a.x > blonde.sigma_a.x);
+        // scattering picks sensible directions and weights
+        let r = Ray::new(Vec3::new(0, 0, 5), Vec3::new(0, 0, -1));
+        for _ in 0..100 {
+            let (weight, out) = red
+                .scatter_along(
+                    r,
+                    Vec3::new(0, 0, 1),
+                    Vec3::new(1, 0, 0),
+                    Vec3::zero(),
+                    0.0,
+                    0.5,
+                )
+                .unwrap();
+            assert!((out.dir.len() - 1.0).abs() < 1e-4);
+            assert!(weight.x >= 0.0 && weight.x.is_finite());
+        }
+    }
+}
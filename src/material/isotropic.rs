@@ -3,6 +3,7 @@ use crate::ray::Ray;
 use crate::scene::Color;
 use crate::texture::Texture;
 use crate::vec3::Vec3;
+use std::f32::consts::PI;
 
 #[macro_export]
 macro_rules! isotropic {
@@ -36,4 +37,15 @@ impl<T: Texture> Material for Isotropic<T> {
             Ray::new(point, Vec3::rand_in_unit_sphere()),
         ))
     }
+    fn phase(
+        &self,
+        _r: Ray,
+        _dir: Vec3,
+        point: Vec3,
+        u: f32,
+        v: f32,
+    ) -> Option<(Color, f32)> {
+        let pdf = 1.0 / (4.0 * PI);
+        Some((self.texture.value(u, v, point) * pdf, pdf))
+    }
 }
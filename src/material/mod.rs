@@ -1,7 +1,9 @@
 pub mod dielectric;
 pub mod diffuse;
+pub mod hair;
 pub mod isotropic;
 pub mod light;
+pub mod phase;
 pub mod specular;
 
 use crate::ray::Ray;
@@ -20,6 +22,40 @@ pub trait Material: Send + Sync + std::fmt::Debug {
         u: f32,
         v: f32,
     ) -> Option<(Color, Ray)>;
+    /// Like `scatter` but with the direction the surface runs in, which
+    /// fibers like hair scatter around rather than the normal. It's zero on
+    /// surfaces that don't have one.
+    fn scatter_along(
+        &self,
+        r: Ray,
+        normal: Vec3,
+        _tangent: Vec3,
+        point: Vec3,
+        u: f32,
+        v: f32,
+    ) -> Option<(Color, Ray)> {
+        self.scatter(r, normal, point, u, v)
+    }
+    /// Whether the surface looks the same from behind, in which case it gets
+    /// the normal flipped to the side the ray came from on back faces. Glass
+    /// needs the real normal to know if the ray is going in or out.
+    fn two_sided(&self) -> bool {
+        true
+    }
+    /// For the phase functions of media, how much of the light coming from
+    /// `dir` gets scattered back along the ray, and how likely `scatter` is
+    /// to pick `dir`, both per steradian. Lights only get sampled straight from
+    /// media whose phase functions have this.
+    fn phase(
+        &self,
+        _r: Ray,
+        _dir: Vec3,
+        _point: Vec3,
+        _u: f32,
+        _v: f32,
+    ) -> Option<(Color, f32)> {
+        None
+    }
     /// Some materials could also be "emissive" meaning that they actively
     /// give off light instead of just reflecting/absorbing it
     fn emit(&self, _u: f32, _v: f32, _p: Vec3) -> Vec3 {
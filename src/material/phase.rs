@@ -0,0 +1,374 @@
+use super::Material;
+use crate::geometry::tangents;
+use crate::ray::Ray;
+use crate::scene::Color;
+use crate::texture::Texture;
+use crate::vec3::Vec3;
+use rand::random;
+use std::f32::consts::PI;
+
+#[macro_export]
+macro_rules! henyey_greenstein {
+    ($texture:expr, $g:expr) => {
+        Arc::new(HenyeyGreenstein::new($texture, $g as f32))
+    };
+}
+
+#[macro_export]
+macro_rules! rayleigh {
+    ($texture:expr) => {
+        Arc::new(Rayleigh::new($texture))
+    };
+}
+
+#[macro_export]
+macro_rules! mie {
+    ($texture:expr, $g:expr) => {
+        Arc::new(Mie::new($texture, $g as f32))
+    };
+}
+
+/// The Henyey-Greenstein phase function for a given asymmetry, per steradian
+pub fn henyey_greenstein(cos_theta: f32, g: f32) -> f32 {
+    let denom = 1.0 + g * g - 2.0 * g * cos_theta;
+    (1.0 - g * g) / (4.0 * PI * denom * denom.sqrt())
+}
+
+/// A cosine of the angle from the ray picked in proportion to
+/// `henyey_greenstein`
+fn sample_henyey_greenstein(g: f32) -> f32 {
+    let u = random::<f32>();
+    if g.abs() < 1e-3 {
+        return 1.0 - 2.0 * u;
+    }
+    let s = (1.0 - g * g) / (1.0 - g + 2.0 * g * u);
+    ((1.0 + g * g - s * s) / (2.0 * g)).max(-1.0).min(1.0)
+}
+
+/// The direction at an angle from `dir` with the given cosine, spun a random
+/// amount around it
+fn turn(dir: Vec3, cos_theta: f32) -> Vec3 {
+    let dir = dir.normalize();
+    let (a, b) = tangents(dir);
+    let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+    let phi = 2.0 * PI * random::<f32>();
+    dir * cos_theta + (a * phi.cos() + b * phi.sin()) * sin_theta
+}
+
+/// Scatters mostly forward for `g` up to 1, mostly back for `g` down to -1,
+/// and evenly at 0. Clouds are around 0.85 and smoke around 0.5.
+#[derive(Debug)]
+pub struct HenyeyGreenstein<T: Texture> {
+    texture: T,
+    g: f32,
+}
+
+impl<T: Texture> HenyeyGreenstein<T> {
+    pub fn new(texture: T, g: f32) -> Self {
+        HenyeyGreenstein {
+            texture,
+            g: g.max(-0.999).min(0.999),
+        }
+    }
+
+    pub fn value(&self, cos_theta: f32) -> f32 {
+        henyey_greenstein(cos_theta, self.g)
+    }
+}
+
+impl<T: Texture> Material for HenyeyGreenstein<T> {
+    fn scatter(
+        &self,
+        r: Ray,
+        _normal: Vec3,
+        point: Vec3,
+        u: f32,
+        v: f32,
+    ) -> Option<(Color, Ray)> {
+        let dir = turn(r.dir, sample_henyey_greenstein(self.g));
+        Some((self.texture.value(u, v, point), Ray::new(point, dir)))
+    }
+    fn phase(
+        &self,
+        r: Ray,
+        dir: Vec3,
+        point: Vec3,
+        u: f32,
+        v: f32,
+    ) -> Option<(Color, f32)> {
+        let value = self.value(r.dir.normalize().dot(&dir.normalize()));
+        Some((self.texture.value(u, v, point) * value, value))
+    }
+}
+
+/// A blend of two Henyey-Greenstein lobes, usually one forward and one back
+/// like in skin or dust. `weight` of the light goes to the first.
+#[derive(Debug)]
+pub struct DoubleHenyeyGreenstein<T: Texture> {
+    texture: T,
+    g1: f32,
+    g2: f32,
+    weight: f32,
+}
+
+impl<T: Texture> DoubleHenyeyGreenstein<T> {
+    pub fn new(texture: T, g1: f32, g2: f32, weight: f32) -> Self {
+        DoubleHenyeyGreenstein {
+            texture,
+            g1: g1.max(-0.999).min(0.999),
+            g2: g2.max(-0.999).min(0.999),
+            weight: weight.max(0.0).min(1.0),
+        }
+    }
+
+    pub fn value(&self, cos_theta: f32) -> f32 {
+        self.weight * henyey_greenstein(cos_theta, self.g1)
+            + (1.0 - self.weight) * henyey_greenstein(cos_theta, self.g2)
+    }
+}
+
+impl<T: Texture> Material for DoubleHenyeyGreenstein<T> {
+    fn scatter(
+        &self,
+        r: Ray,
+        _normal: Vec3,
+        point: Vec3,
+        u: f32,
+        v: f32,
+    ) -> Option<(Color, Ray)> {
+        let g = if random::<f32>() < self.weight {
+            self.g1
+        } else {
+            self.g2
+        };
+        let dir = turn(r.dir, sample_henyey_greenstein(g));
+        Some((self.texture.value(u, v, point), Ray::new(point, dir)))
+    }
+    fn phase(
+        &self,
+        r: Ray,
+        dir: Vec3,
+        point: Vec3,
+        u: f32,
+        v: f32,
+    ) -> Option<(Color, f32)> {
+        let value = self.value(r.dir.normalize().dot(&dir.normalize()));
+        Some((self.texture.value(u, v, point) * value, value))
+    }
+}
+
+/// Scattering off particles much smaller than the wavelength, like the air
+/// molecules that make the sky blue. It's the same forwards and back.
+#[derive(Debug)]
+pub struct Rayleigh<T: Texture> {
+    texture: T,
+}
+
+impl<T: Texture> Rayleigh<T> {
+    pub fn new(texture: T) -> Self {
+        Rayleigh { texture }
+    }
+
+    pub fn value(&self, cos_theta: f32) -> f32 {
+        3.0 / (16.0 * PI) * (1.0 + cos_theta * cos_theta)
+    }
+}
+
+impl<T: Texture> Material for Rayleigh<T> {
+    fn scatter(
+        &self,
+        r: Ray,
+        _normal: Vec3,
+        point: Vec3,
+        u: f32,
+        v: f32,
+    ) -> Option<(Color, Ray)> {
+        // inverting the cdf means solving cos^3 + 3 cos = 8u - 4, which has
+        // one real root
+        let q = 4.0 * random::<f32>() - 2.0;
+        let root = (q * q + 1.0).sqrt();
+        let cos_theta = ((q + root).cbrt() + (q - root).cbrt()).max(-1.0).min(1.0);
+        let dir = turn(r.dir, cos_theta);
+        Some((self.texture.value(u, v, point), Ray::new(point, dir)))
+    }
+    fn phase(
+        &self,
+        r: Ray,
+        dir: Vec3,
+        point: Vec3,
+        u: f32,
+        v: f32,
+    ) -> Option<(Color, f32)> {
+        let value = self.value(r.dir.normalize().dot(&dir.normalize()));
+        Some((self.texture.value(u, v, point) * value, value))
+    }
+}
+
+/// Scattering off particles around the size of the wavelength, like haze
+/// and water droplets, using the Cornette-Shanks approximation. Real Mie
+/// scattering has lots of bumps this smooths over. It's sampled like a
+/// Henyey-Greenstein with the same `g` and weighted by how much they differ.
+#[derive(Debug)]
+pub struct Mie<T: Texture> {
+    texture: T,
+    g: f32,
+}
+
+impl<T: Texture> Mie<T> {
+    /// Hazy air is around 0.75 and fog or clouds more like 0.9
+    pub fn new(texture: T, g: f32) -> Self {
+        Mie {
+            texture,
+            g: g.max(-0.999).min(0.999),
+        }
+    }
+
+    pub fn value(&self, cos_theta: f32) -> f32 {
+        let g2 = self.g * self.g;
+        let denom = 1.0 + g2 - 2.0 * self.g * cos_theta;
+        3.0 / (8.0 * PI) * (1.0 - g2) * (1.0 + cos_theta * cos_theta)
+            / ((2.0 + g2) * denom * denom.sqrt())
+    }
+}
+
+impl<T: Texture> Material for Mie<T> {
+    fn scatter(
+        &self,
+        r: Ray,
+        _normal: Vec3,
+        point: Vec3,
+        u: f32,
+        v: f32,
+    ) -> Option<(Color, Ray)> {
+        let cos_theta = sample_henyey_greenstein(self.g);
+        let weight = self.value(cos_theta) / henyey_greenstein(cos_theta, self.g);
+        let dir = turn(r.dir, cos_theta);
+        Some((
+            self.texture.value(u, v, point) * weight,
+            Ray::new(point, dir),
+        ))
+    }
+    /// Picked like a Henyey-Greenstein, so that's the pdf
+    fn phase(
+        &self,
+        r: Ray,
+        dir: Vec3,
+        point: Vec3,
+        u: f32,
+        v: f32,
+    ) -> Option<(Color, f32)> {
+        let cos_theta = r.dir.normalize().dot(&dir.normalize());
+        Some((
+            self.texture.value(u, v, point) * self.value(cos_theta),
+            henyey_greenstein(cos_theta, self.g),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::texture::solid::Solid;
+
+    fn white() -> Solid {
+        Solid::new(Color::from(1))
+    }
+
+    /// The average of f over scattered directions, weighted by what the
+    /// phase function returns, against integrating f times its value over
+    /// the sphere
+    fn check(
+        material: &dyn Material,
+        value: impl Fn(f32) -> f32,
+        f: impl Fn(f32) -> f32,
+        tolerance: f32,
+    ) {
+        let dir = Vec3::new(1.0, 2.0, -0.5);
+        let r = Ray::new(Vec3::zero(), dir);
+        let samples = 50_000;
+        let sampled = (0..samples)
+            .map(|_| {
+                let (weight, out) = material
+                    .scatter(r, Vec3::zero(), Vec3::zero(), 0.0, 0.0)
+                    .unwrap();
+                assert!((out.dir.len() - 1.0).abs() < 1e-4);
+                weight.x * f(out.dir.dot(&dir.normalize()))
+            })
+            .sum::<f32>()
+            / samples as f32;
+        let steps = 10_000;
+        let integrated = (0..steps)
+            .map(|i| {
+                let cos_theta = -1.0 + 2.0 * (i as f32 + 0.5) / steps as f32;
+                value(cos_theta) * f(cos_theta) * 2.0 * PI * 2.0 / steps as f32
+            })
+            .sum::<f32>();
+        assert!(
+            (sampled - integrated).abs() < tolerance,
+            "{} {}",
+            sampled,
+            integrated
+        );
+    }
+
+    #[test]
+    fn test_henyey_greenstein() {
+        for &g in &[-0.6, 0.0, 0.3, 0.85] {
+            let hg = HenyeyGreenstein::new(white(), g);
+            // it integrates to 1, and sampling matches its shape
+            check(&hg, |c| hg.value(c), |_| 1.0, 0.01);
+            check(&hg, |c| hg.value(c), |c| c, 0.01);
+        }
+        let hg = HenyeyGreenstein::new(white(), 0.5);
+        assert!(hg.value(1.0) > hg.value(0.0) && hg.value(0.0) > hg.value(-1.0));
+    }
+
+    #[test]
+    fn test_double() {
+        let double = DoubleHenyeyGreenstein::new(white(), 0.8, -0.4, 0.7);
+        check(&double, |c| double.value(c), |_| 1.0, 0.01);
+        check(&double, |c| double.value(c), |c| c, 0.01);
+        check(&double, |c| double.value(c), |c| c * c, 0.01);
+    }
+
+    #[test]
+    fn test_rayleigh() {
+        let rayleigh = Rayleigh::new(white());
+        check(&rayleigh, |c| rayleigh.value(c), |_| 1.0, 0.01);
+        check(&rayleigh, |c| rayleigh.value(c), |c| c, 0.01);
+        // cos^2 averages to 2/5 rather than 1/3 for uniform
+        check(&rayleigh, |c| rayleigh.value(c), |c| c * c, 0.01);
+    }
+
+    #[test]
+    fn test_mie() {
+        let mie = Mie::new(white(), 0.75);
+        check(&mie, |c| mie.value(c), |_| 1.0, 0.02);
+        check(&mie, |c| mie.value(c), |c| c, 0.02);
+        check(&mie, |c| mie.value(c), |c| c * c, 0.02);
+    }
+
+    #[test]
+    fn test_phase() {
+        let r = Ray::new(Vec3::zero(), Vec3::new(1.0, 2.0, -0.5));
+        let materials: Vec<Box<dyn Material>> = vec![
+            Box::new(HenyeyGreenstein::new(white(), 0.6)),
+            Box::new(DoubleHenyeyGreenstein::new(white(), 0.5, -0.4, 0.7)),
+            Box::new(Rayleigh::new(white())),
+            Box::new(Mie::new(white(), 0.5)),
+        ];
+        for material in &materials {
+            // both the value and the pdf add up to 1 over every direction,
+            // which with a white texture are the same thing
+            let samples = 100_000;
+            let (value, pdf) = (0..samples).fold((0.0, 0.0), |(value, pdf), _| {
+                let dir = Vec3::rand_in_unit_sphere();
+                let (v, p) = material.phase(r, dir, Vec3::zero(), 0.0, 0.0).unwrap();
+                (value + v.x, pdf + p)
+            });
+            let scale = 4.0 * PI / samples as f32;
+            assert!((value * scale - 1.0).abs() < 0.05, "{:?}", material);
+            assert!((pdf * scale - 1.0).abs() < 0.05, "{:?}", material);
+        }
+    }
+}
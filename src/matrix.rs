@@ -0,0 +1,241 @@
+use crate::quaternion::Quaternion;
+use crate::vec3::Vec3;
+use std::ops::Mul;
+
+/// A row major 4x4 matrix for affine transformations. Transforms compose
+/// right to left like their math notation, so `translate * rotate` rotates
+/// first and then translates.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Matrix4 {
+    pub m: [[f32; 4]; 4],
+}
+
+impl Matrix4 {
+    pub fn new(m: [[f32; 4]; 4]) -> Self {
+        Matrix4 { m }
+    }
+
+    pub fn identity() -> Self {
+        Matrix4::new([
+            [1.0, 0.0, 0.0, 0.0],
+            [0.0, 1.0, 0.0, 0.0],
+            [0.0, 0.0, 1.0, 0.0],
+            [0.0, 0.0, 0.0, 1.0],
+        ])
+    }
+
+    pub fn translate(offset: Vec3) -> Self {
+        let mut temp = Matrix4::identity();
+        temp.m[0][3] = offset.x;
+        temp.m[1][3] = offset.y;
+        temp.m[2][3] = offset.z;
+        temp
+    }
+
+    pub fn scale(factor: Vec3) -> Self {
+        let mut temp = Matrix4::identity();
+        temp.m[0][0] = factor.x;
+        temp.m[1][1] = factor.y;
+        temp.m[2][2] = factor.z;
+        temp
+    }
+
+    /// Rotation about an arbitrary axis through the origin, angle is in degrees
+    pub fn rotate(axis: Vec3, angle: f32) -> Self {
+        Quaternion::from_axis_angle(axis, angle).into()
+    }
+
+    /// Each factor is how much that axis gets pushed along per unit of
+    /// another, so `xy` slants x proportionally to y
+    pub fn shear(xy: f32, xz: f32, yx: f32, yz: f32, zx: f32, zy: f32) -> Self {
+        Matrix4::new([
+            [1.0, xy, xz, 0.0],
+            [yx, 1.0, yz, 0.0],
+            [zx, zy, 1.0, 0.0],
+            [0.0, 0.0, 0.0, 1.0],
+        ])
+    }
+
+    /// Places an object at `position` with its +z axis facing `target` and
+    /// its +y axis as close to `up_dir` as possible
+    pub fn look_at(position: Vec3, target: Vec3, up_dir: Vec3) -> Self {
+        let z = (target - position).normalize();
+        let x = up_dir.cross(&z).normalize();
+        let y = z.cross(&x);
+        Matrix4::new([
+            [x.x, y.x, z.x, position.x],
+            [x.y, y.y, z.y, position.y],
+            [x.z, y.z, z.z, position.z],
+            [0.0, 0.0, 0.0, 1.0],
+        ])
+    }
+
+    pub fn transpose(&self) -> Self {
+        let mut temp = *self;
+        for i in 0..4 {
+            for j in 0..4 {
+                temp.m[i][j] = self.m[j][i];
+            }
+        }
+        temp
+    }
+
+    /// Gauss-Jordan elimination with partial pivoting, None if the matrix is
+    /// singular (like a scale by 0)
+    pub fn inverse(&self) -> Option<Self> {
+        let mut a = self.m;
+        let mut inv = Matrix4::identity().m;
+        for col in 0..4 {
+            let pivot = (col..4)
+                .max_by(|&i, &j| a[i][col].abs().partial_cmp(&a[j][col].abs()).unwrap())
+                .unwrap();
+            if a[pivot][col].abs() < 1e-12 {
+                return None;
+            }
+            a.swap(col, pivot);
+            inv.swap(col, pivot);
+            let div = a[col][col];
+            for j in 0..4 {
+                a[col][j] /= div;
+                inv[col][j] /= div;
+            }
+            for row in 0..4 {
+                if row != col {
+                    let factor = a[row][col];
+                    for j in 0..4 {
+                        a[row][j] -= factor * a[col][j];
+                        inv[row][j] -= factor * inv[col][j];
+                    }
+                }
+            }
+        }
+        Some(Matrix4::new(inv))
+    }
+
+    pub fn transform_point(&self, p: Vec3) -> Vec3 {
+        let m = &self.m;
+        Vec3::new(
+            m[0][0] * p.x + m[0][1] * p.y + m[0][2] * p.z + m[0][3],
+            m[1][0] * p.x + m[1][1] * p.y + m[1][2] * p.z + m[1][3],
+            m[2][0] * p.x + m[2][1] * p.y + m[2][2] * p.z + m[2][3],
+        )
+    }
+
+    /// Like `transform_point` but ignores translation
+    pub fn transform_vector(&self, v: Vec3) -> Vec3 {
+        let m = &self.m;
+        Vec3::new(
+            m[0][0] * v.x + m[0][1] * v.y + m[0][2] * v.z,
+            m[1][0] * v.x + m[1][1] * v.y + m[1][2] * v.z,
+            m[2][0] * v.x + m[2][1] * v.y + m[2][2] * v.z,
+        )
+    }
+}
+
+impl Default for Matrix4 {
+    fn default() -> Self {
+        Matrix4::identity()
+    }
+}
+
+impl From<Quaternion> for Matrix4 {
+    fn from(q: Quaternion) -> Self {
+        let q = q.normalize();
+        let (w, x, y, z) = (q.w, q.v.x, q.v.y, q.v.z);
+        Matrix4::new([
+            [
+                1.0 - 2.0 * (y * y + z * z),
+                2.0 * (x * y - w * z),
+                2.0 * (x * z + w * y),
+                0.0,
+            ],
+            [
+                2.0 * (x * y + w * z),
+                1.0 - 2.0 * (x * x + z * z),
+                2.0 * (y * z - w * x),
+                0.0,
+            ],
+            [
+                2.0 * (x * z - w * y),
+                2.0 * (y * z + w * x),
+                1.0 - 2.0 * (x * x + y * y),
+                0.0,
+            ],
+            [0.0, 0.0, 0.0, 1.0],
+        ])
+    }
+}
+
+impl Mul for Matrix4 {
+    type Output = Self;
+    fn mul(self, other: Self) -> Self {
+        let mut temp = [[0.0; 4]; 4];
+        for i in 0..4 {
+            for j in 0..4 {
+                temp[i][j] = (0..4).map(|k| self.m[i][k] * other.m[k][j]).sum();
+            }
+        }
+        Matrix4::new(temp)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: Vec3, b: Vec3) -> bool {
+        (a - b).len() < 1e-5
+    }
+
+    #[test]
+    fn test_compose() {
+        let m = Matrix4::translate(Vec3::new(1, 2, 3)) * Matrix4::scale(Vec3::from(2));
+        assert_eq!(m.transform_point(Vec3::new(1, 1, 1)), Vec3::new(3, 4, 5));
+        assert_eq!(m.transform_vector(Vec3::new(1, 1, 1)), Vec3::new(2, 2, 2));
+    }
+
+    #[test]
+    fn test_rotate() {
+        let m = Matrix4::rotate(Vec3::new(0, 0, 1), 90.0);
+        assert!(close(
+            m.transform_point(Vec3::new(1, 0, 0)),
+            Vec3::new(0, 1, 0)
+        ));
+        let q = Quaternion::from_axis_angle(Vec3::new(1, 2, 3), 40.0);
+        let p = Vec3::new(-2, 0.5, 4);
+        assert!(close(Matrix4::from(q).transform_point(p), q.rotate(p)));
+    }
+
+    #[test]
+    fn test_inverse() {
+        let m = Matrix4::translate(Vec3::new(1, -2, 3))
+            * Matrix4::rotate(Vec3::new(1, 1, 0), 30.0)
+            * Matrix4::shear(0.5, 0.0, 0.0, 0.2, 0.0, 0.0)
+            * Matrix4::scale(Vec3::new(2, 3, 4));
+        let inv = m.inverse().unwrap();
+        let p = Vec3::new(5, 6, -7);
+        assert!(close(inv.transform_point(m.transform_point(p)), p));
+        let id = m * inv;
+        for i in 0..4 {
+            for j in 0..4 {
+                let expected = if i == j { 1.0 } else { 0.0 };
+                assert!((id.m[i][j] - expected).abs() < 1e-5);
+            }
+        }
+        assert!(Matrix4::scale(Vec3::new(1, 0, 1)).inverse().is_none());
+    }
+
+    #[test]
+    fn test_look_at() {
+        let m =
+            Matrix4::look_at(Vec3::new(1, 0, 0), Vec3::new(1, 0, 5), Vec3::new(0, 1, 0));
+        assert!(close(
+            m.transform_point(Vec3::new(0, 0, 1)),
+            Vec3::new(1, 0, 1)
+        ));
+        assert!(close(
+            m.transform_vector(Vec3::new(0, 1, 0)),
+            Vec3::new(0, 1, 0)
+        ));
+    }
+}
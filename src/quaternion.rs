@@ -0,0 +1,123 @@
+use crate::vec3::Vec3;
+use std::ops::*;
+
+/// A unit quaternion representing a rotation. Unlike chained euler angles
+/// these can be smoothly interpolated without gimbal lock.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Quaternion {
+    pub w: f32,
+    pub v: Vec3,
+}
+
+impl Quaternion {
+    pub fn new(w: f32, v: Vec3) -> Self {
+        Quaternion { w, v }
+    }
+
+    pub fn identity() -> Self {
+        Quaternion::new(1.0, Vec3::zero())
+    }
+
+    /// angle is in degrees
+    pub fn from_axis_angle(axis: Vec3, angle: f32) -> Self {
+        let half = angle.to_radians() / 2.0;
+        Quaternion::new(half.cos(), axis.normalize() * half.sin())
+    }
+
+    pub fn conjugate(&self) -> Self {
+        Quaternion::new(self.w, -self.v)
+    }
+
+    pub fn dot(&self, other: &Self) -> f32 {
+        self.w * other.w + self.v.dot(&other.v)
+    }
+
+    pub fn normalize(&self) -> Self {
+        let len = self.dot(self).sqrt();
+        Quaternion::new(self.w / len, self.v / len)
+    }
+
+    /// Rotates a vector using the expanded form of q * p * q^-1
+    pub fn rotate(&self, p: Vec3) -> Vec3 {
+        let t = self.v.cross(&p) * 2.0;
+        p + t * self.w + self.v.cross(&t)
+    }
+
+    /// Spherical linear interpolation, always takes the shortest path between
+    /// the two orientations
+    pub fn slerp(&self, other: &Self, t: f32) -> Self {
+        let mut other = *other;
+        let mut cos_theta = self.dot(&other);
+        if cos_theta < 0.0 {
+            other = Quaternion::new(-other.w, -other.v);
+            cos_theta = -cos_theta;
+        }
+        // nearly parallel quaternions would divide by ~0 so just lerp them
+        if cos_theta > 0.9995 {
+            return Quaternion::new(
+                self.w + (other.w - self.w) * t,
+                self.v + (other.v - self.v) * t,
+            )
+            .normalize();
+        }
+        let theta = cos_theta.acos();
+        let a = ((1.0 - t) * theta).sin() / theta.sin();
+        let b = (t * theta).sin() / theta.sin();
+        Quaternion::new(self.w * a + other.w * b, self.v * a + other.v * b)
+    }
+}
+
+impl Default for Quaternion {
+    fn default() -> Self {
+        Quaternion::identity()
+    }
+}
+
+impl Mul for Quaternion {
+    type Output = Self;
+    fn mul(self, other: Self) -> Self {
+        Quaternion::new(
+            self.w * other.w - self.v.dot(&other.v),
+            other.v * self.w + self.v * other.w + self.v.cross(&other.v),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: Vec3, b: Vec3) -> bool {
+        (a - b).len() < 1e-5
+    }
+
+    #[test]
+    fn test_rotate() {
+        let q = Quaternion::from_axis_angle(Vec3::new(0, 1, 0), 90.0);
+        assert!(close(q.rotate(Vec3::new(1, 0, 0)), Vec3::new(0, 0, -1)));
+        let q = Quaternion::from_axis_angle(Vec3::new(0, 0, 1), 90.0);
+        assert!(close(q.rotate(Vec3::new(1, 0, 0)), Vec3::new(0, 1, 0)));
+    }
+
+    #[test]
+    fn test_compose() {
+        let a = Quaternion::from_axis_angle(Vec3::new(0, 1, 0), 30.0);
+        let b = Quaternion::from_axis_angle(Vec3::new(0, 1, 0), 60.0);
+        let p = Vec3::new(1, 2, 3);
+        assert!(close(
+            (a * b).rotate(p),
+            Quaternion::from_axis_angle(Vec3::new(0, 1, 0), 90.0).rotate(p)
+        ));
+        assert!(close((a * a.conjugate()).rotate(p), p));
+    }
+
+    #[test]
+    fn test_slerp() {
+        let a = Quaternion::identity();
+        let b = Quaternion::from_axis_angle(Vec3::new(0, 1, 0), 90.0);
+        let half = a.slerp(&b, 0.5);
+        let expected = Quaternion::from_axis_angle(Vec3::new(0, 1, 0), 45.0);
+        assert!((half.dot(&expected) - 1.0).abs() < 1e-5);
+        assert!((a.slerp(&b, 1.0).dot(&b) - 1.0).abs() < 1e-5);
+    }
+}
@@ -1,7 +1,10 @@
+use crate::atmosphere::Atmosphere;
 use crate::background::Background;
 use crate::bvh::BVHNode;
-use crate::camera::Camera;
+use crate::camera::{Camera, Lens};
+use crate::geometry::volume::{track, Event, Segment};
 use crate::geometry::Hitable;
+use crate::material::Material;
 use crate::ray::Ray;
 use crate::vec3::{ToF32, Vec3};
 use image::{ImageBuffer, Pixel, Rgb, RgbImage};
@@ -11,7 +14,8 @@ use rayon::prelude::*;
 use std::f32;
 use std::fs::File;
 use std::path::PathBuf;
-use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
 use std::thread;
 use std::time::{Duration, Instant};
 use termion::cursor;
@@ -38,45 +42,182 @@ impl Color {
     }
 }
 
-fn color(
-    r: Ray,
-    world: &impl Hitable,
-    bg: &dyn Background,
+/// The power heuristic weight for a sample picked with pdf `a`, when another
+/// way of sampling with pdf `b` could have picked it too
+fn mis(a: f32, b: f32) -> f32 {
+    a * a / (a * a + b * b)
+}
+
+/// Everything about the scene a path needs as it bounces around
+struct Tracer<'a, W: Hitable> {
+    world: &'a W,
+    bg: &'a dyn Background,
+    atmosphere: Option<&'a Atmosphere>,
+    lights: &'a [Arc<dyn Hitable>],
     show_bg: bool,
-    depth: u16,
     max_bounces: u16,
-) -> Color {
-    if let Some(hit) = world.hit(r, 0.001, std::f32::MAX) {
-        let emited = hit.material.emit(hit.u, hit.v, hit.point);
-        if let Some((attenuation, scattered)) =
-            hit.material.scatter(r, hit.normal, hit.point, hit.u, hit.v)
-        {
-            if depth < max_bounces {
-                return emited
-                    + attenuation
-                        * color(scattered, world, bg, show_bg, depth + 1, max_bounces);
+}
+
+impl<'a, W: Hitable> Tracer<'a, W> {
+    /// The media like smoke and the atmosphere along a ray up to `t_max`
+    fn media(&self, r: Ray, t_max: f32) -> Vec<Segment<'a>> {
+        let mut media = Vec::new();
+        self.world.media(r, 0.001, t_max, &mut media);
+        media.extend(self.atmosphere.and_then(|a| a.segment(r, t_max)));
+        media
+    }
+
+    /// Whether what the ray hits at t is one of the lights
+    fn is_light(&self, r: Ray, t: f32) -> bool {
+        self.lights
+            .iter()
+            .any(|light| light.hit(r, 0.001, t * 1.0001 + 1e-4).is_some())
+    }
+
+    /// How likely `direct` is to pick `dir` from `origin`, per steradian. The
+    /// background is sampled evenly in every direction.
+    fn light_pdf(&self, origin: Vec3, dir: Vec3) -> f32 {
+        let lights: f32 = self
+            .lights
+            .iter()
+            .map(|light| light.pdf_towards(origin, dir))
+            .sum();
+        (lights + 1.0 / (4.0 * f32::consts::PI)) / (self.lights.len() + 1) as f32
+    }
+
+    /// Light reaching a point in a medium straight from one of the lights or
+    /// the background, through whatever media are in the way. Small lights
+    /// hardly ever get found by the phase function, so this is what makes
+    /// light shafts through fog show up.
+    fn direct(&self, r: Ray, point: Vec3, phase_function: &dyn Material) -> Color {
+        let choices = self.lights.len() + 1;
+        let pick = ((random::<f32>() * choices as f32) as usize).min(choices - 1);
+        let dir = match self.lights.get(pick) {
+            Some(light) => match light.sample_towards(point) {
+                Some(dir) => dir,
+                None => return Color::zero(),
+            },
+            None => Vec3::rand_in_unit_sphere().normalize(),
+        };
+        let (value, phase_pdf) = match phase_function.phase(r, dir, point, 0.0, 0.0) {
+            Some(phase) => phase,
+            None => return Color::zero(),
+        };
+        // lights blocked by anything else don't count, the path carrying on
+        // from here picks up whatever it is
+        let shadow = Ray::new(point, dir);
+        let (t_max, emitted) = match self.world.hit(shadow, 0.001, std::f32::MAX) {
+            Some(hit) if self.is_light(shadow, hit.t) => {
+                (hit.t, hit.material.emit(hit.u, hit.v, hit.point))
             }
-        }
-        emited
-    } else if show_bg || depth > 0 {
-        bg.get_color(r)
-    } else {
-        Color::zero()
+            Some(_) => return Color::zero(),
+            None => (std::f32::MAX, self.bg.get_color(shadow)),
+        };
+        let transmittance = match track(&self.media(shadow, t_max)) {
+            Event::Through(weight) => weight,
+            _ => return Color::zero(),
+        };
+        let light_pdf = self.light_pdf(point, dir);
+        value * emitted * transmittance * mis(light_pdf, phase_pdf) / light_pdf
+    }
+
+    /// `phase_pdf` is how likely the medium the ray scattered off was to pick
+    /// its direction, so that lights `direct` could have found as well only
+    /// count for their share
+    fn color(&self, r: Ray, depth: u16, phase_pdf: Option<f32>) -> Color {
+        let hit = self.world.hit(r, 0.001, std::f32::MAX);
+        // media like smoke and the atmosphere can stop the ray before it gets
+        // to what it hits, or tint it on the way
+        let t_max = hit.as_ref().map_or(std::f32::MAX, |h| h.t);
+        let through = match track(&self.media(r, t_max)) {
+            Event::Absorbed => return Color::zero(),
+            Event::Scattered(_, _, _) if depth >= self.max_bounces => {
+                return Color::zero()
+            }
+            Event::Scattered(t, weight, phase_function) => {
+                let point = r.point_at_param(t);
+                let direct = self.direct(r, point, phase_function);
+                return match phase_function.scatter(r, Vec3::zero(), point, 0.0, 0.0) {
+                    Some((attenuation, scattered)) => {
+                        let pdf = phase_function
+                            .phase(r, scattered.dir, point, 0.0, 0.0)
+                            .map(|(_, pdf)| pdf);
+                        weight
+                            * (direct
+                                + attenuation * self.color(scattered, depth + 1, pdf))
+                    }
+                    None => weight * direct,
+                };
+            }
+            Event::Through(weight) => weight,
+        };
+        let surface = if let Some(hit) = hit {
+            let emited = hit.material.emit(hit.u, hit.v, hit.point);
+            let emited = match phase_pdf {
+                Some(pdf) if emited != Color::zero() && self.is_light(r, hit.t) => {
+                    emited * mis(pdf, self.light_pdf(r.origin, r.dir))
+                }
+                _ => emited,
+            };
+            // interpolated normals can lean past the ray near silhouettes,
+            // which would make materials think they were hit from the other
+            // side
+            let normal = if hit.front_face == (r.dir.dot(&hit.normal) < 0.0) {
+                hit.normal
+            } else {
+                hit.geometric_normal
+            };
+            // an open surface seen from behind should act the same as from the
+            // front, rather than scattering through itself
+            let normal = if hit.front_face || !hit.material.two_sided() {
+                normal
+            } else {
+                -normal
+            };
+            match hit.material.scatter_along(
+                r,
+                normal,
+                hit.tangent,
+                hit.point,
+                hit.u,
+                hit.v,
+            ) {
+                Some((attenuation, scattered)) if depth < self.max_bounces => {
+                    emited
+                        + attenuation * hit.color * self.color(scattered, depth + 1, None)
+                }
+                _ => emited,
+            }
+        } else if self.show_bg || depth > 0 {
+            let bg = self.bg.get_color(r);
+            match phase_pdf {
+                Some(pdf) => bg * mis(pdf, self.light_pdf(r.origin, r.dir)),
+                None => bg,
+            }
+        } else {
+            Color::zero()
+        };
+        through * surface
     }
 }
 
-pub struct Scene<T: Background> {
+pub struct Scene<T: Background, C: Lens = Camera> {
     pub width: usize,
     pub height: usize,
     pub objects: BVHNode,
-    pub camera: Camera,
+    pub camera: C,
     pub samples: u16,
     pub bounces: u16,
     pub show_bg: bool,
     pub background: T,
+    /// Fog or haze filling the whole scene
+    pub atmosphere: Option<Atmosphere>,
+    /// Emissive objects that fog and smoke look for directly, as well as
+    /// being in `objects`. Only spheres, quads and rects can be sampled.
+    pub lights: Vec<Arc<dyn Hitable>>,
 }
 
-impl<T: Background> Scene<T> {
+impl<T: Background, C: Lens> Scene<T, C> {
     pub fn render(&self) -> Vec<Rgb<f32>> {
         if cfg!(feature = "single_thread") {
             iproduct!((0..self.height).rev(), 0..self.width)
@@ -93,19 +234,24 @@ impl<T: Background> Scene<T> {
 
     fn render_pixel(&self, x: usize, y: usize) -> Rgb<f32> {
         PROGRESS_COUNTER.fetch_add(1, Ordering::Relaxed);
+        let tracer = Tracer {
+            world: &self.objects,
+            bg: &self.background,
+            atmosphere: self.atmosphere.as_ref(),
+            lights: &self.lights,
+            show_bg: self.show_bg,
+            max_bounces: self.bounces,
+        };
         let col = (0..self.samples)
             .map(|_| {
-                color(
+                tracer.color(
                     self.camera.get_ray(
                         // TODO: add jitter sampling
                         (x as f32 + random::<f32>()) / self.width as f32,
                         (y as f32 + random::<f32>()) / self.height as f32,
                     ),
-                    &self.objects,
-                    &self.background,
-                    self.show_bg,
                     0,
-                    self.bounces,
+                    None,
                 )
             })
             .fold(Color::zero(), |a, b| a + b)
@@ -116,19 +262,34 @@ impl<T: Background> Scene<T> {
     }
 
     pub fn render_to_file(&self, filename: &str) -> std::io::Result<()> {
+        self.render_and_save(filename)?;
+        Ok(())
+    }
+
+    /// Renders with a progress display, writes the image (and hdr output if
+    /// enabled) to disk, and returns the tonemapped image
+    pub(crate) fn render_and_save(&self, filename: &str) -> std::io::Result<RgbImage> {
         let samples = self.samples as usize;
         let goal = self.width * self.height * samples;
         let start_time = Instant::now();
+        PROGRESS_COUNTER.store(0, Ordering::Relaxed);
+        let done = Arc::new(AtomicBool::new(false));
         println!("\n\n\n");
-        thread::spawn(move || {
-            let mut prog = 0;
-            while prog < goal {
-                thread::sleep(Duration::from_millis(100));
-                show_progress(start_time, goal, prog);
-                prog = PROGRESS_COUNTER.load(Ordering::Relaxed) * samples;
-            }
-        });
+        let progress = {
+            let done = done.clone();
+            thread::spawn(move || {
+                while !done.load(Ordering::Relaxed) {
+                    thread::sleep(Duration::from_millis(100));
+                    let prog = PROGRESS_COUNTER.load(Ordering::Relaxed) * samples;
+                    if prog < goal {
+                        show_progress(start_time, goal, prog);
+                    }
+                }
+            })
+        };
         let data = self.render();
+        done.store(true, Ordering::Relaxed);
+        progress.join().unwrap();
         show_progress(start_time, goal, goal);
         #[cfg(feature = "hdr_output")]
         {
@@ -150,7 +311,7 @@ impl<T: Background> Scene<T> {
         let output: RgbImage =
             ImageBuffer::from_vec(self.width as u32, self.height as u32, data).unwrap();
         output.save(filename)?;
-        Ok(())
+        Ok(output)
     }
 }
 
@@ -165,3 +326,141 @@ fn show_progress(start: Instant, goal: usize, current: usize) {
     println!("remaining: {:.1}s    ", elapsed / percent - elapsed);
     println!("speed: {} rays/s    ", (current as f32 / elapsed) as usize);
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometry::{prism::Prism, quad::Quad, sphere::Sphere, volume::Volume};
+    use crate::material::{
+        diffuse::Diffuse, isotropic::Isotropic, light::Light, specular::Specular,
+    };
+    use crate::texture::solid::Solid;
+
+    fn trace(
+        r: Ray,
+        world: &BVHNode,
+        bg: &dyn Background,
+        atmosphere: Option<&Atmosphere>,
+        lights: &[Arc<dyn Hitable>],
+        bounces: u16,
+    ) -> Color {
+        let tracer = Tracer {
+            world,
+            bg,
+            atmosphere,
+            lights,
+            show_bg: true,
+            max_bounces: bounces,
+        };
+        tracer.color(r, 0, None)
+    }
+
+    #[test]
+    fn test_back_face() {
+        // a sheet facing +z hit from behind
+        let sheet = |material| {
+            Box::new(Quad::new(
+                Vec3::new(-1, -1, 0),
+                Vec3::new(2, 0, 0),
+                Vec3::new(0, 2, 0),
+                material,
+            )) as Box<dyn Hitable>
+        };
+        let r = Ray::new(Vec3::new(0.2, 0.3, -0.25), Vec3::new(0.1, 0.0, 1.0));
+
+        // a mirror reflects rather than going black
+        let mirror = BVHNode::from(&mut vec![sheet(Arc::new(Specular::new(
+            Color::from(0.5),
+            0.0,
+        )))]);
+        let white = Solid::new(Color::from(1));
+        assert_eq!(trace(r, &mirror, &white, None, &[], 1), Color::from(0.5));
+
+        // a diffuse surface scatters back towards a big light behind the
+        // camera, instead of out the front where there's nothing
+        let lit = BVHNode::from(&mut vec![
+            sheet(Arc::new(Diffuse::new(Solid::new(Color::from(0.5))))),
+            Box::new(Quad::new(
+                Vec3::new(-1000, -1000, -0.5),
+                Vec3::new(2000, 0, 0),
+                Vec3::new(0, 2000, 0),
+                Arc::new(Light::new(Solid::new(Color::from(1)))),
+            )),
+        ]);
+        let black = Solid::new(Color::zero());
+        let samples = 100;
+        let average = (0..samples)
+            .map(|_| trace(r, &lit, &black, None, &[], 1).x)
+            .sum::<f32>()
+            / samples as f32;
+        assert!(average > 0.49, "{}", average);
+    }
+
+    #[test]
+    fn test_inside_medium() {
+        // a light half way into smoke that absorbs red the most, which should
+        // get tinted by the half unit of smoke in front of it
+        let white: Arc<dyn Material> =
+            Arc::new(Isotropic::new(Solid::new(Color::from(1))));
+        let smoke = Volume::new(
+            1.0,
+            Box::new(Prism::new(Vec3::zero(), Vec3::from(1), white.clone())),
+            white,
+        )
+        .with_coefficients(Color::new(2.0, 0.5, 0.1), Color::zero());
+        let light = Quad::new(
+            Vec3::new(0.0, 0.0, 0.5),
+            Vec3::new(0, 1, 0),
+            Vec3::new(1, 0, 0),
+            Arc::new(Light::new(Solid::new(Color::from(1)))),
+        );
+        let world = BVHNode::from(&mut vec![
+            Box::new(smoke) as Box<dyn Hitable>,
+            Box::new(light),
+        ]);
+        let r = Ray::new(Vec3::new(0.5, 0.51, -1.0), Vec3::new(0.01, 0.0, 1.0));
+        let black = Solid::new(Color::zero());
+        let samples = 20_000;
+        let average = (0..samples).fold(Color::zero(), |sum, _| {
+            sum + trace(r, &world, &black, None, &[], 4)
+        }) / samples as f32;
+        let expected = Color::new((-1.0f32).exp(), (-0.25f32).exp(), (-0.05f32).exp());
+        assert!((average - expected).len() < 0.02, "{:?}", average);
+    }
+
+    #[test]
+    fn test_light_shafts() {
+        // a light just off a ray through fog, seen only by light scattering
+        // off the fog once
+        let lamp: Arc<dyn Hitable> = Arc::new(Sphere::new(
+            Vec3::new(0, 1, 0),
+            0.8,
+            Arc::new(Light::new(Solid::new(Color::from(4)))),
+        ));
+        let world = BVHNode::from(&mut vec![Box::new(lamp.clone()) as Box<dyn Hitable>]);
+        let fog =
+            Atmosphere::new(0.2, Arc::new(Isotropic::new(Solid::new(Color::from(1)))));
+        let r = Ray::new(Vec3::new(-3, 0, 0), Vec3::new(1, 0, 0));
+        let black = Solid::new(Color::zero());
+        let stats = |lights: &[Arc<dyn Hitable>], samples: usize| {
+            let values: Vec<f32> = (0..samples)
+                .map(|_| trace(r, &world, &black, Some(&fog), lights, 1).x)
+                .collect();
+            let mean = values.iter().sum::<f32>() / samples as f32;
+            let variance =
+                values.iter().map(|v| (v - mean).powi(2)).sum::<f32>() / samples as f32;
+            (mean, variance)
+        };
+        // sampling the light gets the same answer as waiting for the fog to
+        // scatter into it, with a lot less noise
+        let (sampled, sampled_variance) = stats(&[lamp.clone()], 50_000);
+        let (found, found_variance) = stats(&[], 200_000);
+        assert!(
+            (sampled - found).abs() < 0.05 * found,
+            "{} {}",
+            sampled,
+            found
+        );
+        assert!(sampled_variance * 5.0 < found_variance);
+    }
+}
@@ -0,0 +1,164 @@
+use crate::bvh::BVHNode;
+use crate::geometry::{instance::Instance, Hitable};
+use crate::matrix::Matrix4;
+use std::sync::Arc;
+
+/// A named node in a transform hierarchy. Each node's transform is relative to
+/// its parent, so moving a node moves everything under it. The graph only
+/// holds shared references to objects so it can be edited and flattened
+/// again, for example once per animation frame.
+#[derive(Debug, Clone)]
+pub struct SceneNode {
+    pub name: String,
+    pub transform: Matrix4,
+    pub object: Option<Arc<dyn Hitable>>,
+    pub children: Vec<SceneNode>,
+}
+
+impl SceneNode {
+    /// An empty group node
+    pub fn new(name: &str) -> Self {
+        SceneNode {
+            name: name.to_string(),
+            transform: Matrix4::identity(),
+            object: None,
+            children: Vec::new(),
+        }
+    }
+
+    pub fn with_object(name: &str, object: Arc<dyn Hitable>) -> Self {
+        SceneNode {
+            object: Some(object),
+            ..SceneNode::new(name)
+        }
+    }
+
+    pub fn transform(mut self, transform: Matrix4) -> Self {
+        self.transform = transform;
+        self
+    }
+
+    pub fn child(mut self, child: SceneNode) -> Self {
+        self.children.push(child);
+        self
+    }
+
+    /// Depth first search for the first node with a matching name
+    pub fn find(&self, name: &str) -> Option<&SceneNode> {
+        if self.name == name {
+            return Some(self);
+        }
+        self.children.iter().find_map(|c| c.find(name))
+    }
+
+    pub fn find_mut(&mut self, name: &str) -> Option<&mut SceneNode> {
+        if self.name == name {
+            return Some(self);
+        }
+        self.children.iter_mut().find_map(|c| c.find_mut(name))
+    }
+
+    /// The transform from a named node's local space to the space of this
+    /// node, including every parent in between
+    pub fn world_transform(&self, name: &str) -> Option<Matrix4> {
+        if self.name == name {
+            return Some(self.transform);
+        }
+        self.children
+            .iter()
+            .find_map(|c| c.world_transform(name))
+            .map(|m| self.transform * m)
+    }
+
+    fn collect(&self, parent: Matrix4, objects: &mut Vec<Box<dyn Hitable>>) {
+        let world = parent * self.transform;
+        if let Some(object) = &self.object {
+            if world == Matrix4::identity() {
+                objects.push(Box::new(object.clone()));
+            } else if let Some(instance) = Instance::new(object.clone(), world) {
+                objects.push(Box::new(instance));
+            }
+            // otherwise it's been scaled down to nothing, which is a common
+            // way to hide things, so there's nothing to see
+        }
+        for child in &self.children {
+            child.collect(world, objects);
+        }
+    }
+
+    /// Bakes the hierarchy into a BVH of instances ready to render, or
+    /// `None` if there's nothing in it to see
+    pub fn flatten(&self) -> Option<BVHNode> {
+        let mut objects = Vec::new();
+        self.collect(Matrix4::identity(), &mut objects);
+        if objects.is_empty() {
+            None
+        } else {
+            Some(BVHNode::from(&mut objects))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometry::sphere::Sphere;
+    use crate::material::specular::Specular;
+    use crate::ray::Ray;
+    use crate::vec3::Vec3;
+
+    fn graph() -> SceneNode {
+        let ball: Arc<dyn Hitable> = Arc::new(Sphere::new(
+            Vec3::zero(),
+            1.0,
+            Arc::new(Specular::new(Vec3::from(1), 0.0)),
+        ));
+        SceneNode::new("root").child(
+            SceneNode::new("arm")
+                .transform(Matrix4::translate(Vec3::new(10, 0, 0)))
+                .child(
+                    SceneNode::with_object("hand", ball)
+                        .transform(Matrix4::translate(Vec3::new(0, 5, 0))),
+                ),
+        )
+    }
+
+    #[test]
+    fn test_find() {
+        let mut root = graph();
+        assert!(root.find("hand").unwrap().object.is_some());
+        assert!(root.find("foot").is_none());
+        root.find_mut("arm").unwrap().transform = Matrix4::identity();
+        assert_eq!(root.find("arm").unwrap().transform, Matrix4::identity());
+    }
+
+    #[test]
+    fn test_inherited_transform() {
+        let mut root = graph();
+        let world = root.world_transform("hand").unwrap();
+        assert_eq!(world.transform_point(Vec3::zero()), Vec3::new(10, 5, 0));
+        let r = Ray::new(Vec3::new(10, 5, -10), Vec3::new(0, 0, 1));
+        assert!(root.flatten().unwrap().hit(r, 0.0, std::f32::MAX).is_some());
+        // moving the parent moves the child along with it
+        root.find_mut("arm").unwrap().transform =
+            Matrix4::translate(Vec3::new(-10, 0, 0));
+        assert!(root.flatten().unwrap().hit(r, 0.0, std::f32::MAX).is_none());
+    }
+
+    #[test]
+    fn test_hidden() {
+        let ball = graph().find("hand").unwrap().object.clone().unwrap();
+        let mut root = graph().child(SceneNode::with_object("other", ball));
+        root.find_mut("arm").unwrap().transform = Matrix4::scale(Vec3::zero());
+        let r = Ray::new(Vec3::new(10, 5, -10), Vec3::new(0, 0, 1));
+        assert!(root.flatten().unwrap().hit(r, 0.0, std::f32::MAX).is_none());
+        let r = Ray::new(Vec3::new(0, 0, -10), Vec3::new(0, 0, 1));
+        assert!(root.flatten().unwrap().hit(r, 0.0, std::f32::MAX).is_some());
+    }
+
+    #[test]
+    fn test_empty() {
+        let root = SceneNode::new("root").child(SceneNode::new("camera"));
+        assert!(root.flatten().is_none());
+    }
+}
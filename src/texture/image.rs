@@ -4,38 +4,96 @@ use crate::vec3::{ToF32, Vec3};
 use image::hdr::HDRDecoder;
 use image::{ImageBuffer, Pixel, Primitive, Rgb, RgbImage};
 use std::fs::File;
-use std::io::BufReader;
+use std::io::{self, BufReader, Error, ErrorKind};
 
 impl<T> Texture for ImageBuffer<Rgb<T>, Vec<T>>
 where
     T: 'static + Send + Sync + Primitive + ToF32 + std::fmt::Debug,
 {
+    /// Repeats outside of 0 to 1 like most model formats expect, with 1
+    /// itself still the far edge so spheres don't wrap at the poles
     fn value(&self, u: f32, v: f32, _p: Vec3) -> Color {
-        let x = (u * self.width() as f32) as u32;
-        let y = ((1.0 - v) * self.height() as f32 - 0.001) as u32;
+        let (u, v) = (wrap(u), wrap(v));
+        let x = ((u * self.width() as f32) as u32).min(self.width() - 1);
+        let y = (((1.0 - v) * self.height() as f32) as u32).min(self.height() - 1);
         let channels = self.get_pixel(x, y).channels();
         Color::new(channels[0].to(), channels[1].to(), channels[2].to())
     }
 }
 
+fn wrap(x: f32) -> f32 {
+    if (0.0..=1.0).contains(&x) {
+        x
+    } else {
+        x - x.floor()
+    }
+}
+
 pub fn ldr_image(filename: &str) -> RgbImage {
     image::open(filename).unwrap().to_rgb()
 }
 
 pub fn hdr_image(filename: &str) -> ImageBuffer<Rgb<f32>, Vec<f32>> {
-    let hdr_decoder =
-        HDRDecoder::new(BufReader::new(File::open(filename).unwrap())).unwrap();
+    load_hdr(filename).unwrap()
+}
+
+/// Like `hdr_image`, but with an error instead of a panic for files that
+/// are missing or broken
+pub fn load_hdr(filename: &str) -> io::Result<ImageBuffer<Rgb<f32>, Vec<f32>>> {
+    let invalid = |e: &dyn std::fmt::Display| {
+        Error::new(ErrorKind::InvalidData, format!("{}: {}", filename, e))
+    };
+    let hdr_decoder = HDRDecoder::new(BufReader::new(File::open(filename)?))
+        .map_err(|e| invalid(&e))?;
     let metadata = hdr_decoder.metadata();
-    ImageBuffer::from_vec(
-        metadata.width,
-        metadata.height,
-        hdr_decoder
-            .read_image_hdr()
-            .unwrap()
-            .into_iter()
-            .map(|p| p.channels().to_vec())
-            .flatten()
-            .collect::<Vec<f32>>(),
-    )
-    .unwrap()
+    let pixels = hdr_decoder
+        .read_image_hdr()
+        .map_err(|e| invalid(&e))?
+        .into_iter()
+        .map(|p| p.channels().to_vec())
+        .flatten()
+        .collect::<Vec<f32>>();
+    ImageBuffer::from_vec(metadata.width, metadata.height, pixels)
+        .ok_or_else(|| invalid(&"fewer pixels than the size says"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wrap() {
+        // red on the left, green on the right, and blue along the top
+        let img = RgbImage::from_fn(2, 2, |x, y| match (x, y) {
+            (_, 0) => Rgb([0, 0, 255]),
+            (0, _) => Rgb([255, 0, 0]),
+            _ => Rgb([0, 255, 0]),
+        });
+        let (red, green, blue) = (
+            Color::new(1, 0, 0),
+            Color::new(0, 1, 0),
+            Color::new(0, 0, 1),
+        );
+        assert_eq!(img.value(0.25, 0.25, Vec3::zero()), red);
+        assert_eq!(img.value(0.75, 0.25, Vec3::zero()), green);
+        // the edges stay where they are
+        assert_eq!(img.value(1.0, 0.0, Vec3::zero()), green);
+        assert_eq!(img.value(0.0, 1.0, Vec3::zero()), blue);
+        assert_eq!(img.value(1.0, 1.0, Vec3::zero()), blue);
+        // and everything past them repeats
+        assert_eq!(img.value(2.3, 0.25, Vec3::zero()), red);
+        assert_eq!(img.value(2.8, -3.75, Vec3::zero()), green);
+        assert_eq!(img.value(-0.1, 1.25, Vec3::zero()), green);
+        assert_eq!(img.value(0.25, 1.75, Vec3::zero()), blue);
+    }
+
+    #[test]
+    fn test_load_hdr() {
+        let path = std::env::temp_dir().join("ray_broken.hdr");
+        std::fs::write(&path, "#?RADIANCE\nnot really\n").unwrap();
+        let broken = load_hdr(path.to_str().unwrap()).unwrap_err();
+        assert_eq!(broken.kind(), ErrorKind::InvalidData);
+        let missing = load_hdr("no such image.hdr").unwrap_err();
+        assert_eq!(missing.kind(), ErrorKind::NotFound);
+    }
 }
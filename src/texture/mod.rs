@@ -3,6 +3,7 @@ pub mod gradient;
 pub mod image;
 pub mod perlin;
 pub mod solid;
+pub mod tinted;
 
 use crate::scene::Color;
 use crate::vec3::Vec3;
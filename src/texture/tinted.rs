@@ -0,0 +1,23 @@
+use super::Texture;
+use crate::scene::Color;
+use crate::vec3::Vec3;
+
+/// Another texture multiplied by a color, like an image map with the base
+/// color a model format puts on top of it
+#[derive(Debug)]
+pub struct Tinted<T: Texture> {
+    texture: T,
+    tint: Color,
+}
+
+impl<T: Texture> Tinted<T> {
+    pub fn new(texture: T, tint: Color) -> Self {
+        Tinted { texture, tint }
+    }
+}
+
+impl<T: Texture> Texture for Tinted<T> {
+    fn value(&self, u: f32, v: f32, p: Vec3) -> Color {
+        self.texture.value(u, v, p) * self.tint
+    }
+}